@@ -1,6 +1,16 @@
 mod app_data;
+mod auth;
 mod core;
+mod csrf;
+mod graphql;
 pub mod handlers;
+mod metrics;
+mod openapi;
+mod rate_limit;
+mod request_id;
+mod tls;
+mod versioning;
 
 use app_data::AppData;
 pub use core::App;
+pub use tls::Tls;
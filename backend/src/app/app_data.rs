@@ -1,13 +1,53 @@
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 
+use actix_web::HttpRequest;
+
 use crate::Logic;
+use crate::config::Secret;
+use crate::shutdown::Shutdown;
 
 pub struct AppData {
     pub logic: Arc<Logic>,
+    pub shutdown: Shutdown,
+    /// Required as a bearer token by `/metrics`, when set.
+    pub metrics_token: Option<Secret>,
+    /// Mirrors `rate_limit.trust_proxy`.
+    pub trust_proxy: bool,
 }
 
 impl AppData {
-    pub fn new(logic: Arc<Logic>) -> Self {
-        AppData { logic }
+    pub fn new(
+        logic: Arc<Logic>,
+        shutdown: Shutdown,
+        metrics_token: Option<Secret>,
+        trust_proxy: bool,
+    ) -> Self {
+        AppData {
+            logic,
+            shutdown,
+            metrics_token,
+            trust_proxy,
+        }
+    }
+
+    pub fn client_ip(&self, req: &HttpRequest) -> Option<String> {
+        client_ip(req, self.trust_proxy).map(|ip| ip.to_string())
     }
 }
+
+/// The forwarding headers are only believed behind a trusted proxy. IPv4
+/// clients reaching an IPv6 socket are reported by their IPv4 address.
+pub fn client_ip(req: &HttpRequest, trust_proxy: bool) -> Option<IpAddr> {
+    let ip = if trust_proxy {
+        req.connection_info().realip_remote_addr().and_then(|addr| {
+            addr.parse::<IpAddr>()
+                .ok()
+                .or_else(|| addr.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+        })
+    } else {
+        req.peer_addr().map(|addr| addr.ip())
+    };
+
+    ip.map(|ip| ip.to_canonical())
+}
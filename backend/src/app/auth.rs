@@ -0,0 +1,306 @@
+//! Authentication of requests.
+//!
+//! Routes declare what they need with [`Require`], which resolves the caller
+//! once and rejects the request before it reaches the handler. Handlers take
+//! the resolved [`Principal`] as an argument. A handler which takes a
+//! `Principal` on a route without a requirement still can't be reached
+//! unauthenticated, the extractor then resolves the caller itself.
+
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{Payload, Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::error::InternalError;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, web};
+use tracing::Span;
+
+use crate::app::AppData;
+use crate::{Credential, Principal, Role, Scope};
+
+#[derive(Clone, Copy)]
+enum Requirement {
+    Role(Role),
+    Session,
+    Scope(Scope),
+}
+
+/// Middleware rejecting requests whose caller doesn't meet a requirement,
+/// with 401 when the caller is unknown and 403 when it isn't allowed.
+pub struct Require(Requirement);
+
+impl Require {
+    /// Any valid session or personal access token.
+    pub fn authenticated() -> Self {
+        Require::role(Role::User)
+    }
+
+    pub fn role(role: Role) -> Self {
+        Require(Requirement::Role(role))
+    }
+
+    /// A session, for managing the account itself, which personal access
+    /// tokens aren't allowed to do.
+    pub fn session() -> Self {
+        Require(Requirement::Session)
+    }
+
+    /// A session or a personal access token with the scope.
+    pub fn scope(scope: Scope) -> Self {
+        Require(Requirement::Scope(scope))
+    }
+}
+
+impl Requirement {
+    fn is_met_by(&self, principal: &Principal) -> bool {
+        match self {
+            Requirement::Role(role) => principal.has_role(*role),
+            Requirement::Session => principal.session.is_some(),
+            Requirement::Scope(scope) => principal.has_scope(*scope),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Require
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RequireMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequireMiddleware {
+            service: Rc::new(service),
+            requirement: self.0,
+        }))
+    }
+}
+
+pub struct RequireMiddleware<S> {
+    service: Rc<S>,
+    requirement: Requirement,
+}
+
+impl<S, B> Service<ServiceRequest> for RequireMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let requirement = self.requirement;
+
+        Box::pin(async move {
+            let Some(principal) = resolve(req.request()).await else {
+                return Ok(req
+                    .into_response(HttpResponse::Unauthorized().finish())
+                    .map_into_right_body());
+            };
+
+            if !requirement.is_met_by(&principal) {
+                return Ok(req
+                    .into_response(HttpResponse::Forbidden().finish())
+                    .map_into_right_body());
+            }
+
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+impl FromRequest for Principal {
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self, Self::Error>>>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let req = req.clone();
+
+        Box::pin(async move {
+            resolve(&req).await.ok_or_else(|| {
+                InternalError::from_response("", HttpResponse::Unauthorized().finish()).into()
+            })
+        })
+    }
+}
+
+/// A bearer token takes precedence over the session cookie.
+pub fn credential(req: &HttpRequest) -> Option<Credential> {
+    if let Some(authorization) = req.headers().get("Authorization") {
+        return authorization
+            .to_str()
+            .ok()?
+            .strip_prefix("Bearer ")
+            .map(|token| Credential::Token(token.trim().to_string()));
+    }
+
+    req.cookie("sessionid").map(Credential::Session)
+}
+
+/// Resolves the caller once per request, later lookups use the cached result.
+pub async fn resolve(req: &HttpRequest) -> Option<Principal> {
+    if let Some(principal) = req.extensions().get::<Principal>() {
+        return Some(principal.clone());
+    }
+
+    let data = req.app_data::<web::Data<AppData>>()?;
+    let principal = data.logic.authenticate(credential(req)?).await.ok()?;
+
+    req.extensions_mut().insert(principal.clone());
+    // The request's span, which is the current one outside of `Logic`.
+    Span::current().record("user_id", principal.user_id);
+
+    Some(principal)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::cookie::Cookie;
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpMessage, HttpResponse, web};
+
+    use super::{Require, credential};
+    use crate::{Credential, Principal, Role, Scope};
+
+    fn principal(caller: &str) -> Option<Principal> {
+        let (roles, scopes, session) = match caller {
+            "admin" => (vec![Role::User, Role::Admin], Scope::ALL.to_vec(), true),
+            "user" => (vec![Role::User], Scope::ALL.to_vec(), true),
+            "reader" => (vec![Role::User], vec![Scope::TodoRead], false),
+            "writer" => (vec![Role::User], vec![Scope::TodoWrite], false),
+            _ => return None,
+        };
+
+        Some(Principal {
+            user_id: 1,
+            roles,
+            scopes,
+            session: session.then(|| String::from("session")),
+            token_id: (!session).then_some(1),
+            list_id: None,
+        })
+    }
+
+    async fn status(caller: &str, path: &str) -> StatusCode {
+        let app = init_service(
+            App::new()
+                .wrap_fn(|req, service| {
+                    let caller = req
+                        .headers()
+                        .get("x-caller")
+                        .and_then(|caller| caller.to_str().ok())
+                        .and_then(principal);
+                    if let Some(principal) = caller {
+                        req.extensions_mut().insert(principal);
+                    }
+                    service.call(req)
+                })
+                .service(
+                    web::resource("/authenticated")
+                        .wrap(Require::authenticated())
+                        .to(HttpResponse::Ok),
+                )
+                .service(
+                    web::resource("/admin")
+                        .wrap(Require::role(Role::Admin))
+                        .to(HttpResponse::Ok),
+                )
+                .service(
+                    web::resource("/session")
+                        .wrap(Require::session())
+                        .to(HttpResponse::Ok),
+                )
+                .service(
+                    web::resource("/read")
+                        .wrap(Require::scope(Scope::TodoRead))
+                        .to(HttpResponse::Ok),
+                )
+                .service(
+                    web::resource("/write")
+                        .wrap(Require::scope(Scope::TodoWrite))
+                        .to(HttpResponse::Ok),
+                ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri(path)
+            .insert_header(("x-caller", caller))
+            .to_request();
+
+        call_service(&app, req).await.status()
+    }
+
+    #[actix_web::test]
+    async fn unknown_callers_are_unauthorized() {
+        for path in ["/authenticated", "/admin", "/session", "/read", "/write"] {
+            assert_eq!(status("nobody", path).await, StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[actix_web::test]
+    async fn requirements_are_checked() {
+        let expected = [
+            ("admin", "/admin", StatusCode::OK),
+            ("user", "/admin", StatusCode::FORBIDDEN),
+            ("user", "/session", StatusCode::OK),
+            ("user", "/write", StatusCode::OK),
+            ("reader", "/authenticated", StatusCode::OK),
+            ("reader", "/session", StatusCode::FORBIDDEN),
+            ("reader", "/read", StatusCode::OK),
+            ("reader", "/write", StatusCode::FORBIDDEN),
+            ("writer", "/read", StatusCode::OK),
+            ("writer", "/write", StatusCode::OK),
+        ];
+
+        for (caller, path, status_code) in expected {
+            assert_eq!(
+                status(caller, path).await,
+                status_code,
+                "{} on {}",
+                caller,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn bearer_tokens_take_precedence() {
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Bearer todo_pat_abc "))
+            .cookie(Cookie::new("sessionid", "session"))
+            .to_http_request();
+        assert!(
+            matches!(credential(&req), Some(Credential::Token(token)) if token == "todo_pat_abc")
+        );
+
+        // A malformed header doesn't fall back to the cookie.
+        let req = TestRequest::default()
+            .insert_header(("Authorization", "Basic YWxpY2U6"))
+            .cookie(Cookie::new("sessionid", "session"))
+            .to_http_request();
+        assert!(credential(&req).is_none());
+
+        let req = TestRequest::default()
+            .cookie(Cookie::new("sessionid", "session"))
+            .to_http_request();
+        assert!(
+            matches!(credential(&req), Some(Credential::Session(cookie)) if cookie.value() == "session")
+        );
+    }
+}
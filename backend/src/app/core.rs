@@ -1,24 +1,78 @@
-use std::{sync::Arc, u16};
+use std::sync::Arc;
 
 use actix_cors::Cors;
-use actix_web::{App as ActixApp, HttpServer, http::Method, middleware::Logger, web};
+use actix_web::dev::Server;
+use actix_web::middleware::Condition;
+use actix_web::{App as ActixApp, HttpRequest, HttpResponse, HttpServer, http::Method, web};
+use tracing::warn;
+#[cfg(feature = "docs-ui")]
+use utoipa_swagger_ui::{Config as SwaggerConfig, SwaggerUi};
 
-use crate::Logic;
 use crate::app::AppData;
+use crate::app::auth::Require;
+use crate::app::csrf::CsrfProtection;
+use crate::app::graphql;
 use crate::app::handlers;
+use crate::app::metrics::RequestMetrics;
+use crate::app::rate_limit::{MemoryStore, RateLimit, RateLimiter};
+use crate::app::request_id::RequestTracing;
+use crate::app::tls::Tls;
+use crate::app::versioning::{API_V1, ApiVersion, Legacy};
+use crate::config::Config;
+use crate::shutdown::Shutdown;
+use crate::{Logic, Role, Scope};
 
 pub struct App {}
 
 impl App {
+    /// With TLS the API is served over HTTPS, and the redirect port, if any,
+    /// sends plain HTTP requests there. Runs until the shutdown starts and the
+    /// requests in flight are drained. `/metrics` is served on its own port
+    /// when one is configured.
     pub async fn run(
-        address: &str,
-        port: u16,
-        allowed_origin: String,
+        app_config: &Config,
+        tls: Option<&Tls>,
         logic: Arc<Logic>,
+        shutdown: &Shutdown,
     ) -> std::io::Result<()> {
-        HttpServer::new(move || {
+        let config = &app_config.server;
+        let tls = tls.map(|tls| (tls, app_config.tls.redirect_port));
+        let metrics = &app_config.metrics;
+        let rate_limit = &app_config.rate_limit;
+        let docs = &app_config.docs;
+        let api = &app_config.api;
+        let allowed_origin = config.allowed_origin.clone();
+        let schema = web::Data::new(graphql::schema(logic.clone(), &app_config.graphql));
+        let graphql_enabled = app_config.graphql.enabled;
+        let app_data = web::Data::new(AppData::new(
+            logic,
+            shutdown.clone(),
+            metrics.token.clone(),
+            rate_limit.trust_proxy,
+        ));
+        let metrics_separate = metrics.port.is_some();
+        let json_limit = config.json_limit_bytes;
+        let docs_ui = docs.ui;
+        let legacy = Legacy::new(api);
+        let rate_limit_enabled = rate_limit.enabled;
+        let rate_limiter = Arc::new(RateLimiter::new(
+            rate_limit.clone(),
+            Box::new(MemoryStore::new()),
+        ));
+
+        if metrics.token.is_none() && !metrics_separate {
+            warn!("The metrics are public, set METRICS_TOKEN or METRICS_PORT to restrict them");
+        }
+
+        let main_app_data = app_data.clone();
+
+        let server = HttpServer::new(move || {
             ActixApp::new()
-                .wrap(Logger::default())
+                .wrap(CsrfProtection::new(allowed_origin.clone()))
+                .wrap(Condition::new(
+                    rate_limit_enabled,
+                    RateLimit::new(rate_limiter.clone()),
+                ))
                 .wrap(
                     Cors::default()
                         .allowed_origin(&allowed_origin)
@@ -29,37 +83,350 @@ impl App {
                             Method::DELETE,
                         ])
                         .allow_any_header()
+                        .expose_headers(vec![
+                            "RateLimit-Limit",
+                            "RateLimit-Remaining",
+                            "RateLimit-Reset",
+                            "Retry-After",
+                            "API-Version",
+                            "Deprecation",
+                            "Sunset",
+                            "Link",
+                        ])
                         .supports_credentials()
                         .max_age(3600),
                 )
-                .app_data(web::Data::new(AppData::new(logic.clone())))
-                .route("/", web::get().to(handlers::root))
+                .wrap(RequestMetrics)
+                .wrap(RequestTracing)
+                .app_data(main_app_data.clone())
+                .app_data(schema.clone())
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(json_limit)
+                        .error_handler(handlers::json_error),
+                )
+                .configure(|config| {
+                    routes(config, metrics_separate, docs_ui, graphql_enabled, legacy)
+                })
+        })
+        .disable_signals()
+        .shutdown_timeout(config.shutdown_timeout_secs);
+
+        let server = match tls {
+            Some((tls, _)) => server
+                .bind_rustls_0_23((config.address.as_str(), config.port), tls.server_config())?,
+            None => server.bind((config.address.as_str(), config.port))?,
+        }
+        .run();
+
+        let redirect = match tls.and_then(|(_, redirect_port)| redirect_port) {
+            Some(redirect_port) => {
+                let https_port = config.port;
+
+                Some(
+                    HttpServer::new(move || {
+                        ActixApp::new().default_service(web::to(move |req: HttpRequest| {
+                            redirect_to_https(req, https_port)
+                        }))
+                    })
+                    .disable_signals()
+                    .shutdown_timeout(config.shutdown_timeout_secs)
+                    .bind((config.address.as_str(), redirect_port))?
+                    .run(),
+                )
+            }
+            None => None,
+        };
+
+        let metrics = match metrics.port {
+            Some(metrics_port) => Some(
+                HttpServer::new(move || {
+                    ActixApp::new()
+                        .wrap(RequestTracing)
+                        .app_data(app_data.clone())
+                        .route("/metrics", web::get().to(handlers::metrics))
+                })
+                .workers(1)
+                .disable_signals()
+                .shutdown_timeout(config.shutdown_timeout_secs)
+                .bind((metrics.address.as_str(), metrics_port))?
+                .run(),
+            ),
+            None => None,
+        };
+
+        tokio::try_join!(
+            stop_on_shutdown(server, shutdown),
+            run_optional(redirect, shutdown),
+            run_optional(metrics, shutdown),
+        )
+        .map(|_| ())
+    }
+}
+
+/// Every route. `/metrics` is left out when it is served on a port of its
+/// own, the unversioned aliases of the API when `legacy` is `None`.
+pub fn routes(
+    config: &mut web::ServiceConfig,
+    metrics_separate: bool,
+    docs_ui: bool,
+    graphql: bool,
+    legacy: Option<Legacy>,
+) {
+    if !metrics_separate {
+        config.route("/metrics", web::get().to(handlers::metrics));
+    }
+    if docs_ui {
+        docs_ui_routes(config);
+    }
+    if graphql {
+        config.service(
+            web::resource("/graphql")
+                .wrap(Require::authenticated())
+                .route(web::post().to(handlers::graphql))
+                .route(web::get().to(handlers::graphql_subscriptions)),
+        );
+    }
+
+    config
+        .route("/healthz", web::get().to(handlers::healthz))
+        .route("/readyz", web::get().to(handlers::readyz))
+        .route("/version", web::get().to(handlers::version))
+        .route("/openapi.json", web::get().to(handlers::openapi_spec))
+        .service(
+            web::scope(API_V1)
+                .wrap(ApiVersion::current())
+                .configure(api_v1),
+        );
+
+    // Last, the empty prefix matches everything.
+    if let Some(legacy) = legacy {
+        config.service(
+            web::scope("")
+                .wrap(ApiVersion::legacy(legacy))
+                .configure(api_v1),
+        );
+    }
+}
+
+fn api_v1(config: &mut web::ServiceConfig) {
+    config
+        .route("/", web::get().to(handlers::root))
+        .service(
+            web::scope("/user")
+                .service(
+                    web::resource("/")
+                        .wrap(Require::authenticated())
+                        .route(web::get().to(handlers::info)),
+                )
+                .service(
+                    web::resource("/export")
+                        .wrap(Require::session())
+                        .route(web::get().to(handlers::export_data)),
+                )
                 .service(
-                    web::scope("/user")
-                        .route("/", web::get().to(handlers::info))
+                    web::scope("/deletion")
+                        .wrap(Require::session())
+                        .route("", web::post().to(handlers::request_account_deletion))
+                        .route("", web::delete().to(handlers::cancel_account_deletion))
+                        .route("/confirm", web::post().to(handlers::delete_account)),
+                )
+                .service(
+                    web::scope("/sessions")
+                        .wrap(Require::session())
+                        .route("", web::get().to(handlers::sessions))
+                        .route("", web::delete().to(handlers::revoke_other_sessions))
+                        .route("/{session_id}", web::delete().to(handlers::revoke_session)),
+                )
+                .service(
+                    web::scope("/tokens")
+                        .wrap(Require::session())
+                        .route("", web::get().to(handlers::access_tokens))
+                        .route("", web::post().to(handlers::create_access_token))
+                        .route(
+                            "/{token_id}",
+                            web::delete().to(handlers::revoke_access_token),
+                        ),
+                )
+                .service(
+                    web::scope("/auth")
+                        .service(
+                            web::scope("/github")
+                                .route("/init", web::get().to(handlers::github_init))
+                                .route("/success", web::post().to(handlers::github_success)),
+                        )
+                        .service(
+                            web::scope("/local")
+                                .route("/register", web::post().to(handlers::local_register))
+                                .route("/login", web::post().to(handlers::local_login))
+                                .service(
+                                    web::resource("/password")
+                                        .wrap(Require::session())
+                                        .route(web::patch().to(handlers::change_password)),
+                                ),
+                        )
                         .service(
-                            web::scope("/auth")
+                            web::scope("/webauthn")
+                                .route("/login/init", web::post().to(handlers::webauthn_login_init))
+                                .route(
+                                    "/login/finish",
+                                    web::post().to(handlers::webauthn_login_finish),
+                                )
                                 .service(
-                                    web::scope("/github")
-                                        .route("/init", web::get().to(handlers::github_init))
+                                    web::scope("")
+                                        .wrap(Require::session())
+                                        .route(
+                                            "/register/init",
+                                            web::post().to(handlers::webauthn_register_init),
+                                        )
+                                        .route(
+                                            "/register/finish",
+                                            web::post().to(handlers::webauthn_register_finish),
+                                        )
+                                        .route(
+                                            "/credentials",
+                                            web::get().to(handlers::webauthn_credentials),
+                                        )
                                         .route(
-                                            "/success",
-                                            web::post().to(handlers::github_success),
+                                            "/credentials/{credential_id}",
+                                            web::patch().to(handlers::rename_webauthn_credential),
+                                        )
+                                        .route(
+                                            "/credentials/{credential_id}",
+                                            web::delete().to(handlers::delete_webauthn_credential),
                                         ),
-                                )
-                                .route("/logout", web::delete().to(handlers::logout)),
-                        ),
+                                ),
+                        )
+                        .service(
+                            web::scope("/totp")
+                                // Completes a session which is still
+                                // waiting for its second factor
+                                .route("/verify", web::post().to(handlers::totp_verify))
+                                .service(
+                                    web::scope("")
+                                        .wrap(Require::session())
+                                        .route("/enroll", web::post().to(handlers::totp_enroll))
+                                        .route("/enable", web::post().to(handlers::totp_enable))
+                                        .route(
+                                            "/recovery-codes",
+                                            web::post().to(handlers::totp_recovery_codes),
+                                        )
+                                        .route("", web::delete().to(handlers::totp_disable)),
+                                ),
+                        )
+                        .service(
+                            web::resource("/csrf")
+                                .wrap(Require::session())
+                                .route(web::get().to(handlers::csrf_token)),
+                        )
+                        .route("/logout", web::delete().to(handlers::logout)),
+                ),
+        )
+        .service(
+            web::scope("/admin")
+                .wrap(Require::role(Role::Admin))
+                .wrap(Require::session())
+                .route("/users", web::get().to(handlers::admin_users))
+                .route(
+                    "/users/{user_id}/disable",
+                    web::post().to(handlers::disable_user),
+                )
+                .route(
+                    "/users/{user_id}/enable",
+                    web::post().to(handlers::enable_user),
+                )
+                .route(
+                    "/users/{user_id}/logout",
+                    web::post().to(handlers::logout_user),
                 )
+                .route("/invites", web::get().to(handlers::invites))
+                .route("/invites", web::post().to(handlers::create_invite))
+                .route(
+                    "/invites/{invite_id}",
+                    web::delete().to(handlers::revoke_invite),
+                )
+                .route("/stats", web::get().to(handlers::admin_stats))
+                .route("/audit", web::get().to(handlers::audit_log)),
+        )
+        .service(
+            web::scope("/todo")
+                .wrap(Require::scope(Scope::TodoRead))
+                .route("/", web::get().to(handlers::get_items))
                 .service(
-                    web::scope("/todo")
-                        .route("/", web::get().to(handlers::get_items))
-                        .route("/set", web::post().to(handlers::set_item))
-                        .route("/update/{item_id}", web::patch().to(handlers::update_item))
-                        .route("/delete/{item_id}", web::delete().to(handlers::delete_item)),
+                    web::resource("/set")
+                        .wrap(Require::scope(Scope::TodoWrite))
+                        .route(web::post().to(handlers::set_item)),
                 )
-        })
-        .bind((address, port))?
-        .run()
-        .await
+                .service(
+                    web::resource("/update/{item_id}")
+                        .wrap(Require::scope(Scope::TodoWrite))
+                        .route(web::patch().to(handlers::update_item)),
+                )
+                .service(
+                    web::resource("/delete/{item_id}")
+                        .wrap(Require::scope(Scope::TodoWrite))
+                        .route(web::delete().to(handlers::delete_item)),
+                )
+                .route("/lists", web::get().to(handlers::get_lists))
+                .service(
+                    web::resource("/lists/set")
+                        .wrap(Require::scope(Scope::TodoWrite))
+                        .route(web::post().to(handlers::set_list)),
+                )
+                .service(
+                    web::resource("/lists/delete/{list_id}")
+                        .wrap(Require::scope(Scope::TodoWrite))
+                        .route(web::delete().to(handlers::delete_list)),
+                ),
+        );
+}
+
+#[cfg(feature = "docs-ui")]
+fn docs_ui_routes(config: &mut web::ServiceConfig) {
+    config.service(SwaggerUi::new("/docs/{_:.*}").config(SwaggerConfig::from("/openapi.json")));
+}
+
+/// Configurations turning the UI on without the feature don't validate.
+#[cfg(not(feature = "docs-ui"))]
+fn docs_ui_routes(_config: &mut web::ServiceConfig) {}
+
+async fn run_optional(server: Option<Server>, shutdown: &Shutdown) -> std::io::Result<()> {
+    match server {
+        Some(server) => stop_on_shutdown(server, shutdown).await,
+        None => Ok(()),
     }
 }
+
+/// Stops accepting connections once the shutdown starts and drains the
+/// ones which are open.
+fn stop_on_shutdown(server: Server, shutdown: &Shutdown) -> Server {
+    let handle = server.handle();
+    let mut shutdown = shutdown.subscribe();
+
+    tokio::spawn(async move {
+        let _ = shutdown.wait_for(|stop| *stop).await;
+        handle.stop(true).await;
+    });
+
+    server
+}
+
+async fn redirect_to_https(req: HttpRequest, https_port: u16) -> HttpResponse {
+    let connection_info = req.connection_info();
+    let host = connection_info.host();
+    // Without the port, `[::1]` has no digits after its last colon.
+    let host = match host.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        _ => host,
+    };
+
+    let location = if https_port == 443 {
+        format!("https://{}{}", host, req.uri())
+    } else {
+        format!("https://{}:{}{}", host, https_port, req.uri())
+    };
+
+    HttpResponse::PermanentRedirect()
+        .insert_header(("Location", location))
+        .finish()
+}
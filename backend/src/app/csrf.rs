@@ -0,0 +1,288 @@
+//! Cross-site request forgery protection.
+//!
+//! Requests which can change something (anything but `GET`, `HEAD` and
+//! `OPTIONS`) are rejected when their `Origin`, or lacking that their
+//! `Referer`, isn't the frontend. When they also carry the session cookie
+//! they need the session's CSRF token in the `X-CSRF-Token` header, which
+//! is returned when logging in and by `GET /user/auth/csrf`. Requests with
+//! a bearer token don't send cookies implicitly and are exempt.
+//!
+//! WebSocket handshakes are `GET` requests which browsers send cross-site
+//! with cookies and without custom headers, so their `Origin` is checked as
+//! well, but no CSRF token is asked for.
+
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::{Method, header};
+use actix_web::{Error, HttpResponse, web};
+use tracing::warn;
+
+use crate::app::AppData;
+
+pub struct CsrfProtection {
+    allowed_origin: Rc<str>,
+}
+
+impl CsrfProtection {
+    pub fn new(allowed_origin: String) -> Self {
+        CsrfProtection {
+            allowed_origin: allowed_origin.trim_end_matches('/').into(),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for CsrfProtection
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CsrfMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfMiddleware {
+            service: Rc::new(service),
+            allowed_origin: self.allowed_origin.clone(),
+        }))
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: Rc<S>,
+    allowed_origin: Rc<str>,
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+
+        if !is_allowed(&req, &self.allowed_origin) {
+            return Box::pin(async move {
+                Ok(req
+                    .into_response(HttpResponse::Forbidden().finish())
+                    .map_into_right_body())
+            });
+        }
+
+        Box::pin(async move {
+            service
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body)
+        })
+    }
+}
+
+fn is_allowed(req: &ServiceRequest, allowed_origin: &str) -> bool {
+    let websocket = req
+        .headers()
+        .get(header::UPGRADE)
+        .is_some_and(|upgrade| upgrade.as_bytes().eq_ignore_ascii_case(b"websocket"));
+
+    if (!websocket && matches!(*req.method(), Method::GET | Method::HEAD | Method::OPTIONS))
+        || req
+            .headers()
+            .get("Authorization")
+            .and_then(|authorization| authorization.to_str().ok())
+            .is_some_and(|authorization| authorization.starts_with("Bearer "))
+    {
+        return true;
+    }
+
+    let origin = req
+        .headers()
+        .get("Origin")
+        .and_then(|origin| origin.to_str().ok())
+        .map(String::from)
+        .or_else(|| {
+            req.headers()
+                .get("Referer")
+                .and_then(|referer| referer.to_str().ok())
+                .and_then(referer_origin)
+        });
+
+    if let Some(origin) = origin
+        && origin != allowed_origin
+    {
+        warn!("Rejected a request from the foreign origin {}", origin);
+        return false;
+    }
+
+    if websocket {
+        return true;
+    }
+
+    let Some(session) = req.cookie("sessionid") else {
+        return true;
+    };
+
+    let Some(data) = req.app_data::<web::Data<AppData>>() else {
+        return false;
+    };
+
+    let valid = req
+        .headers()
+        .get("X-CSRF-Token")
+        .and_then(|csrf_token| csrf_token.to_str().ok())
+        .is_some_and(|csrf_token| data.logic.verify_csrf_token(session, csrf_token));
+
+    if !valid {
+        warn!("Rejected a request with a missing or invalid CSRF token");
+    }
+
+    valid
+}
+
+/// The `scheme://host[:port]` part of a `Referer`.
+fn referer_origin(referer: &str) -> Option<String> {
+    let (scheme, rest) = referer.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+
+    Some(format!("{}://{}", scheme, authority))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use actix_web::cookie::Cookie;
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, web};
+
+    use super::{CsrfProtection, referer_origin};
+    use crate::Logic;
+    use crate::app::AppData;
+    use crate::config::Config;
+    use crate::database::TestDatabase;
+    use crate::shutdown::Shutdown;
+
+    const ORIGIN: &str = "http://localhost:3000";
+
+    #[actix_web::test]
+    async fn changes_need_the_origin_and_the_sessions_token() {
+        let (_test_database, database) = TestDatabase::connect("csrf").await;
+        let logic = Arc::new(Logic::new(database, &Config::default()));
+        let csrf_token = logic.csrf_token(Cookie::new("sessionid", "session"));
+        let other_token = logic.csrf_token(Cookie::new("sessionid", "other"));
+
+        let app = init_service(
+            App::new()
+                .app_data(web::Data::new(AppData::new(
+                    logic,
+                    Shutdown::new(),
+                    None,
+                    false,
+                )))
+                .wrap(CsrfProtection::new(format!("{}/", ORIGIN)))
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let cases = [
+            (TestRequest::get(), StatusCode::OK),
+            (
+                TestRequest::get().insert_header(("Origin", "https://evil.example")),
+                StatusCode::OK,
+            ),
+            (TestRequest::post(), StatusCode::OK),
+            (
+                TestRequest::post().insert_header(("Origin", ORIGIN)),
+                StatusCode::OK,
+            ),
+            (
+                TestRequest::post().insert_header(("Origin", "https://evil.example")),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                TestRequest::delete().insert_header(("Referer", "https://evil.example/page")),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                TestRequest::post().insert_header(("Referer", "http://localhost:3000/todo?x")),
+                StatusCode::OK,
+            ),
+            (
+                TestRequest::post()
+                    .insert_header(("Origin", "https://evil.example"))
+                    .insert_header(("Authorization", "Bearer todo_pat_abc")),
+                StatusCode::OK,
+            ),
+            (
+                TestRequest::post()
+                    .insert_header(("Origin", ORIGIN))
+                    .cookie(Cookie::new("sessionid", "session")),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                TestRequest::post()
+                    .insert_header(("Origin", ORIGIN))
+                    .insert_header(("X-CSRF-Token", other_token))
+                    .cookie(Cookie::new("sessionid", "session")),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                TestRequest::post()
+                    .insert_header(("Origin", ORIGIN))
+                    .insert_header(("X-CSRF-Token", csrf_token.clone()))
+                    .cookie(Cookie::new("sessionid", "session")),
+                StatusCode::OK,
+            ),
+            (
+                TestRequest::post()
+                    .insert_header(("X-CSRF-Token", csrf_token))
+                    .cookie(Cookie::new("sessionid", "session")),
+                StatusCode::OK,
+            ),
+            (
+                TestRequest::get()
+                    .insert_header(("Upgrade", "websocket"))
+                    .insert_header(("Origin", "https://evil.example"))
+                    .cookie(Cookie::new("sessionid", "session")),
+                StatusCode::FORBIDDEN,
+            ),
+            (
+                TestRequest::get()
+                    .insert_header(("Upgrade", "WebSocket"))
+                    .insert_header(("Origin", ORIGIN))
+                    .cookie(Cookie::new("sessionid", "session")),
+                StatusCode::OK,
+            ),
+        ];
+
+        for (index, (req, status)) in cases.into_iter().enumerate() {
+            let res = call_service(&app, req.to_request()).await;
+            assert_eq!(res.status(), status, "case {}", index);
+        }
+    }
+
+    #[test]
+    fn referers_are_cut_down_to_their_origin() {
+        assert_eq!(
+            referer_origin("https://example.com:8443/a/b?c#d").as_deref(),
+            Some("https://example.com:8443")
+        );
+        assert_eq!(
+            referer_origin("http://localhost:3000").as_deref(),
+            Some("http://localhost:3000")
+        );
+        assert_eq!(referer_origin("not a url"), None);
+    }
+}
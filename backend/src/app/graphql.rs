@@ -0,0 +1,512 @@
+//! The GraphQL API on `/graphql`.
+//!
+//! Queries and mutations are posted as JSON, subscriptions run over a
+//! WebSocket on the same path with the `graphql-transport-ws` or the older
+//! `graphql-ws` protocol. Both are authenticated like the REST API, and the
+//! fields check the same scopes as the routes they mirror: items need
+//! `todo:read` or `todo:write`, sessions and personal access tokens can
+//! only be managed with a session. Signing in, passkeys, two-factor
+//! authentication, deleting the account and administration stay REST only.
+//!
+//! The dashboard's view is the user, the counts of their items, their lists
+//! and the items themselves. A personal access token restricted to a list
+//! only sees and changes that list, like on the REST API.
+
+use std::cmp::Reverse;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::{Error as HttpError, HttpRequest, HttpResponse, web};
+use actix_ws::{AggregatedMessage, CloseCode, CloseReason};
+use async_graphql::http::{WebSocket, WebSocketProtocols, WsMessage};
+use async_graphql::{
+    ComplexObject, Context, Data, Enum, Error, ErrorExtensions, Object, Result, Schema,
+    SimpleObject, Subscription, to_value,
+};
+use futures_util::future::ready;
+use futures_util::{Stream, StreamExt, stream};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+use crate::app::handlers::{AccessTokenInfo, SessionInfo, TodoItem, TodoList, User};
+use crate::config::GraphQlConfig;
+use crate::shutdown::Shutdown;
+use crate::{Credential, Logic, Principal, Scope, TodoChange, TodoError};
+
+/// The most items a list returns when no `first` is given.
+const DEFAULT_PAGE_SIZE: usize = 50;
+
+pub type TodoSchema = Schema<Query, Mutation, Subscription>;
+
+pub fn schema(logic: Arc<Logic>, config: &GraphQlConfig) -> TodoSchema {
+    Schema::build(Query, Mutation, Subscription)
+        .data(logic)
+        .limit_depth(config.max_depth)
+        .limit_complexity(config.max_complexity)
+        .finish()
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// The caller's account.
+    async fn viewer(&self, ctx: &Context<'_>) -> Result<User> {
+        let principal = authenticated(ctx)?;
+
+        logic(ctx)
+            .get_user(principal.user_id)
+            .await
+            .map_err(|_| internal())
+    }
+
+    /// The caller's sessions which haven't expired. Needs a session.
+    async fn sessions(&self, ctx: &Context<'_>) -> Result<Vec<SessionInfo>> {
+        let principal = session(ctx)?;
+
+        logic(ctx).sessions(principal).await.map_err(|_| internal())
+    }
+
+    /// The caller's personal access tokens. Needs a session.
+    async fn access_tokens(&self, ctx: &Context<'_>) -> Result<Vec<AccessTokenInfo>> {
+        let principal = session(ctx)?;
+
+        logic(ctx)
+            .access_tokens(principal.user_id)
+            .await
+            .map_err(|_| internal())
+    }
+}
+
+#[derive(SimpleObject)]
+struct ItemCounts {
+    total: u32,
+    done: u32,
+    open: u32,
+}
+
+#[ComplexObject]
+impl User {
+    /// Only those on the token's list when it is restricted to one. Needs
+    /// `todo:read`.
+    async fn item_counts(&self, ctx: &Context<'_>) -> Result<ItemCounts> {
+        let principal = authorized(ctx, Scope::TodoRead)?;
+
+        let (total, done) = logic(ctx)
+            .count_items(self.id, principal.list_id)
+            .await
+            .map_err(|_| internal())?;
+
+        Ok(ItemCounts {
+            total,
+            done,
+            open: total - done,
+        })
+    }
+
+    /// The newest first, only those which are done or open when `done` is
+    /// given and those on the list when `listId` is, at most 100. Needs
+    /// `todo:read`.
+    // The complexity is computed before `first` is validated.
+    #[graphql(complexity = "first.min(100).saturating_mul(child_complexity)")]
+    async fn items(
+        &self,
+        ctx: &Context<'_>,
+        done: Option<bool>,
+        list_id: Option<u32>,
+        #[graphql(default_with = "DEFAULT_PAGE_SIZE", validator(maximum = 100))] first: usize,
+    ) -> Result<Vec<TodoItem>> {
+        let list_id = list(authorized(ctx, Scope::TodoRead)?, list_id)?;
+
+        let mut items = logic(ctx)
+            .get_items(self.id, list_id)
+            .await
+            .map_err(|_| internal())?;
+
+        items.retain(|item| done.is_none_or(|done| item.done == done));
+        items.sort_unstable_by_key(|item| Reverse(item.id));
+        items.truncate(first);
+
+        Ok(items)
+    }
+
+    /// Only the token's list when it is restricted to one. Needs
+    /// `todo:read`.
+    async fn lists(&self, ctx: &Context<'_>) -> Result<Vec<TodoList>> {
+        let principal = authorized(ctx, Scope::TodoRead)?;
+
+        logic(ctx)
+            .get_lists(self.id, principal.list_id)
+            .await
+            .map_err(|_| internal())
+    }
+}
+
+pub struct Mutation;
+
+#[Object]
+impl Mutation {
+    /// Puts the item on the list when `listId` is given, or on the token's
+    /// list. Needs `todo:write`.
+    async fn add_item(
+        &self,
+        ctx: &Context<'_>,
+        content: String,
+        list_id: Option<u32>,
+    ) -> Result<TodoItem> {
+        let principal = authorized(ctx, Scope::TodoWrite)?;
+        let list_id = list(principal, list_id)?;
+
+        logic(ctx)
+            .add_item(principal.user_id, list_id, &content)
+            .await
+            .map_err(|err| {
+                todo_error(
+                    err,
+                    "The todo item is invalid",
+                    "The account has as many todo items as it is allowed",
+                )
+            })
+    }
+
+    /// `null` when the caller has no such item. Needs `todo:write`.
+    async fn update_item(
+        &self,
+        ctx: &Context<'_>,
+        item_id: u32,
+        done: bool,
+    ) -> Result<Option<TodoItem>> {
+        let principal = authorized(ctx, Scope::TodoWrite)?;
+
+        logic(ctx)
+            .update_item(principal.user_id, principal.list_id, item_id, done)
+            .await
+            .map_err(|_| internal())
+    }
+
+    /// `false` when the caller has no such item. Needs `todo:write`.
+    async fn delete_item(&self, ctx: &Context<'_>, item_id: u32) -> Result<bool> {
+        let principal = authorized(ctx, Scope::TodoWrite)?;
+
+        logic(ctx)
+            .delete_item(principal.user_id, principal.list_id, item_id)
+            .await
+            .map_err(|_| internal())
+    }
+
+    /// Needs `todo:write` and a token which isn't restricted to a list.
+    async fn add_list(&self, ctx: &Context<'_>, name: String) -> Result<TodoList> {
+        let principal = all_lists(ctx)?;
+
+        logic(ctx)
+            .add_list(principal.user_id, &name)
+            .await
+            .map_err(|err| {
+                todo_error(
+                    err,
+                    "The todo list is invalid",
+                    "The account has as many todo lists as it is allowed",
+                )
+            })
+    }
+
+    /// Deletes the items on the list and the tokens restricted to it as
+    /// well, `false` when the caller has no such list. Needs `todo:write`
+    /// and a token which isn't restricted to a list.
+    async fn delete_list(&self, ctx: &Context<'_>, list_id: u32) -> Result<bool> {
+        let principal = all_lists(ctx)?;
+
+        logic(ctx)
+            .delete_list(principal.user_id, list_id)
+            .await
+            .map_err(|_| internal())
+    }
+
+    /// `false` when the caller has no such session. Needs a session.
+    async fn revoke_session(&self, ctx: &Context<'_>, session_id: u32) -> Result<bool> {
+        let principal = session(ctx)?;
+
+        logic(ctx)
+            .revoke_session(principal.user_id, session_id)
+            .await
+            .map_err(|_| internal())
+    }
+
+    /// `false` when the caller has no such token. Needs a session.
+    async fn revoke_access_token(&self, ctx: &Context<'_>, token_id: u32) -> Result<bool> {
+        let principal = session(ctx)?;
+
+        logic(ctx)
+            .revoke_access_token(principal.user_id, token_id)
+            .await
+            .map_err(|_| internal())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Enum)]
+enum ChangeKind {
+    Added,
+    Updated,
+    Deleted,
+}
+
+#[derive(SimpleObject)]
+struct ItemChange {
+    kind: ChangeKind,
+    item_id: u32,
+    /// `null` when the item was deleted.
+    item: Option<TodoItem>,
+}
+
+impl From<TodoChange> for ItemChange {
+    fn from(change: TodoChange) -> Self {
+        match change {
+            TodoChange::Added(item) => ItemChange {
+                kind: ChangeKind::Added,
+                item_id: item.id as u32,
+                item: Some(item),
+            },
+            TodoChange::Updated(item) => ItemChange {
+                kind: ChangeKind::Updated,
+                item_id: item.id as u32,
+                item: Some(item),
+            },
+            TodoChange::Deleted(item) => ItemChange {
+                kind: ChangeKind::Deleted,
+                item_id: item.id as u32,
+                item: None,
+            },
+        }
+    }
+}
+
+pub struct Subscription;
+
+#[Subscription]
+impl Subscription {
+    /// Changes to the caller's items, only those on the token's list when it
+    /// is restricted to one. Ends once the session or token it was started
+    /// with is revoked, which is checked with every change. Needs
+    /// `todo:read`.
+    async fn item_changes(
+        &self,
+        ctx: &Context<'_>,
+    ) -> Result<impl Stream<Item = Result<ItemChange>> + use<>> {
+        let principal = authorized(ctx, Scope::TodoRead)?;
+        let (user_id, list_id) = (principal.user_id, principal.list_id);
+        let credential = ctx.data::<Credential>()?.clone();
+        let logic = logic(ctx).clone();
+        let receiver = logic.item_changes();
+
+        Ok(stream::unfold(Some(receiver), move |receiver| {
+            let logic = logic.clone();
+            let credential = credential.clone();
+
+            async move {
+                let mut receiver = receiver?;
+
+                loop {
+                    let event = match receiver.recv().await {
+                        Ok(event) => event,
+                        Err(RecvError::Lagged(missed)) => {
+                            debug!(user_id, missed, "A subscriber fell behind");
+                            let err = error("LAGGED", "Changes were missed, refetch the items");
+                            return Some((Err(err), None));
+                        }
+                        Err(RecvError::Closed) => return None,
+                    };
+
+                    let item_list_id = event.change.item().list_id.map(|id| id as u32);
+                    if event.user_id != user_id
+                        || list_id.is_some_and(|list_id| item_list_id != Some(list_id))
+                    {
+                        continue;
+                    }
+
+                    let still_allowed =
+                        logic
+                            .authenticate(credential.clone())
+                            .await
+                            .is_ok_and(|principal| {
+                                principal.user_id == user_id && principal.has_scope(Scope::TodoRead)
+                            });
+                    if !still_allowed {
+                        return None;
+                    }
+
+                    return Some((Ok(ItemChange::from(event.change)), Some(receiver)));
+                }
+            }
+        }))
+    }
+}
+
+/// Runs the subscriptions of a WebSocket connection until either side
+/// closes it or the shutdown starts.
+pub fn serve_websocket(
+    req: &HttpRequest,
+    payload: web::Payload,
+    schema: TodoSchema,
+    principal: Principal,
+    credential: Credential,
+    shutdown: &Shutdown,
+) -> Result<HttpResponse, HttpError> {
+    let Some(protocol) = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|protocols| protocols.to_str().ok())
+        .and_then(|protocols| {
+            protocols
+                .split(',')
+                .find_map(|protocol| WebSocketProtocols::from_str(protocol.trim()).ok())
+        })
+    else {
+        return Ok(HttpResponse::BadRequest()
+            .body("The Sec-WebSocket-Protocol must be graphql-transport-ws or graphql-ws"));
+    };
+
+    let (mut res, mut session, messages) = actix_ws::handle(req, payload)?;
+    res.headers_mut().insert(
+        header::SEC_WEBSOCKET_PROTOCOL,
+        HeaderValue::from_static(protocol.sec_websocket_protocol()),
+    );
+
+    let pong = session.clone();
+    let messages = messages
+        .aggregate_continuations()
+        .take_while(|message| {
+            ready(matches!(
+                message,
+                Ok(AggregatedMessage::Text(_)
+                    | AggregatedMessage::Ping(_)
+                    | AggregatedMessage::Pong(_))
+            ))
+        })
+        .filter_map(move |message| {
+            let mut pong = pong.clone();
+
+            async move {
+                match message {
+                    Ok(AggregatedMessage::Text(text)) => Some(text.into_bytes()),
+                    Ok(AggregatedMessage::Ping(bytes)) => {
+                        let _ = pong.pong(&bytes).await;
+                        None
+                    }
+                    _ => None,
+                }
+            }
+        });
+
+    let mut data = Data::default();
+    data.insert(principal);
+    data.insert(credential);
+
+    let mut responses = Box::pin(WebSocket::new(schema, messages, protocol).connection_data(data));
+    let mut shutdown = shutdown.subscribe();
+
+    actix_web::rt::spawn(async move {
+        let reason = loop {
+            tokio::select! {
+                response = responses.next() => match response {
+                    Some(WsMessage::Text(text)) => {
+                        if session.text(text).await.is_err() {
+                            return;
+                        }
+                    }
+                    Some(WsMessage::Close(code, reason)) => {
+                        break Some(CloseReason {
+                            code: CloseCode::from(code),
+                            description: Some(reason),
+                        });
+                    }
+                    None => break None,
+                },
+                _ = shutdown.wait_for(|started| *started) => {
+                    break Some(CloseReason::from(CloseCode::Away));
+                }
+            }
+        };
+
+        let _ = session.close(reason).await;
+    });
+
+    Ok(res)
+}
+
+fn logic<'a>(ctx: &Context<'a>) -> &'a Arc<Logic> {
+    ctx.data_unchecked::<Arc<Logic>>()
+}
+
+fn authenticated<'a>(ctx: &Context<'a>) -> Result<&'a Principal> {
+    ctx.data::<Principal>()
+        .map_err(|_| error("UNAUTHENTICATED", "Not authenticated"))
+}
+
+/// A session or a personal access token with the scope.
+fn authorized<'a>(ctx: &Context<'a>, scope: Scope) -> Result<&'a Principal> {
+    let principal = authenticated(ctx)?;
+
+    if !principal.has_scope(scope) {
+        return Err(error(
+            "FORBIDDEN",
+            format!("The token lacks the {} scope", scope.as_str()),
+        ));
+    }
+
+    Ok(principal)
+}
+
+/// Personal access tokens aren't allowed to manage the account.
+fn session<'a>(ctx: &Context<'a>) -> Result<&'a Principal> {
+    let principal = authenticated(ctx)?;
+
+    if principal.session.is_none() {
+        return Err(error("FORBIDDEN", "Only a session can do this"));
+    }
+
+    Ok(principal)
+}
+
+/// The list to work on, see `Principal::list`.
+fn list(principal: &Principal, requested: Option<u32>) -> Result<Option<u32>> {
+    principal
+        .list(requested)
+        .map_err(|_| error("FORBIDDEN", "The token is restricted to another list"))
+}
+
+/// Tokens restricted to a list can't manage lists.
+fn all_lists<'a>(ctx: &Context<'a>) -> Result<&'a Principal> {
+    let principal = authorized(ctx, Scope::TodoWrite)?;
+
+    if principal.list_id.is_some() {
+        return Err(error(
+            "FORBIDDEN",
+            "The token is restricted to another list",
+        ));
+    }
+
+    Ok(principal)
+}
+
+fn todo_error(err: TodoError, invalid: &'static str, quota_reached: &'static str) -> Error {
+    match err {
+        TodoError::Invalid(fields) => Error::new(invalid).extend_with(|_, extensions| {
+            extensions.set("code", "INVALID");
+            if let Ok(fields) = to_value(&fields) {
+                extensions.set("fields", fields);
+            }
+        }),
+        TodoError::QuotaReached => error("QUOTA_REACHED", quota_reached),
+        TodoError::Internal => internal(),
+    }
+}
+
+fn error(code: &'static str, message: impl Into<String>) -> Error {
+    Error::new(message).extend_with(|_, extensions| extensions.set("code", code))
+}
+
+/// `Logic` logged what went wrong already.
+fn internal() -> Error {
+    error("INTERNAL", "Something went wrong")
+}
@@ -1,52 +1,379 @@
-use actix_web::{HttpRequest, HttpResponse, Responder, web};
+use actix_web::cookie::SameSite;
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::{Error, HttpRequest, HttpResponse, HttpResponseBuilder, Responder, web};
+use async_graphql::SimpleObject;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use subtle::ConstantTimeEq;
+use utoipa::{IntoParams, ToSchema};
 
-use crate::app::AppData;
+use crate::app::graphql::{self, TodoSchema};
+use crate::app::openapi;
+use crate::app::{AppData, auth};
+use crate::logic::StoredCredential;
+use crate::metrics::{self, METRICS};
+use crate::{
+    AccountError, AdminError, AdmissionError, AuthenticationCredential, ClientInfo, GitHubError,
+    LocalAuthError, NewSession, Principal, RegistrationCredential, Role, Scope, TodoError,
+    TotpError,
+};
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
 struct Root {
     user_count: u32,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, ToSchema)]
+struct Health {
+    status: &'static str,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Check {
+    Ok,
+    Failed,
+    /// Turned off in the configuration, which doesn't fail readiness.
+    Disabled,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Readiness {
+    pub ready: bool,
+    pub database: Check,
+    pub migrations: Check,
+    pub github_oauth: Check,
+    /// Fails once the shutdown starts, so no new requests are sent here.
+    pub shutdown: Check,
+}
+
+#[derive(Serialize, ToSchema)]
+struct Version {
+    version: &'static str,
+    git_hash: &'static str,
+    /// Unix seconds.
+    build_time: u64,
+    features: Vec<&'static str>,
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+#[graphql(complex)]
 pub struct User {
     pub id: u32,
-    pub github_id: u32,
+    pub github_id: Option<u32>,
     pub username: String,
     pub email: String,
-    pub profile_picture_url: String,
+    pub profile_picture_url: Option<String>,
+    pub role: Role,
+    pub disabled: bool,
+    /// When the account will be deleted, if its deletion was requested.
+    pub deletion_scheduled: Option<u64>,
 }
 
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, ToSchema)]
 pub struct GitHubInit {
     pub redirect_url: String,
 }
 
-#[derive(Clone, Debug, Deserialize)]
+#[derive(Clone, Debug, Deserialize, ToSchema)]
 pub struct GitHubSucces {
     pub code: String,
     pub csrf_token: String,
+    /// Only needed for new accounts in invite-only mode.
+    pub invite_code: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LocalRegister {
+    pub username: String,
+    pub email: String,
+    pub password: String,
+    pub invite_code: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LocalLogin {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PasswordChange {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WebAuthnRegister {
+    #[serde(default)]
+    pub name: String,
+    pub credential: RegistrationCredential,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct WebAuthnRename {
+    pub name: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TotpCode {
+    pub code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct TotpEnrollment {
+    secret: String,
+    provisioning_uri: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct SessionCreated {
+    two_factor_required: bool,
+    csrf_token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct CsrfToken {
+    csrf_token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AuthError {
+    error: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    reasons: Vec<&'static str>,
+}
+
+/// Returned with 422 when a request is well-formed but its values aren't
+/// accepted.
+#[derive(Serialize, ToSchema)]
+struct ValidationError {
+    error: &'static str,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    fields: Vec<FieldError>,
+}
+
+#[derive(Serialize, Debug, ToSchema)]
+pub struct FieldError {
+    pub field: String,
+    /// Stable for clients to match on, like `required` or `too_long`.
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: &str, code: &'static str, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.to_string(),
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+#[graphql(name = "Session")]
+pub struct SessionInfo {
+    pub id: u32,
+    pub created: u64,
+    pub last_seen: u64,
+    pub expires: u64,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub current: bool,
+}
+
+#[derive(Serialize, ToSchema, SimpleObject)]
+#[graphql(name = "AccessToken")]
+pub struct AccessTokenInfo {
+    pub id: u32,
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub created: u64,
+    pub expires: Option<u64>,
+    pub last_used: Option<u64>,
+    /// The only list the token reaches, every list when `null`.
+    pub list_id: Option<u32>,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewAccessToken {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<u64>,
+    /// Restricts the token to one of the caller's lists.
+    pub list_id: Option<u32>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct AccessTokenCreated {
+    id: u32,
+    token: String,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct UserSearch {
+    pub query: Option<String>,
+    #[serde(default = "default_page_size")]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct Page {
+    #[serde(default = "default_page_size")]
+    pub limit: u32,
+    #[serde(default)]
+    pub offset: u32,
+}
+
+fn default_page_size() -> u32 {
+    50
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AdminStats {
+    pub users: u32,
+    pub disabled_users: u32,
+    pub admins: u32,
+    pub active_sessions: u32,
+    pub access_tokens: u32,
+    pub todo_items: u32,
+    pub completed_todo_items: u32,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct AuditEntry {
+    pub id: u32,
+    /// `None` for actions taken by the server itself.
+    pub actor_id: Option<u32>,
+    pub action: String,
+    pub target_id: Option<u32>,
+    pub details: Option<String>,
+    pub ip: Option<String>,
+    pub created: u64,
 }
 
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Deserialize, ToSchema)]
+pub struct NewInvite {
+    #[serde(default = "default_invite_uses")]
+    pub max_uses: u32,
+    pub expires_in_days: Option<u64>,
+}
+
+fn default_invite_uses() -> u32 {
+    1
+}
+
+#[derive(Serialize, ToSchema)]
+struct InviteCreated {
+    id: u32,
+    code: String,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct InviteInfo {
+    pub id: u32,
+    pub created_by: Option<u32>,
+    pub created: u64,
+    pub expires: Option<u64>,
+    pub max_uses: u32,
+    pub uses: u32,
+}
+
+/// Everything stored about a user, their items and the lists they are on
+/// included.
+#[derive(Serialize, ToSchema)]
+pub struct DataExport {
+    pub version: u32,
+    pub exported: u64,
+    pub user: User,
+    pub todo_items: Vec<TodoItem>,
+    pub todo_lists: Vec<TodoList>,
+    pub sessions: Vec<SessionInfo>,
+    pub access_tokens: Vec<AccessTokenInfo>,
+    pub passkeys: Vec<StoredCredential>,
+    pub two_factor_enabled: bool,
+    pub audit_entries: Vec<AuditEntry>,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DeletionConfirmation {
+    confirmation_token: String,
+    expires: u64,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct AccountDeletion {
+    pub confirmation_token: String,
+}
+
+#[derive(Serialize, ToSchema)]
+struct DeletionScheduled {
+    deletion_scheduled: u64,
+}
+
+#[derive(Clone, Serialize, Deserialize, FromRow, ToSchema, SimpleObject)]
 pub struct TodoItem {
     pub id: i32,
     pub content: String,
     pub done: bool,
+    #[graphql(skip)]
     pub user_id: i32,
+    /// `null` when the item isn't on a list.
+    pub list_id: Option<i32>,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct NewTodoItem {
     pub content: String,
+    /// The list to put the item on. A token restricted to a list puts it on
+    /// that one.
+    pub list_id: Option<u32>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct ItemFilter {
+    /// Only the items on this list.
+    pub list_id: Option<u32>,
+}
+
+#[derive(Clone, Serialize, FromRow, ToSchema, SimpleObject)]
+pub struct TodoList {
+    pub id: i32,
+    pub name: String,
+    #[graphql(skip)]
+    pub user_id: i32,
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct NewTodoList {
+    pub name: String,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, ToSchema)]
 pub struct UpdateTodoItem {
     pub done: bool,
 }
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/",
+    tag = "meta",
+    responses(
+        (status = 200, description = "The number of users", body = Root),
+        (status = 500, description = "Something went wrong"),
+    ),
+)]
 pub async fn root(data: web::Data<AppData>) -> impl Responder {
     let Ok(user_count) = data.logic.user_count().await else {
         return HttpResponse::InternalServerError().finish();
@@ -55,20 +382,275 @@ pub async fn root(data: web::Data<AppData>) -> impl Responder {
     HttpResponse::Ok().json(Root { user_count })
 }
 
-pub async fn info(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
-    if let Some(session) = req.cookie("sessionid") {
-        if let Ok(user_id) = data.logic.validate(session).await {
-            if let Ok(user) = data.logic.get_user(user_id).await {
-                return HttpResponse::Ok().json(user);
-            }
+/// Liveness, answered without touching the database.
+#[utoipa::path(
+    get,
+    path = "/healthz",
+    tag = "meta",
+    responses(
+        (status = 200, description = "The process is alive", body = Health),
+    ),
+)]
+pub async fn healthz() -> impl Responder {
+    HttpResponse::Ok().json(Health { status: "ok" })
+}
+
+#[utoipa::path(
+    get,
+    path = "/readyz",
+    tag = "meta",
+    responses(
+        (status = 200, description = "Ready to serve requests", body = Readiness),
+        (status = 503, description = "A check failed", body = Readiness),
+    ),
+)]
+pub async fn readyz(data: web::Data<AppData>) -> impl Responder {
+    let readiness = data.logic.readiness(data.shutdown.is_started()).await;
+
+    if readiness.ready {
+        HttpResponse::Ok().json(readiness)
+    } else {
+        HttpResponse::ServiceUnavailable().json(readiness)
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/version",
+    tag = "meta",
+    responses(
+        (status = 200, description = "The build", body = Version),
+    ),
+)]
+pub async fn version() -> impl Responder {
+    HttpResponse::Ok().json(Version {
+        version: env!("CARGO_PKG_VERSION"),
+        git_hash: env!("GIT_HASH"),
+        build_time: env!("BUILD_TIME")
+            .parse()
+            .expect("The build script sets a number"),
+        features: env!("FEATURES")
+            .split(',')
+            .filter(|feature| !feature.is_empty())
+            .collect(),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/openapi.json",
+    tag = "meta",
+    responses(
+        (status = 200, description = "This document", body = Object),
+    ),
+)]
+pub async fn openapi_spec() -> impl Responder {
+    HttpResponse::Ok()
+        .content_type("application/json")
+        .body(openapi::spec())
+}
+
+#[utoipa::path(
+    get,
+    path = "/metrics",
+    tag = "meta",
+    responses(
+        (status = 200, description = "Prometheus text exposition format", body = String, content_type = "text/plain"),
+        (status = 401, description = "The metrics token is missing or wrong"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security((), ("metrics" = [])),
+)]
+pub async fn metrics(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
+    if let Some(token) = &data.metrics_token {
+        let given = req
+            .headers()
+            .get("Authorization")
+            .and_then(|authorization| authorization.to_str().ok())
+            .and_then(|authorization| authorization.strip_prefix("Bearer "))
+            .unwrap_or_default()
+            .trim();
+
+        if !bool::from(given.as_bytes().ct_eq(token.expose().as_bytes())) {
+            return HttpResponse::Unauthorized()
+                .insert_header(("WWW-Authenticate", "Bearer"))
+                .finish();
+        }
+    }
+
+    // Stale gauges are better than no metrics at all.
+    let _ = data.logic.update_metrics().await;
+
+    match METRICS.encode() {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(metrics::CONTENT_TYPE)
+            .body(body),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/",
+    tag = "user",
+    responses(
+        (status = 200, description = "The caller", body = User),
+        (status = 401, description = "Not authenticated"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = []), ("token" = [])),
+)]
+pub async fn info(principal: Principal, data: web::Data<AppData>) -> impl Responder {
+    match data.logic.get_user(principal.user_id).await {
+        Ok(user) => HttpResponse::Ok().json(user),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Account
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/export",
+    tag = "account",
+    responses(
+        (status = 200, description = "Everything stored about the caller, as an attachment", body = DataExport),
+        (status = 401, description = "Not authenticated"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn export_data(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    match data
+        .logic
+        .export_data(&principal, &client_info(&req).ip)
+        .await
+    {
+        Ok(export) => HttpResponse::Ok()
+            .insert_header((
+                "Content-Disposition",
+                format!(
+                    "attachment; filename=\"todo-export-{}.json\"",
+                    principal.user_id
+                ),
+            ))
+            .json(export),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// The first step of deleting the account, the token has to be sent back to
+/// confirm it.
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/deletion",
+    tag = "account",
+    responses(
+        (status = 200, description = "The token confirming the deletion", body = DeletionConfirmation),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn request_account_deletion(
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    let (confirmation_token, expires) = data.logic.request_account_deletion(&principal);
+
+    HttpResponse::Ok().json(DeletionConfirmation {
+        confirmation_token,
+        expires,
+    })
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/deletion/confirm",
+    tag = "account",
+    request_body = AccountDeletion,
+    responses(
+        (status = 200, description = "The account was deleted and the session cookie cleared"),
+        (status = 202, description = "The account will be deleted after the grace period", body = DeletionScheduled),
+        (status = 400, description = "The confirmation token is invalid or expired", body = AuthError),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn delete_account(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<AccountDeletion>,
+) -> impl Responder {
+    match data
+        .logic
+        .delete_account(&principal, &json.confirmation_token, &client_info(&req).ip)
+        .await
+    {
+        Ok(None) => HttpResponse::Ok()
+            .insert_header((
+                "Set-Cookie",
+                session_cookie("", 0, data.logic.cookie_same_site()),
+            ))
+            .finish(),
+        Ok(Some(deletion_scheduled)) => {
+            HttpResponse::Accepted().json(DeletionScheduled { deletion_scheduled })
         }
+        Err(AccountError::InvalidConfirmation) => HttpResponse::BadRequest().json(AuthError {
+            error: "The confirmation token is invalid or expired",
+            reasons: Vec::new(),
+        }),
+        Err(AccountError::Internal) => HttpResponse::InternalServerError().finish(),
     }
+}
 
-    HttpResponse::Unauthorized().finish()
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/deletion",
+    tag = "account",
+    responses(
+        (status = 200, description = "The scheduled deletion was cancelled"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 404, description = "No deletion is scheduled"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn cancel_account_deletion(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    match data
+        .logic
+        .cancel_account_deletion(&principal, &client_info(&req).ip)
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
 }
 
 // Auth
 
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/auth/github/init",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Where to send the user to sign in with GitHub", body = GitHubInit),
+        (status = 500, description = "Something went wrong"),
+    ),
+)]
 pub async fn github_init(data: web::Data<AppData>) -> impl Responder {
     let Ok(redirect_url) = data.logic.github_init().await else {
         return HttpResponse::InternalServerError().finish();
@@ -77,115 +659,1520 @@ pub async fn github_init(data: web::Data<AppData>) -> impl Responder {
     HttpResponse::Ok().json(GitHubInit { redirect_url })
 }
 
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/github/success",
+    tag = "auth",
+    request_body = GitHubSucces,
+    responses(
+        (status = 200, description = "Signed in, with the session cookie", body = SessionCreated),
+        (status = 401, description = "The code or CSRF token is invalid"),
+        (status = 403, description = "Not admitted", body = AuthError),
+        (status = 409, description = "The email address is already in use", body = AuthError),
+    ),
+)]
 pub async fn github_success(
+    req: HttpRequest,
     data: web::Data<AppData>,
     github_success: web::Json<GitHubSucces>,
 ) -> impl Responder {
-    if let Ok(session_value) = data
+    let result = data
+        .logic
+        .github_success(
+            &github_success.code,
+            &github_success.csrf_token,
+            github_success.invite_code.as_deref(),
+            client_info(&req),
+        )
+        .await;
+    metrics::record_login("github", result.is_ok());
+
+    match result {
+        Ok(session) => session_response(HttpResponse::Ok(), session),
+        Err(GitHubError::Rejected(err)) => admission_error(err),
+        Err(GitHubError::Unauthorized) => HttpResponse::Unauthorized().finish(),
+        Err(GitHubError::EmailTaken) => HttpResponse::Conflict().json(AuthError {
+            error: "The GitHub account's email address is already in use",
+            reasons: Vec::new(),
+        }),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/local/register",
+    tag = "auth",
+    request_body = LocalRegister,
+    responses(
+        (status = 201, description = "Registered and signed in, with the session cookie", body = SessionCreated),
+        (status = 400, description = "The username, email address or password is invalid", body = AuthError),
+        (status = 403, description = "Not admitted", body = AuthError),
+        (status = 409, description = "The username or email address is in use", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+)]
+pub async fn local_register(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    json: web::Json<LocalRegister>,
+) -> impl Responder {
+    let json = json.into_inner();
+
+    match data
         .logic
-        .github_success(&github_success.code, &github_success.csrf_token)
+        .local_register(
+            &json.username,
+            &json.email,
+            json.password,
+            json.invite_code.as_deref(),
+            client_info(&req),
+        )
         .await
     {
-        return HttpResponse::Ok()
-            .insert_header((
-                "Set-Cookie",
-                format!(
-                    "sessionid={}; Max-Age={}; HttpOnly; Path=/; Secure; Partitioned;",
-                    session_value,
-                    60 * 60 * 6
-                ),
-            ))
-            .finish();
-    };
+        Ok(session) => session_response(HttpResponse::Created(), session),
+        Err(err) => local_auth_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/local/login",
+    tag = "auth",
+    request_body = LocalLogin,
+    responses(
+        (status = 200, description = "Signed in, with the session cookie", body = SessionCreated),
+        (status = 401, description = "Invalid username or password", body = AuthError),
+        (status = 403, description = "The account is disabled", body = AuthError),
+        (status = 429, description = "Too many failed attempts", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+)]
+pub async fn local_login(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    json: web::Json<LocalLogin>,
+) -> impl Responder {
+    let json = json.into_inner();
+
+    let result = data
+        .logic
+        .local_login(&json.username, json.password, client_info(&req))
+        .await;
+    metrics::record_login("local", result.is_ok());
 
-    return HttpResponse::Unauthorized().finish();
+    match result {
+        Ok(session) => session_response(HttpResponse::Ok(), session),
+        Err(err) => local_auth_error(err),
+    }
 }
 
-pub async fn logout(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
-    if let Some(session) = req.cookie("sessionid") {
-        if let Err(()) = data.logic.logout(session).await {
-            return HttpResponse::Ok().finish();
-        }
+#[utoipa::path(
+    patch,
+    path = "/api/v1/user/auth/local/password",
+    tag = "auth",
+    request_body = PasswordChange,
+    responses(
+        (status = 200, description = "The password was changed"),
+        (status = 400, description = "The new password is too weak", body = AuthError),
+        (status = 401, description = "Not authenticated, or the current password is wrong", body = AuthError),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 409, description = "The account has no password", body = AuthError),
+        (status = 429, description = "Too many failed attempts", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn change_password(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<PasswordChange>,
+) -> impl Responder {
+    let json = json.into_inner();
+    let ip = client_info(&req).ip;
+
+    match data
+        .logic
+        .change_password(&principal, json.current_password, json.new_password, &ip)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => local_auth_error(err),
     }
+}
 
-    HttpResponse::Unauthorized().finish()
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/webauthn/register/init",
+    tag = "webauthn",
+    responses(
+        (status = 200, description = "`PublicKeyCredentialCreationOptions` for `navigator.credentials.create`", body = Object),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn webauthn_register_init(
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    match data.logic.webauthn_register_init(principal.user_id).await {
+        Ok(options) => HttpResponse::Ok().json(options),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
 }
 
-// Todo
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/webauthn/register/finish",
+    tag = "webauthn",
+    request_body = WebAuthnRegister,
+    responses(
+        (status = 201, description = "The passkey was added"),
+        (status = 400, description = "The attestation is invalid"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn webauthn_register_finish(
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<WebAuthnRegister>,
+) -> impl Responder {
+    let json = json.into_inner();
+
+    match data
+        .logic
+        .webauthn_register_finish(principal.user_id, &json.name, json.credential)
+        .await
+    {
+        Ok(()) => HttpResponse::Created().finish(),
+        Err(_) => HttpResponse::BadRequest().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/webauthn/login/init",
+    tag = "webauthn",
+    responses(
+        (status = 200, description = "`PublicKeyCredentialRequestOptions` for `navigator.credentials.get`", body = Object),
+        (status = 500, description = "Something went wrong"),
+    ),
+)]
+pub async fn webauthn_login_init(data: web::Data<AppData>) -> impl Responder {
+    match data.logic.webauthn_login_init().await {
+        Ok(options) => HttpResponse::Ok().json(options),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/webauthn/login/finish",
+    tag = "webauthn",
+    request_body = AuthenticationCredential,
+    responses(
+        (status = 200, description = "Signed in, with the session cookie", body = SessionCreated),
+        (status = 401, description = "The assertion is invalid"),
+    ),
+)]
+pub async fn webauthn_login_finish(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    json: web::Json<AuthenticationCredential>,
+) -> impl Responder {
+    let result = data
+        .logic
+        .webauthn_login_finish(json.into_inner(), client_info(&req))
+        .await;
+    metrics::record_login("webauthn", result.is_ok());
+
+    match result {
+        Ok(session) => session_response(HttpResponse::Ok(), session),
+        Err(_) => HttpResponse::Unauthorized().finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/auth/webauthn/credentials",
+    tag = "webauthn",
+    responses(
+        (status = 200, description = "The caller's passkeys", body = [StoredCredential]),
+        (status = 401, description = "Not authenticated"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn webauthn_credentials(
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    match data.logic.webauthn_credentials(principal.user_id).await {
+        Ok(credentials) => HttpResponse::Ok().json(credentials),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/user/auth/webauthn/credentials/{credential_id}",
+    tag = "webauthn",
+    params(("credential_id" = String, Path)),
+    request_body = WebAuthnRename,
+    responses(
+        (status = 200, description = "The passkey was renamed"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such passkey"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn rename_webauthn_credential(
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<String>,
+    json: web::Json<WebAuthnRename>,
+) -> impl Responder {
+    match data
+        .logic
+        .rename_webauthn_credential(principal.user_id, &path.into_inner(), &json.name)
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
 
-pub async fn get_items(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/auth/webauthn/credentials/{credential_id}",
+    tag = "webauthn",
+    params(("credential_id" = String, Path)),
+    responses(
+        (status = 200, description = "The passkey was deleted"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such passkey"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn delete_webauthn_credential(
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match data
+        .logic
+        .delete_webauthn_credential(principal.user_id, &path.into_inner())
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/totp/enroll",
+    tag = "totp",
+    responses(
+        (status = 200, description = "A secret to confirm with `/api/v1/user/auth/totp/enable`", body = TotpEnrollment),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 409, description = "Already enabled", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn totp_enroll(principal: Principal, data: web::Data<AppData>) -> impl Responder {
+    match data.logic.totp_enroll(principal.user_id).await {
+        Ok((secret, provisioning_uri)) => HttpResponse::Ok().json(TotpEnrollment {
+            secret,
+            provisioning_uri,
+        }),
+        Err(err) => totp_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/totp/enable",
+    tag = "totp",
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "Enabled, with the recovery codes", body = RecoveryCodes),
+        (status = 401, description = "Not authenticated, or the code is invalid", body = AuthError),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 409, description = "Not enrolled or already enabled", body = AuthError),
+        (status = 429, description = "Too many invalid codes", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn totp_enable(
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<TotpCode>,
+) -> impl Responder {
+    match data.logic.totp_enable(principal.user_id, &json.code).await {
+        Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodes { recovery_codes }),
+        Err(err) => totp_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/totp/verify",
+    tag = "totp",
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "The session completed its second factor"),
+        (status = 401, description = "Not authenticated, or the code is invalid", body = AuthError),
+        (status = 429, description = "Too many invalid codes", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn totp_verify(
+    req: HttpRequest,
+    data: web::Data<AppData>,
+    json: web::Json<TotpCode>,
+) -> impl Responder {
     let Some(session) = req.cookie("sessionid") else {
         return HttpResponse::Unauthorized().finish();
     };
 
-    let Ok(user_id) = data.logic.validate(session).await else {
-        return HttpResponse::Unauthorized().finish();
+    let result = data.logic.totp_verify(session, &json.code).await;
+    metrics::record_login("totp", result.is_ok());
+
+    match result {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => totp_error(err),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/auth/totp",
+    tag = "totp",
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "Disabled"),
+        (status = 401, description = "Not authenticated, or the code is invalid", body = AuthError),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 409, description = "Not enabled", body = AuthError),
+        (status = 429, description = "Too many invalid codes", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn totp_disable(
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<TotpCode>,
+) -> impl Responder {
+    match data.logic.totp_disable(principal.user_id, &json.code).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => totp_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/auth/totp/recovery-codes",
+    tag = "totp",
+    request_body = TotpCode,
+    responses(
+        (status = 200, description = "New recovery codes, replacing the old ones", body = RecoveryCodes),
+        (status = 401, description = "Not authenticated, or the code is invalid", body = AuthError),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 409, description = "Not enabled", body = AuthError),
+        (status = 429, description = "Too many invalid codes", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn totp_recovery_codes(
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<TotpCode>,
+) -> impl Responder {
+    match data
+        .logic
+        .totp_recovery_codes(principal.user_id, &json.code)
+        .await
+    {
+        Ok(recovery_codes) => HttpResponse::Ok().json(RecoveryCodes { recovery_codes }),
+        Err(err) => totp_error(err),
+    }
+}
+
+fn totp_error(err: TotpError) -> HttpResponse {
+    let (mut response, error) = match err {
+        TotpError::Unauthorized => return HttpResponse::Unauthorized().finish(),
+        TotpError::InvalidCode => (HttpResponse::Unauthorized(), "The code is invalid"),
+        TotpError::Throttled(retry_after) => {
+            let mut response = HttpResponse::TooManyRequests();
+            response.insert_header(("Retry-After", retry_after.as_secs().max(1).to_string()));
+            (response, "Too many invalid codes")
+        }
+        TotpError::AlreadyEnabled => (
+            HttpResponse::Conflict(),
+            "Two-factor authentication is already enabled",
+        ),
+        TotpError::NotEnrolled => (
+            HttpResponse::Conflict(),
+            "Two-factor authentication is not set up",
+        ),
+        TotpError::Internal => return HttpResponse::InternalServerError().finish(),
     };
 
-    match data.logic.get_items(user_id).await {
-        Ok(items) => return HttpResponse::Ok().json(items),
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+    response.json(AuthError {
+        error,
+        reasons: Vec::new(),
+    })
+}
+
+fn session_response(mut response: HttpResponseBuilder, session: NewSession) -> HttpResponse {
+    response
+        .insert_header((
+            "Set-Cookie",
+            session_cookie(&session.value, session.max_age, session.same_site),
+        ))
+        .json(SessionCreated {
+            two_factor_required: session.two_factor_required,
+            csrf_token: session.csrf_token,
+        })
+}
+
+fn session_cookie(value: &str, max_age: u64, same_site: SameSite) -> String {
+    format!(
+        "sessionid={}; Max-Age={}; HttpOnly; Path=/; Secure; SameSite={}; Partitioned;",
+        value, max_age, same_site
+    )
+}
+
+fn client_info(req: &HttpRequest) -> ClientInfo {
+    ClientInfo {
+        ip: req
+            .app_data::<web::Data<AppData>>()
+            .and_then(|data| data.client_ip(req))
+            .unwrap_or_else(|| String::from("unknown")),
+        user_agent: req
+            .headers()
+            .get("User-Agent")
+            .and_then(|user_agent| user_agent.to_str().ok())
+            .map(|user_agent| user_agent.chars().take(512).collect()),
     }
 }
 
-pub async fn set_item(
-    req: HttpRequest,
+fn local_auth_error(err: LocalAuthError) -> HttpResponse {
+    let (mut response, error, reasons) = match err {
+        LocalAuthError::InvalidCredentials => (
+            HttpResponse::Unauthorized(),
+            "Invalid username or password",
+            Vec::new(),
+        ),
+        LocalAuthError::Throttled(retry_after) => {
+            let mut response = HttpResponse::TooManyRequests();
+            response.insert_header(("Retry-After", retry_after.as_secs().max(1).to_string()));
+            (response, "Too many failed login attempts", Vec::new())
+        }
+        LocalAuthError::InvalidUsername => (
+            HttpResponse::BadRequest(),
+            "The username must be 3 to 39 letters, digits, dashes or underscores",
+            Vec::new(),
+        ),
+        LocalAuthError::InvalidEmail => (
+            HttpResponse::BadRequest(),
+            "The email address is invalid",
+            Vec::new(),
+        ),
+        LocalAuthError::WeakPassword(reasons) => (
+            HttpResponse::BadRequest(),
+            "The password is too weak",
+            reasons,
+        ),
+        LocalAuthError::UserExists => (
+            HttpResponse::Conflict(),
+            "The username or email address is already in use",
+            Vec::new(),
+        ),
+        LocalAuthError::NoPassword => (
+            HttpResponse::Conflict(),
+            "The account has no password",
+            Vec::new(),
+        ),
+        LocalAuthError::Disabled => (
+            HttpResponse::Forbidden(),
+            "The account is disabled",
+            Vec::new(),
+        ),
+        LocalAuthError::Rejected(err) => return admission_error(err),
+        LocalAuthError::Internal => return HttpResponse::InternalServerError().finish(),
+    };
+
+    response.json(AuthError { error, reasons })
+}
+
+fn admission_error(err: AdmissionError) -> HttpResponse {
+    let error = match err {
+        AdmissionError::NotAMember => {
+            "Signing in requires membership of an allowed GitHub organization or team"
+        }
+        AdmissionError::EmailNotVerified => "The primary GitHub email address isn't verified",
+        AdmissionError::EmailDomain => "The email address isn't in an allowed domain",
+        AdmissionError::InviteRequired => "Signing up requires an invite code",
+        AdmissionError::InvalidInvite => "The invite code is invalid, expired or used up",
+        AdmissionError::LocalSignupClosed => {
+            "Signing up with a password requires an invite code, sign in with GitHub instead"
+        }
+    };
+
+    HttpResponse::Forbidden().json(AuthError {
+        error,
+        reasons: Vec::new(),
+    })
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/sessions",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "The caller's sessions", body = [SessionInfo]),
+        (status = 401, description = "Not authenticated"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn sessions(principal: Principal, data: web::Data<AppData>) -> impl Responder {
+    match data.logic.sessions(&principal).await {
+        Ok(sessions) => HttpResponse::Ok().json(sessions),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/sessions/{session_id}",
+    tag = "sessions",
+    params(("session_id" = u32, Path)),
+    responses(
+        (status = 200, description = "The session was revoked"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such session"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn revoke_session(
+    principal: Principal,
     data: web::Data<AppData>,
-    json: web::Json<NewTodoItem>,
+    path: web::Path<u32>,
 ) -> impl Responder {
+    match data
+        .logic
+        .revoke_session(principal.user_id, path.into_inner())
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/sessions",
+    tag = "sessions",
+    responses(
+        (status = 200, description = "Every other session was revoked"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn revoke_other_sessions(
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    match data.logic.revoke_other_sessions(&principal).await {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/tokens",
+    tag = "tokens",
+    responses(
+        (status = 200, description = "The caller's personal access tokens", body = [AccessTokenInfo]),
+        (status = 401, description = "Not authenticated"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn access_tokens(principal: Principal, data: web::Data<AppData>) -> impl Responder {
+    match data.logic.access_tokens(principal.user_id).await {
+        Ok(tokens) => HttpResponse::Ok().json(tokens),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/user/tokens",
+    tag = "tokens",
+    request_body = NewAccessToken,
+    responses(
+        (status = 201, description = "The token, which is only ever returned here", body = AccessTokenCreated),
+        (status = 400, description = "The name, scopes, expiry or list are invalid", body = AuthError),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn create_access_token(
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<NewAccessToken>,
+) -> impl Responder {
+    let name_length = json.name.trim().chars().count();
+    if name_length == 0 || name_length > 64 {
+        return HttpResponse::BadRequest().json(AuthError {
+            error: "The name must be between 1 and 64 characters",
+            reasons: Vec::new(),
+        });
+    }
+
+    let Some(scopes) = json
+        .scopes
+        .iter()
+        .map(|scope| Scope::parse(scope))
+        .collect::<Option<Vec<Scope>>>()
+        .filter(|scopes| !scopes.is_empty())
+    else {
+        return HttpResponse::BadRequest().json(AuthError {
+            error: "The scopes must be todo:read and/or todo:write",
+            reasons: Vec::new(),
+        });
+    };
+
+    if json.expires_in_days == Some(0) {
+        return HttpResponse::BadRequest().json(AuthError {
+            error: "The token must expire at least one day from now",
+            reasons: Vec::new(),
+        });
+    }
+
+    match data
+        .logic
+        .create_access_token(
+            principal.user_id,
+            &json.name,
+            scopes,
+            json.expires_in_days,
+            json.list_id,
+        )
+        .await
+    {
+        Ok(Some((id, token))) => HttpResponse::Created().json(AccessTokenCreated { id, token }),
+        Ok(None) => HttpResponse::BadRequest().json(AuthError {
+            error: "The list doesn't exist",
+            reasons: Vec::new(),
+        }),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/tokens/{token_id}",
+    tag = "tokens",
+    params(("token_id" = u32, Path)),
+    responses(
+        (status = 200, description = "The token was revoked"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such token"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn revoke_access_token(
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<u32>,
+) -> impl Responder {
+    match data
+        .logic
+        .revoke_access_token(principal.user_id, path.into_inner())
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// The CSRF token of the current session, for clients which lost the one
+/// they got when logging in.
+#[utoipa::path(
+    get,
+    path = "/api/v1/user/auth/csrf",
+    tag = "auth",
+    responses(
+        (status = 200, description = "The session's CSRF token", body = CsrfToken),
+        (status = 401, description = "Not authenticated"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn csrf_token(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
     let Some(session) = req.cookie("sessionid") else {
         return HttpResponse::Unauthorized().finish();
     };
 
-    let Ok(user_id) = data.logic.validate(session).await else {
+    HttpResponse::Ok().json(CsrfToken {
+        csrf_token: data.logic.csrf_token(session),
+    })
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/user/auth/logout",
+    tag = "auth",
+    responses(
+        (status = 200, description = "Logged out and the session cookie cleared"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not allowed, or a missing or wrong CSRF token"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn logout(req: HttpRequest, data: web::Data<AppData>) -> impl Responder {
+    let Some(session) = req.cookie("sessionid") else {
         return HttpResponse::Unauthorized().finish();
     };
 
-    match data.logic.add_item(user_id, json.content.clone()).await {
-        Ok(_) => return HttpResponse::Created().finish(),
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+    match data.logic.logout(session).await {
+        Ok(true) => HttpResponse::Ok()
+            .insert_header((
+                "Set-Cookie",
+                session_cookie("", 0, data.logic.cookie_same_site()),
+            ))
+            .finish(),
+        Ok(false) => HttpResponse::Unauthorized().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
-pub async fn update_item(
+// Admin
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/users",
+    tag = "admin",
+    params(UserSearch),
+    responses(
+        (status = 200, description = "Matching users", body = [User]),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn admin_users(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    query: web::Query<UserSearch>,
+) -> impl Responder {
+    match data
+        .logic
+        .admin_users(
+            &principal,
+            query.query.as_deref().filter(|query| !query.is_empty()),
+            query.limit,
+            query.offset,
+            &client_info(&req).ip,
+        )
+        .await
+    {
+        Ok(users) => HttpResponse::Ok().json(users),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/stats",
+    tag = "admin",
+    responses(
+        (status = 200, description = "Counts across every user", body = AdminStats),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn admin_stats(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    match data
+        .logic
+        .admin_stats(&principal, &client_info(&req).ip)
+        .await
+    {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{user_id}/disable",
+    tag = "admin",
+    params(("user_id" = u32, Path)),
+    responses(
+        (status = 200, description = "Disabled and logged out"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such user"),
+        (status = 409, description = "The admin's own account", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn disable_user(
     req: HttpRequest,
+    principal: Principal,
     data: web::Data<AppData>,
     path: web::Path<u32>,
-    json: web::Json<UpdateTodoItem>,
 ) -> impl Responder {
-    let Some(session) = req.cookie("sessionid") else {
-        return HttpResponse::Unauthorized().finish();
+    match data
+        .logic
+        .admin_set_disabled(&principal, path.into_inner(), true, &client_info(&req).ip)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => admin_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{user_id}/enable",
+    tag = "admin",
+    params(("user_id" = u32, Path)),
+    responses(
+        (status = 200, description = "Enabled"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such user"),
+        (status = 409, description = "The admin's own account", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn enable_user(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<u32>,
+) -> impl Responder {
+    match data
+        .logic
+        .admin_set_disabled(&principal, path.into_inner(), false, &client_info(&req).ip)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => admin_error(err),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/users/{user_id}/logout",
+    tag = "admin",
+    params(("user_id" = u32, Path)),
+    responses(
+        (status = 200, description = "Every session of the user was revoked"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such user"),
+        (status = 409, description = "The admin's own account", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn logout_user(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<u32>,
+) -> impl Responder {
+    match data
+        .logic
+        .admin_logout(&principal, path.into_inner(), &client_info(&req).ip)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => admin_error(err),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/audit",
+    tag = "admin",
+    params(Page),
+    responses(
+        (status = 200, description = "The newest entries first", body = [AuditEntry]),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn audit_log(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    page: web::Query<Page>,
+) -> impl Responder {
+    match data
+        .logic
+        .audit_log(&principal, page.limit, page.offset, &client_info(&req).ip)
+        .await
+    {
+        Ok(entries) => HttpResponse::Ok().json(entries),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/admin/invites",
+    tag = "admin",
+    request_body = NewInvite,
+    responses(
+        (status = 201, description = "The code, which is only ever returned here", body = InviteCreated),
+        (status = 400, description = "The uses or expiry are invalid", body = AuthError),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin, or a missing or wrong CSRF token"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn create_invite(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<NewInvite>,
+) -> impl Responder {
+    if json.max_uses == 0 || json.expires_in_days == Some(0) {
+        return HttpResponse::BadRequest().json(AuthError {
+            error: "An invite code must be usable at least once and for at least one day",
+            reasons: Vec::new(),
+        });
+    }
+
+    match data
+        .logic
+        .admin_create_invite(
+            &principal,
+            json.max_uses,
+            json.expires_in_days,
+            &client_info(&req).ip,
+        )
+        .await
+    {
+        Ok((id, code)) => HttpResponse::Created().json(InviteCreated { id, code }),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/admin/invites",
+    tag = "admin",
+    responses(
+        (status = 200, description = "The invite codes", body = [InviteInfo]),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [])),
+)]
+pub async fn invites(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+) -> impl Responder {
+    match data
+        .logic
+        .admin_invites(&principal, &client_info(&req).ip)
+        .await
+    {
+        Ok(invites) => HttpResponse::Ok().json(invites),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    delete,
+    path = "/api/v1/admin/invites/{invite_id}",
+    tag = "admin",
+    params(("invite_id" = u32, Path)),
+    responses(
+        (status = 200, description = "Revoked"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "Not an admin, or a missing or wrong CSRF token"),
+        (status = 404, description = "No such invite"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = [])),
+)]
+pub async fn revoke_invite(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<u32>,
+) -> impl Responder {
+    match data
+        .logic
+        .admin_revoke_invite(&principal, path.into_inner(), &client_info(&req).ip)
+        .await
+    {
+        Ok(()) => HttpResponse::Ok().finish(),
+        Err(err) => admin_error(err),
+    }
+}
+
+fn admin_error(err: AdminError) -> HttpResponse {
+    match err {
+        AdminError::NotFound => HttpResponse::NotFound().finish(),
+        AdminError::OwnAccount => HttpResponse::Conflict().json(AuthError {
+            error: "Admins can't do this to their own account",
+            reasons: Vec::new(),
+        }),
+        AdminError::Internal => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// Todo
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/todo/",
+    tag = "todo",
+    params(ItemFilter),
+    responses(
+        (status = 200, description = "The caller's items", body = [TodoItem]),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "The token lacks the todo:read scope or is restricted to another list", body = AuthError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = []), ("token" = ["todo:read"])),
+)]
+pub async fn get_items(
+    principal: Principal,
+    data: web::Data<AppData>,
+    query: web::Query<ItemFilter>,
+) -> impl Responder {
+    let Ok(list_id) = principal.list(query.list_id) else {
+        return restricted_token("The token is restricted to another list");
     };
 
-    let Ok(user_id) = data.logic.validate(session).await else {
-        return HttpResponse::Unauthorized().finish();
+    match data.logic.get_items(principal.user_id, list_id).await {
+        Ok(items) => HttpResponse::Ok().json(items),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/todo/set",
+    tag = "todo",
+    request_body = NewTodoItem,
+    responses(
+        (status = 201, description = "The item was added"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "The token lacks the todo:write scope or is restricted to another list, or a missing or wrong CSRF token"),
+        (status = 422, description = "The content or list is invalid or the account has as many items as it is allowed", body = ValidationError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = []), ("token" = ["todo:write"])),
+)]
+pub async fn set_item(
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<NewTodoItem>,
+) -> impl Responder {
+    let Ok(list_id) = principal.list(json.list_id) else {
+        return restricted_token("The token is restricted to another list");
     };
 
+    match data
+        .logic
+        .add_item(principal.user_id, list_id, &json.content)
+        .await
+    {
+        Ok(_) => HttpResponse::Created().finish(),
+        Err(err) => todo_error(
+            err,
+            "The todo item is invalid",
+            "The account has as many todo items as it is allowed",
+        ),
+    }
+}
+
+#[utoipa::path(
+    patch,
+    path = "/api/v1/todo/update/{item_id}",
+    tag = "todo",
+    params(("item_id" = u32, Path)),
+    request_body = UpdateTodoItem,
+    responses(
+        (status = 200, description = "The item was updated"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "The token lacks the todo:write scope, or a missing or wrong CSRF token"),
+        (status = 404, description = "The user has no such item, or it isn't on the token's list"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = []), ("token" = ["todo:write"])),
+)]
+pub async fn update_item(
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<u32>,
+    json: web::Json<UpdateTodoItem>,
+) -> impl Responder {
     let item_id = path.into_inner();
 
-    match data.logic.update_item(user_id, item_id, json.done).await {
-        Ok(_) => return HttpResponse::Ok().finish(),
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+    match data
+        .logic
+        .update_item(principal.user_id, principal.list_id, item_id, json.done)
+        .await
+    {
+        Ok(Some(_)) => HttpResponse::Ok().finish(),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
     }
 }
 
+#[utoipa::path(
+    delete,
+    path = "/api/v1/todo/delete/{item_id}",
+    tag = "todo",
+    params(("item_id" = u32, Path)),
+    responses(
+        (status = 200, description = "The item was deleted"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "The token lacks the todo:write scope, or a missing or wrong CSRF token"),
+        (status = 404, description = "The user has no such item, or it isn't on the token's list"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = []), ("token" = ["todo:write"])),
+)]
 pub async fn delete_item(
-    req: HttpRequest,
+    principal: Principal,
     data: web::Data<AppData>,
     path: web::Path<u32>,
 ) -> impl Responder {
-    let Some(session) = req.cookie("sessionid") else {
-        return HttpResponse::Unauthorized().finish();
+    let item_id = path.into_inner();
+
+    match data
+        .logic
+        .delete_item(principal.user_id, principal.list_id, item_id)
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    get,
+    path = "/api/v1/todo/lists",
+    tag = "todo",
+    responses(
+        (status = 200, description = "The caller's lists, only the token's list when it is restricted to one", body = [TodoList]),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "The token lacks the todo:read scope"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = []), ("token" = ["todo:read"])),
+)]
+pub async fn get_lists(principal: Principal, data: web::Data<AppData>) -> impl Responder {
+    match data
+        .logic
+        .get_lists(principal.user_id, principal.list_id)
+        .await
+    {
+        Ok(lists) => HttpResponse::Ok().json(lists),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+#[utoipa::path(
+    post,
+    path = "/api/v1/todo/lists/set",
+    tag = "todo",
+    request_body = NewTodoList,
+    responses(
+        (status = 201, description = "The list was added", body = TodoList),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "The token lacks the todo:write scope or is restricted to a list, or a missing or wrong CSRF token"),
+        (status = 422, description = "The name is invalid or the account has as many lists as it is allowed", body = ValidationError),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = []), ("token" = ["todo:write"])),
+)]
+pub async fn set_list(
+    principal: Principal,
+    data: web::Data<AppData>,
+    json: web::Json<NewTodoList>,
+) -> impl Responder {
+    if principal.list_id.is_some() {
+        return restricted_token("A token restricted to a list can't manage lists");
+    }
+
+    match data.logic.add_list(principal.user_id, &json.name).await {
+        Ok(list) => HttpResponse::Created().json(list),
+        Err(err) => todo_error(
+            err,
+            "The todo list is invalid",
+            "The account has as many todo lists as it is allowed",
+        ),
+    }
+}
+
+/// The items on the list and the tokens restricted to it are deleted as
+/// well.
+#[utoipa::path(
+    delete,
+    path = "/api/v1/todo/lists/delete/{list_id}",
+    tag = "todo",
+    params(("list_id" = u32, Path)),
+    responses(
+        (status = 200, description = "The list was deleted"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "The token lacks the todo:write scope or is restricted to a list, or a missing or wrong CSRF token"),
+        (status = 404, description = "The user has no such list"),
+        (status = 500, description = "Something went wrong"),
+    ),
+    security(("session" = [], "csrf" = []), ("token" = ["todo:write"])),
+)]
+pub async fn delete_list(
+    principal: Principal,
+    data: web::Data<AppData>,
+    path: web::Path<u32>,
+) -> impl Responder {
+    if principal.list_id.is_some() {
+        return restricted_token("A token restricted to a list can't manage lists");
+    }
+
+    match data
+        .logic
+        .delete_list(principal.user_id, path.into_inner())
+        .await
+    {
+        Ok(true) => HttpResponse::Ok().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(_) => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+/// Tokens restricted to a list can't reach other lists or manage lists.
+fn restricted_token(error: &'static str) -> HttpResponse {
+    HttpResponse::Forbidden().json(AuthError {
+        error,
+        reasons: Vec::new(),
+    })
+}
+
+fn todo_error(err: TodoError, invalid: &'static str, quota_reached: &'static str) -> HttpResponse {
+    match err {
+        TodoError::Invalid(fields) => HttpResponse::UnprocessableEntity().json(ValidationError {
+            error: invalid,
+            fields,
+        }),
+        TodoError::QuotaReached => HttpResponse::UnprocessableEntity().json(ValidationError {
+            error: quota_reached,
+            fields: Vec::new(),
+        }),
+        TodoError::Internal => HttpResponse::InternalServerError().finish(),
+    }
+}
+
+// GraphQL
+
+#[utoipa::path(
+    post,
+    path = "/graphql",
+    tag = "graphql",
+    request_body(content = Object, description = "A GraphQL request with `query`, and optionally `operationName` and `variables`"),
+    responses(
+        (status = 200, description = "The result, with what went wrong in `errors`", body = Object),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "A missing or wrong CSRF token"),
+    ),
+    security(("session" = [], "csrf" = []), ("token" = [])),
+)]
+pub async fn graphql(
+    principal: Principal,
+    schema: web::Data<TodoSchema>,
+    json: web::Json<async_graphql::Request>,
+) -> impl Responder {
+    let request = json.into_inner().data(principal);
+
+    HttpResponse::Ok().json(schema.execute(request).await)
+}
+
+/// Subscriptions, over a WebSocket.
+#[utoipa::path(
+    get,
+    path = "/graphql",
+    tag = "graphql",
+    responses(
+        (status = 101, description = "Switched to a WebSocket speaking graphql-transport-ws or graphql-ws"),
+        (status = 400, description = "Not a WebSocket handshake, or none of the protocols is spoken"),
+        (status = 401, description = "Not authenticated"),
+        (status = 403, description = "From a foreign origin"),
+    ),
+    security(("session" = []), ("token" = [])),
+)]
+pub async fn graphql_subscriptions(
+    req: HttpRequest,
+    principal: Principal,
+    data: web::Data<AppData>,
+    schema: web::Data<TodoSchema>,
+    payload: web::Payload,
+) -> Result<HttpResponse, Error> {
+    let Some(credential) = auth::credential(&req) else {
+        return Ok(HttpResponse::Unauthorized().finish());
     };
 
-    let Ok(user_id) = data.logic.validate(session).await else {
-        return HttpResponse::Unauthorized().finish();
+    graphql::serve_websocket(
+        &req,
+        payload,
+        schema.get_ref().clone(),
+        principal,
+        credential,
+        &data.shutdown,
+    )
+}
+
+/// Rejects JSON bodies which are too large with 413, which aren't JSON with
+/// 400 or 415 and whose values don't fit the request with 422.
+pub fn json_error(err: JsonPayloadError, _: &HttpRequest) -> Error {
+    let response = match &err {
+        JsonPayloadError::OverflowKnownLength { .. } | JsonPayloadError::Overflow { .. } => {
+            HttpResponse::PayloadTooLarge().json(ValidationError {
+                error: "The request body is too large",
+                fields: Vec::new(),
+            })
+        }
+        JsonPayloadError::ContentType => {
+            HttpResponse::UnsupportedMediaType().json(ValidationError {
+                error: "The request body must be application/json",
+                fields: Vec::new(),
+            })
+        }
+        JsonPayloadError::Deserialize(err) if err.is_data() => {
+            let message = err.to_string();
+            // serde_json only names the field for missing and unknown ones.
+            let field = ["missing field `", "unknown field `"]
+                .iter()
+                .find_map(|prefix| message.strip_prefix(prefix))
+                .and_then(|rest| rest.split_once('`'))
+                .map_or("body", |(field, _)| field);
+
+            HttpResponse::UnprocessableEntity().json(ValidationError {
+                error: "The request body doesn't match what was expected",
+                fields: vec![FieldError::new(field, "invalid", message.clone())],
+            })
+        }
+        _ => HttpResponse::BadRequest().json(ValidationError {
+            error: "The request body isn't valid JSON",
+            fields: Vec::new(),
+        }),
     };
 
-    let item_id = path.into_inner();
+    InternalError::from_response(err, response).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service, read_body_json};
+    use actix_web::{App, HttpResponse, web};
+    use serde_json::Value as JsonValue;
+
+    use super::{NewTodoItem, json_error};
+
+    #[actix_web::test]
+    async fn bad_bodies_are_answered_with_json() {
+        let app = init_service(
+            App::new()
+                .app_data(
+                    web::JsonConfig::default()
+                        .limit(64)
+                        .error_handler(json_error),
+                )
+                .route(
+                    "/",
+                    web::post()
+                        .to(|_: web::Json<NewTodoItem>| async { HttpResponse::Ok().finish() }),
+                ),
+        )
+        .await;
+
+        let post = |body: String| {
+            TestRequest::post()
+                .uri("/")
+                .insert_header(("Content-Type", "application/json"))
+                .set_payload(body)
+                .to_request()
+        };
+
+        let res = call_service(&app, post(String::from(r#"{"content":"Buy milk"}"#))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+
+        let res = call_service(&app, post(format!(r#"{{"content":"{}"}}"#, "a".repeat(64)))).await;
+        assert_eq!(res.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let res = call_service(&app, post(String::from("{"))).await;
+        assert_eq!(res.status(), StatusCode::BAD_REQUEST);
+
+        let res = call_service(&app, post(String::from(r#"{"contents":"Buy milk"}"#))).await;
+        assert_eq!(res.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        let body: JsonValue = read_body_json(res).await;
+        assert_eq!(body["fields"][0]["field"], "content");
 
-    match data.logic.delete_item(user_id, item_id).await {
-        Ok(_) => return HttpResponse::Ok().finish(),
-        Err(_) => return HttpResponse::InternalServerError().finish(),
+        let req = TestRequest::post()
+            .uri("/")
+            .insert_header(("Content-Type", "text/plain"))
+            .set_payload(r#"{"content":"Buy milk"}"#)
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
     }
 }
@@ -0,0 +1,77 @@
+//! Counts and times every request by method, route pattern and status.
+//! Requests which didn't match a route are counted under `unmatched`, so
+//! scanners can't create a series per path.
+
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Instant;
+
+use actix_web::Error;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+
+use crate::metrics::METRICS;
+
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let started = Instant::now();
+        let method = req.method().to_string();
+
+        Box::pin(async move {
+            let result = service.call(req).await;
+
+            let (route, status) = match &result {
+                Ok(res) => (res.request().match_pattern(), res.status()),
+                Err(err) => (None, err.as_response_error().status_code()),
+            };
+            let labels = [
+                method.as_str(),
+                route.as_deref().unwrap_or("unmatched"),
+                status.as_str(),
+            ];
+
+            METRICS.http_requests.with_label_values(&labels).inc();
+            METRICS
+                .http_request_duration
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+
+            result
+        })
+    }
+}
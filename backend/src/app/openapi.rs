@@ -0,0 +1,325 @@
+//! The OpenAPI 3 document of the API, served on `/openapi.json`.
+//!
+//! It is generated from the `#[utoipa::path]` attributes on the handlers and
+//! the types they take and return. With the `docs-ui` feature and
+//! `docs.ui` turned on, Swagger UI is served on `/docs/`.
+
+use std::sync::LazyLock;
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, HttpAuthScheme, HttpBuilder, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::app::handlers;
+
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Todo App API",
+        description = "Requests which change something with the session cookie need the \
+            session's CSRF token in `X-CSRF-Token`. Personal access tokens are sent as bearer \
+            tokens and only reach the `/todo` routes their scopes allow. Every response can \
+            also be 429 once the rate limit is reached.\n\nThe API is versioned by its path \
+            prefix. Its paths without `/api/v1` still answer until their `Sunset` date. \
+            Clients can send `Accept: application/vnd.todo.v1+json` to get 406 rather than \
+            another version's shapes.",
+    ),
+    paths(
+        handlers::root,
+        handlers::healthz,
+        handlers::readyz,
+        handlers::version,
+        handlers::openapi_spec,
+        handlers::metrics,
+        handlers::info,
+        handlers::export_data,
+        handlers::request_account_deletion,
+        handlers::cancel_account_deletion,
+        handlers::delete_account,
+        handlers::sessions,
+        handlers::revoke_other_sessions,
+        handlers::revoke_session,
+        handlers::access_tokens,
+        handlers::create_access_token,
+        handlers::revoke_access_token,
+        handlers::github_init,
+        handlers::github_success,
+        handlers::local_register,
+        handlers::local_login,
+        handlers::change_password,
+        handlers::webauthn_login_init,
+        handlers::webauthn_login_finish,
+        handlers::webauthn_register_init,
+        handlers::webauthn_register_finish,
+        handlers::webauthn_credentials,
+        handlers::rename_webauthn_credential,
+        handlers::delete_webauthn_credential,
+        handlers::totp_verify,
+        handlers::totp_enroll,
+        handlers::totp_enable,
+        handlers::totp_recovery_codes,
+        handlers::totp_disable,
+        handlers::csrf_token,
+        handlers::logout,
+        handlers::admin_users,
+        handlers::disable_user,
+        handlers::enable_user,
+        handlers::logout_user,
+        handlers::invites,
+        handlers::create_invite,
+        handlers::revoke_invite,
+        handlers::admin_stats,
+        handlers::audit_log,
+        handlers::get_items,
+        handlers::set_item,
+        handlers::update_item,
+        handlers::delete_item,
+        handlers::get_lists,
+        handlers::set_list,
+        handlers::delete_list,
+        handlers::graphql,
+        handlers::graphql_subscriptions,
+    ),
+    modifiers(&SecuritySchemes),
+    tags(
+        (name = "meta", description = "Probes, metrics and this document"),
+        (name = "user", description = "The caller's account"),
+        (name = "account", description = "Exporting and deleting the account"),
+        (name = "sessions", description = "The caller's sessions"),
+        (name = "tokens", description = "Personal access tokens"),
+        (name = "auth", description = "Signing in with GitHub or a password"),
+        (name = "webauthn", description = "Passkeys"),
+        (name = "totp", description = "Two-factor authentication"),
+        (name = "admin", description = "Managing users and invites, for admins"),
+        (name = "todo", description = "Todo items and lists"),
+        (name = "graphql", description = "The same data as GraphQL, with subscriptions"),
+    ),
+)]
+pub struct ApiDoc;
+
+struct SecuritySchemes;
+
+impl Modify for SecuritySchemes {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+
+        components.add_security_scheme(
+            "session",
+            SecurityScheme::ApiKey(ApiKey::Cookie(ApiKeyValue::new("sessionid"))),
+        );
+        components.add_security_scheme(
+            "csrf",
+            SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::new("X-CSRF-Token"))),
+        );
+        components.add_security_scheme(
+            "token",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+        components.add_security_scheme(
+            "metrics",
+            SecurityScheme::Http(HttpBuilder::new().scheme(HttpAuthScheme::Bearer).build()),
+        );
+    }
+}
+
+/// Generated once, it doesn't change while the server runs.
+pub fn spec() -> &'static str {
+    static SPEC: LazyLock<String> = LazyLock::new(|| {
+        ApiDoc::openapi()
+            .to_json()
+            .expect("The document serializes")
+    });
+
+    &SPEC
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::BTreeSet;
+    use std::rc::Rc;
+
+    use actix_web::dev::{ResourceMap, Service, ServiceResponse};
+    use actix_web::http::{Method, StatusCode};
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, Error, HttpMessage};
+    use utoipa::OpenApi;
+
+    use super::ApiDoc;
+    use crate::app::core::routes;
+    use crate::{Principal, Role, Scope};
+
+    const PATTERN_HEADER: &str = "x-matched-pattern";
+    const METHODS: [Method; 5] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::PATCH,
+        Method::DELETE,
+    ];
+
+    /// Every route with the pattern it matched in a header. The caller
+    /// meets every requirement, so a method which isn't routed gets 405
+    /// rather than 401. The resource map is kept from the first request.
+    async fn app(
+        resource_map: Rc<RefCell<Option<ResourceMap>>>,
+    ) -> impl Service<actix_http::Request, Response = ServiceResponse, Error = Error> {
+        init_service(
+            App::new()
+                .wrap_fn(move |req, service| {
+                    resource_map
+                        .borrow_mut()
+                        .get_or_insert_with(|| req.resource_map().clone());
+                    req.extensions_mut().insert(Principal {
+                        user_id: 1,
+                        roles: vec![Role::User, Role::Admin],
+                        scopes: Scope::ALL.to_vec(),
+                        session: Some(String::from("session")),
+                        token_id: None,
+                        list_id: None,
+                    });
+
+                    let pattern = req.match_pattern().unwrap_or_default();
+                    let response = service.call(req);
+
+                    async move {
+                        let mut res = response.await?;
+                        res.headers_mut()
+                            .insert(PATTERN_HEADER.try_into().unwrap(), pattern.parse().unwrap());
+                        Ok(res)
+                    }
+                })
+                .configure(|config| routes(config, false, false, true, None)),
+        )
+        .await
+    }
+
+    /// Whether `method` on `pattern` reaches a route registered for exactly
+    /// that pattern.
+    async fn is_routed(
+        app: &impl Service<actix_http::Request, Response = ServiceResponse, Error = Error>,
+        method: &Method,
+        pattern: &str,
+    ) -> bool {
+        let uri = pattern
+            .split('/')
+            .map(|segment| {
+                if segment.starts_with('{') {
+                    "1"
+                } else {
+                    segment
+                }
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+
+        let res = call_service(
+            app,
+            TestRequest::default()
+                .method(method.clone())
+                .uri(&uri)
+                .to_request(),
+        )
+        .await;
+
+        !matches!(
+            res.status(),
+            StatusCode::NOT_FOUND | StatusCode::METHOD_NOT_ALLOWED
+        ) && res.headers().get(PATTERN_HEADER).unwrap() == pattern
+    }
+
+    /// The full patterns of the resources in the map. actix has no way to
+    /// list them, so its debug output is walked: every node prints its
+    /// pattern and then its children, indented one level deeper, or
+    /// `nodes: None` when it is a resource.
+    fn resource_patterns(resource_map: &ResourceMap) -> BTreeSet<String> {
+        let debug = format!("{:#?}", resource_map);
+        let mut lines = debug.lines();
+        let mut parents: Vec<(usize, String)> = Vec::new();
+        let mut node: Option<(usize, String)> = None;
+        let mut patterns = BTreeSet::new();
+
+        while let Some(line) = lines.next() {
+            let indent = line.len() - line.trim_start().len();
+
+            match line.trim() {
+                "pattern: ResourceDef {" => {
+                    parents.retain(|(parent, _)| *parent < indent);
+                    node = Some((indent, String::new()));
+                }
+                "patterns: Single(" => {
+                    let pattern = lines.next().unwrap().trim().trim_end_matches(',');
+                    node.as_mut().unwrap().1 = pattern.trim_matches('"').to_string();
+                }
+                "nodes: Some(" => parents.push(node.take().unwrap()),
+                "nodes: None," => {
+                    let (_, pattern) = node.take().unwrap();
+                    let prefix: String =
+                        parents.iter().map(|(_, parent)| parent.as_str()).collect();
+                    patterns.insert(prefix + &pattern);
+                }
+                _ => {}
+            }
+        }
+
+        patterns
+    }
+
+    fn documented() -> BTreeSet<(String, String)> {
+        ApiDoc::openapi()
+            .paths
+            .paths
+            .into_iter()
+            .flat_map(|(path, item)| {
+                [
+                    (Method::GET, item.get),
+                    (Method::POST, item.post),
+                    (Method::PUT, item.put),
+                    (Method::PATCH, item.patch),
+                    (Method::DELETE, item.delete),
+                ]
+                .into_iter()
+                .filter(|(_, operation)| operation.is_some())
+                .map(move |(method, _)| (method.to_string(), path.clone()))
+            })
+            .collect()
+    }
+
+    /// Every documented operation reaches a route with the same pattern and
+    /// method.
+    #[actix_web::test]
+    async fn documented_operations_are_routed() {
+        let app = app(Rc::default()).await;
+
+        for (method, path) in documented() {
+            assert!(
+                is_routed(&app, &method.parse().unwrap(), &path).await,
+                "{} {} is documented but not routed",
+                method,
+                path
+            );
+        }
+    }
+
+    /// The (method, path) pairs `routes` registers are exactly the
+    /// documented ones.
+    #[actix_web::test]
+    async fn registered_routes_are_documented() {
+        let resource_map = Rc::default();
+        let app = app(Rc::clone(&resource_map)).await;
+
+        call_service(&app, TestRequest::get().uri("/").to_request()).await;
+        let patterns = resource_patterns(resource_map.borrow().as_ref().unwrap());
+        assert!(patterns.contains("/api/v1/todo/"));
+
+        let mut registered = BTreeSet::new();
+        for pattern in &patterns {
+            for method in &METHODS {
+                if is_routed(&app, method, pattern).await {
+                    registered.insert((method.to_string(), pattern.clone()));
+                }
+            }
+        }
+
+        assert_eq!(registered, documented());
+    }
+}
@@ -0,0 +1,457 @@
+//! Rate limiting with token buckets.
+//!
+//! Sign-in endpoints, everything under `/user/auth/` with or without the
+//! version prefix, have a budget of
+//! their own per IP address. Other requests are counted per user for
+//! sessions, per personal access token, or per IP address when the caller
+//! is anonymous. Every limited response carries `RateLimit-Limit`,
+//! `RateLimit-Remaining` and `RateLimit-Reset`, a rejected one `Retry-After`
+//! as well.
+//!
+//! The buckets live in a [`RateLimitStore`]. [`MemoryStore`] keeps them in
+//! the process, which is enough for a single instance.
+
+use std::collections::HashMap;
+use std::future::{Future, Ready, ready};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::{Error, HttpResponse};
+use tracing::{debug, error};
+
+use crate::app::versioning::API_V1;
+use crate::app::{app_data, auth};
+use crate::config::{RateBudget, RateLimitConfig};
+use crate::metrics::METRICS;
+
+/// Probes and scrapes, which are never limited.
+const EXEMPT_PATHS: &[&str] = &["/healthz", "/readyz", "/metrics"];
+
+const AUTH_PREFIX: &str = "/user/auth/";
+
+/// How often the memory store drops the buckets which refilled.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// What taking a token from a bucket resulted in.
+pub struct Decision {
+    pub allowed: bool,
+    /// Whole tokens left in the bucket.
+    pub remaining: u32,
+    /// Seconds until the bucket is full again.
+    pub reset_secs: u64,
+    /// Seconds until a token is available, 0 when one was taken.
+    pub retry_after_secs: u64,
+}
+
+/// Where the buckets are kept. Returns a future so a store shared between
+/// instances can be asynchronous, an error lets the request through.
+pub trait RateLimitStore: Send + Sync {
+    fn acquire<'a>(
+        &'a self,
+        key: &'a str,
+        budget: RateBudget,
+    ) -> Pin<Box<dyn Future<Output = Result<Decision, ()>> + Send + 'a>>;
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket is full again if nothing is taken from it.
+    full_at: Instant,
+}
+
+/// Buckets in the process, lost on restart and not shared between
+/// instances.
+pub struct MemoryStore {
+    buckets: Mutex<HashMap<String, Bucket>>,
+    last_sweep: Mutex<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore {
+            buckets: Mutex::new(HashMap::new()),
+            last_sweep: Mutex::new(Instant::now()),
+        }
+    }
+
+    fn take(&self, key: &str, budget: RateBudget, now: Instant) -> Decision {
+        let capacity = f64::from(budget.burst);
+        let rate = f64::from(budget.per_minute) / 60.0;
+
+        let mut buckets = self.buckets.lock().unwrap();
+        self.sweep(&mut buckets, now);
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            full_at: now,
+        });
+
+        bucket.tokens =
+            (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(capacity);
+        bucket.updated = now;
+
+        let allowed = bucket.tokens >= 1.0;
+        if allowed {
+            bucket.tokens -= 1.0;
+        }
+
+        let reset = (capacity - bucket.tokens) / rate;
+        bucket.full_at = now + Duration::from_secs_f64(reset);
+
+        Decision {
+            allowed,
+            remaining: bucket.tokens as u32,
+            reset_secs: reset.ceil() as u64,
+            retry_after_secs: if allowed {
+                0
+            } else {
+                ((1.0 - bucket.tokens) / rate).ceil() as u64
+            },
+        }
+    }
+
+    /// A full bucket is the same as a missing one.
+    fn sweep(&self, buckets: &mut HashMap<String, Bucket>, now: Instant) {
+        let mut last_sweep = self.last_sweep.lock().unwrap();
+        if now.duration_since(*last_sweep) < SWEEP_INTERVAL {
+            return;
+        }
+
+        buckets.retain(|_, bucket| bucket.full_at > now);
+        *last_sweep = now;
+    }
+}
+
+impl RateLimitStore for MemoryStore {
+    fn acquire<'a>(
+        &'a self,
+        key: &'a str,
+        budget: RateBudget,
+    ) -> Pin<Box<dyn Future<Output = Result<Decision, ()>> + Send + 'a>> {
+        Box::pin(ready(Ok(self.take(key, budget, Instant::now()))))
+    }
+}
+
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    store: Box<dyn RateLimitStore>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig, store: Box<dyn RateLimitStore>) -> Self {
+        RateLimiter { config, store }
+    }
+}
+
+pub struct RateLimit {
+    limiter: Arc<RateLimiter>,
+}
+
+impl RateLimit {
+    pub fn new(limiter: Arc<RateLimiter>) -> Self {
+        RateLimit { limiter }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimit
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = RateLimitMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimitMiddleware {
+            service: Rc::new(service),
+            limiter: self.limiter.clone(),
+        }))
+    }
+}
+
+pub struct RateLimitMiddleware<S> {
+    service: Rc<S>,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimitMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            if EXEMPT_PATHS.contains(&req.path()) {
+                return service
+                    .call(req)
+                    .await
+                    .map(ServiceResponse::map_into_left_body);
+            }
+
+            let ip = client_bucket(&req, limiter.config.trust_proxy);
+            let unversioned = req.path().strip_prefix(API_V1).unwrap_or(req.path());
+            let (name, budget, key) = if unversioned.starts_with(AUTH_PREFIX) {
+                ("auth", limiter.config.auth, format!("auth:ip:{}", ip))
+            } else {
+                match auth::resolve(req.request()).await {
+                    Some(principal) => (
+                        "authenticated",
+                        limiter.config.authenticated,
+                        match principal.token_id {
+                            Some(token_id) => format!("token:{}", token_id),
+                            None => format!("user:{}", principal.user_id),
+                        },
+                    ),
+                    None => ("anonymous", limiter.config.anonymous, format!("ip:{}", ip)),
+                }
+            };
+
+            let decision = match limiter.store.acquire(&key, budget).await {
+                Ok(decision) => decision,
+                Err(()) => {
+                    error!("Can't reach the rate limit store, letting the request through");
+                    return service
+                        .call(req)
+                        .await
+                        .map(ServiceResponse::map_into_left_body);
+                }
+            };
+
+            if !decision.allowed {
+                // Counted in the metrics, logging each would flood the logs.
+                debug!(budget = name, key, "Rate limited a request");
+                METRICS.rate_limited.with_label_values(&[name]).inc();
+
+                let mut res = HttpResponse::TooManyRequests().finish();
+                insert_headers(res.headers_mut(), &budget, &decision);
+                return Ok(req.into_response(res).map_into_right_body());
+            }
+
+            let mut res = service.call(req).await?;
+            insert_headers(res.headers_mut(), &budget, &decision);
+            Ok(res.map_into_left_body())
+        })
+    }
+}
+
+fn insert_headers(headers: &mut HeaderMap, budget: &RateBudget, decision: &Decision) {
+    let mut insert = |name: &'static str, value: u64| {
+        headers.insert(HeaderName::from_static(name), HeaderValue::from(value));
+    };
+
+    insert("ratelimit-limit", u64::from(budget.burst));
+    insert("ratelimit-remaining", u64::from(decision.remaining));
+    insert("ratelimit-reset", decision.reset_secs);
+    if !decision.allowed {
+        insert("retry-after", decision.retry_after_secs);
+    }
+}
+
+/// IPv6 clients are counted per /64, which a single host usually has to
+/// itself.
+fn client_bucket(req: &ServiceRequest, trust_proxy: bool) -> String {
+    match app_data::client_ip(req.request(), trust_proxy) {
+        Some(IpAddr::V6(ip)) => {
+            let segments = ip.segments();
+            format!(
+                "{:x}:{:x}:{:x}:{:x}::/64",
+                segments[0], segments[1], segments[2], segments[3]
+            )
+        }
+        Some(IpAddr::V4(ip)) => ip.to_string(),
+        None => String::from("unknown"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use actix_web::dev::Service;
+    use actix_web::http::StatusCode;
+    use actix_web::http::header::HeaderMap;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpMessage, HttpResponse, web};
+
+    use super::{MemoryStore, RateLimit, RateLimiter, client_bucket};
+    use crate::config::{RateBudget, RateLimitConfig};
+    use crate::{Principal, Role, Scope};
+
+    const BUDGET: RateBudget = RateBudget {
+        burst: 2,
+        per_minute: 60,
+    };
+
+    #[test]
+    fn buckets_refill_over_time() {
+        let store = MemoryStore::new();
+        let now = Instant::now();
+
+        let first = store.take("key", BUDGET, now);
+        assert!(first.allowed);
+        assert_eq!(first.remaining, 1);
+        assert_eq!(first.reset_secs, 1);
+        assert!(store.take("key", BUDGET, now).allowed);
+
+        let denied = store.take("key", BUDGET, now);
+        assert!(!denied.allowed);
+        assert_eq!(denied.remaining, 0);
+        assert_eq!(denied.retry_after_secs, 1);
+        assert_eq!(denied.reset_secs, 2);
+        assert!(store.take("other", BUDGET, now).allowed);
+
+        assert!(
+            store
+                .take("key", BUDGET, now + Duration::from_secs(1))
+                .allowed
+        );
+        let full = store.take("key", BUDGET, now + Duration::from_secs(60));
+        assert_eq!(full.remaining, 1);
+    }
+
+    fn bucket(peer: &str, forwarded_for: Option<&str>, trust_proxy: bool) -> String {
+        let mut req = TestRequest::default().peer_addr(peer.parse::<SocketAddr>().unwrap());
+        if let Some(forwarded_for) = forwarded_for {
+            req = req.insert_header(("X-Forwarded-For", forwarded_for));
+        }
+
+        client_bucket(&req.to_srv_request(), trust_proxy)
+    }
+
+    #[test]
+    fn clients_are_bucketed_by_address() {
+        assert_eq!(bucket("192.0.2.1:1234", None, false), "192.0.2.1");
+        assert_eq!(
+            bucket("[2001:db8:1:2:3:4:5:6]:1234", None, false),
+            "2001:db8:1:2::/64"
+        );
+        assert_eq!(bucket("[::ffff:192.0.2.1]:1234", None, false), "192.0.2.1");
+
+        assert_eq!(
+            bucket("192.0.2.1:1234", Some("198.51.100.7"), false),
+            "192.0.2.1"
+        );
+        assert_eq!(
+            bucket("192.0.2.1:1234", Some("198.51.100.7, 192.0.2.1"), true),
+            "198.51.100.7"
+        );
+        assert_eq!(
+            bucket("192.0.2.1:1234", Some("2001:db8::1"), true),
+            "2001:db8:0:0::/64"
+        );
+    }
+
+    #[actix_web::test]
+    async fn limited_responses_carry_the_headers() {
+        let config = RateLimitConfig {
+            anonymous: RateBudget {
+                burst: 1,
+                per_minute: 1,
+            },
+            authenticated: BUDGET,
+            auth: RateBudget {
+                burst: 1,
+                per_minute: 1,
+            },
+            ..RateLimitConfig::default()
+        };
+        let limiter = Arc::new(RateLimiter::new(config, Box::new(MemoryStore::new())));
+
+        let app = init_service(
+            App::new()
+                .wrap(RateLimit::new(limiter))
+                .wrap_fn(|req, service| {
+                    if let Some(user_id) = req
+                        .headers()
+                        .get("x-user")
+                        .and_then(|user| user.to_str().ok()?.parse().ok())
+                    {
+                        req.extensions_mut().insert(Principal {
+                            user_id,
+                            roles: vec![Role::User],
+                            scopes: Scope::ALL.to_vec(),
+                            session: Some(String::from("session")),
+                            token_id: None,
+                            list_id: None,
+                        });
+                    }
+                    service.call(req)
+                })
+                .default_service(web::to(HttpResponse::Ok)),
+        )
+        .await;
+
+        let get = |path: &str, user: Option<&str>| {
+            let mut req = TestRequest::get()
+                .uri(path)
+                .peer_addr("192.0.2.1:1234".parse().unwrap());
+            if let Some(user) = user {
+                req = req.insert_header(("x-user", user));
+            }
+            req.to_request()
+        };
+        let header = |headers: &HeaderMap, name: &str| {
+            headers
+                .get(name)
+                .map(|value| value.to_str().unwrap().to_string())
+        };
+
+        let res = call_service(&app, get("/api/v1/todo/", None)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            header(res.headers(), "ratelimit-limit").as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            header(res.headers(), "ratelimit-remaining").as_deref(),
+            Some("0")
+        );
+        assert_eq!(header(res.headers(), "retry-after"), None);
+
+        let res = call_service(&app, get("/api/v1/todo/", None)).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(header(res.headers(), "retry-after").as_deref(), Some("60"));
+
+        // Probes, sign-ins and signed-in users have budgets of their own.
+        let res = call_service(&app, get("/healthz", None)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(header(res.headers(), "ratelimit-limit"), None);
+        let res = call_service(&app, get("/api/v1/user/auth/csrf", None)).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let res = call_service(&app, get("/user/auth/csrf", None)).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        for _ in 0..2 {
+            let res = call_service(&app, get("/api/v1/todo/", Some("1"))).await;
+            assert_eq!(res.status(), StatusCode::OK);
+            assert_eq!(
+                header(res.headers(), "ratelimit-limit").as_deref(),
+                Some("2")
+            );
+        }
+        let res = call_service(&app, get("/api/v1/todo/", Some("2"))).await;
+        assert_eq!(res.status(), StatusCode::OK);
+    }
+}
@@ -0,0 +1,196 @@
+//! Gives every request an id and runs it in a `request` span.
+//!
+//! An `X-Request-Id` sent by a proxy in front of the API is kept when it
+//! looks like an id, otherwise one is generated. Either way it is returned
+//! in the response's `X-Request-Id` header. The span carries the id, the
+//! method, the route pattern and, once the caller is resolved, the user id.
+//!
+//! Headers and query strings are only logged at the debug level, with the
+//! values of credentials replaced.
+
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::Instant;
+
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::header::{HeaderMap, HeaderName, HeaderValue};
+use actix_web::{Error, web};
+use tracing::{Instrument, debug, field, info, info_span};
+
+use crate::app::AppData;
+use crate::telemetry;
+
+const HEADER: &str = "x-request-id";
+const MAX_LENGTH: usize = 128;
+
+/// Headers whose values are never logged.
+const SENSITIVE_HEADERS: &[&str] = &[
+    "authorization",
+    "cookie",
+    "set-cookie",
+    "x-csrf-token",
+    "proxy-authorization",
+];
+
+/// Query parameters whose values are never logged.
+const SENSITIVE_PARAMETERS: &[&str] = &["code", "state", "token", "access_token", "csrf_token"];
+
+/// Requests which are answered at the debug level, probes would drown out
+/// everything else.
+const QUIET_PATHS: &[&str] = &["/healthz", "/readyz", "/metrics"];
+
+pub struct RequestTracing;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestTracing
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestTracingMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestTracingMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestTracingMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestTracingMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        let started = Instant::now();
+
+        let request_id = req
+            .headers()
+            .get(HEADER)
+            .and_then(|request_id| request_id.to_str().ok())
+            .filter(|request_id| is_valid(request_id))
+            .map(String::from)
+            .unwrap_or_else(|| format!("{:032x}", rand::random::<u128>()));
+
+        let route = req.match_pattern();
+        let span = info_span!(
+            "request",
+            request_id = %request_id,
+            method = %req.method(),
+            route = route.as_deref().unwrap_or("unmatched"),
+            user_id = field::Empty,
+            otel.name = field::Empty,
+            otel.kind = field::Empty,
+        );
+        telemetry::continue_trace(
+            &span,
+            req.headers(),
+            format!(
+                "{} {}",
+                req.method(),
+                route.as_deref().unwrap_or("unmatched")
+            ),
+        );
+        let quiet = QUIET_PATHS.contains(&req.path());
+
+        span.in_scope(|| {
+            debug!(
+                path = req.path(),
+                query = %redact_query(req.query_string()),
+                headers = %redact_headers(req.headers()),
+                "Received a request"
+            );
+        });
+
+        Box::pin(
+            async move {
+                let result = service.call(req).await;
+
+                let (status, ip) = match &result {
+                    Ok(res) => (
+                        res.status(),
+                        res.request()
+                            .app_data::<web::Data<AppData>>()
+                            .and_then(|data| data.client_ip(res.request()))
+                            .unwrap_or_else(|| String::from("unknown")),
+                    ),
+                    // Turned into a response by actix later, without the
+                    // header.
+                    Err(err) => (
+                        err.as_response_error().status_code(),
+                        String::from("unknown"),
+                    ),
+                };
+                let status = status.as_u16();
+                let elapsed_ms = started.elapsed().as_secs_f64() * 1000.0;
+                if quiet {
+                    debug!(status, elapsed_ms, ip, "Handled the request");
+                } else {
+                    info!(status, elapsed_ms, ip, "Handled the request");
+                }
+
+                let mut res = result?;
+                if let Ok(value) = HeaderValue::from_str(&request_id) {
+                    res.headers_mut()
+                        .insert(HeaderName::from_static(HEADER), value);
+                }
+
+                Ok(res)
+            }
+            .instrument(span),
+        )
+    }
+}
+
+/// Short enough to log and without characters which could forge log lines.
+fn is_valid(request_id: &str) -> bool {
+    !request_id.is_empty()
+        && request_id.len() <= MAX_LENGTH
+        && request_id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.'))
+}
+
+fn redact_headers(headers: &HeaderMap) -> String {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if SENSITIVE_HEADERS.contains(&name.as_str()) {
+                "[redacted]"
+            } else {
+                value.to_str().unwrap_or("[binary]")
+            };
+            format!("{}: {}", name, value)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn redact_query(query: &str) -> String {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| match pair.split_once('=') {
+            Some((name, _)) if SENSITIVE_PARAMETERS.contains(&name) => {
+                format!("{}=[redacted]", name)
+            }
+            _ => pair.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
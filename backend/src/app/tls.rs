@@ -0,0 +1,182 @@
+//! ```sh
+//! TLS_CERT_PATH=cert.pem TLS_KEY_PATH=key.pem TLS_REDIRECT_PORT=80 make run_release
+//! ```
+//!
+//! The certificate is served through a resolver whose key can be swapped,
+//! so renewed certificates are picked up without dropping connections. The
+//! files are reloaded on `SIGHUP` and whenever their modification time
+//! changes. A pair which fails to load is logged and the previous one is
+//! kept.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use rustls::ServerConfig;
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::{error, info};
+
+use crate::config::TlsConfig;
+
+#[derive(Debug)]
+struct Resolver(RwLock<Arc<CertifiedKey>>);
+
+impl ResolvesServerCert for Resolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.read().unwrap().clone())
+    }
+}
+
+pub struct Tls {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    reload_interval: Duration,
+    resolver: Arc<Resolver>,
+    /// The modification times of the loaded files.
+    modified: RwLock<(Option<SystemTime>, Option<SystemTime>)>,
+}
+
+impl Tls {
+    /// `None` when TLS isn't configured. The configuration has to be
+    /// validated already.
+    pub fn load(config: &TlsConfig) -> Result<Option<Self>, ()> {
+        let (Some(cert_path), Some(key_path)) = (&config.cert_path, &config.key_path) else {
+            return Ok(None);
+        };
+
+        let modified = (modified(cert_path), modified(key_path));
+
+        Ok(Some(Tls {
+            resolver: Arc::new(Resolver(RwLock::new(Arc::new(certified_key(
+                cert_path, key_path,
+            )?)))),
+            cert_path: cert_path.clone(),
+            key_path: key_path.clone(),
+            reload_interval: Duration::from_secs(config.reload_interval_secs),
+            modified: RwLock::new(modified),
+        }))
+    }
+
+    pub fn server_config(&self) -> ServerConfig {
+        ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("The ring provider supports the default protocol versions")
+            .with_no_client_auth()
+            .with_cert_resolver(self.resolver.clone())
+    }
+
+    /// Loads the files again, keeping the current certificate when they
+    /// can't be loaded.
+    fn reload(&self) {
+        let modified = (modified(&self.cert_path), modified(&self.key_path));
+
+        if let Ok(key) = certified_key(&self.cert_path, &self.key_path) {
+            *self.resolver.0.write().unwrap() = Arc::new(key);
+            info!("Reloaded the TLS certificate");
+        } else {
+            error!("Keeping the current TLS certificate");
+        }
+
+        // Also after a failure, so a half written pair is only retried once
+        // it changes again.
+        *self.modified.write().unwrap() = modified;
+    }
+
+    fn changed(&self) -> bool {
+        *self.modified.read().unwrap() != (modified(&self.cert_path), modified(&self.key_path))
+    }
+
+    /// Reloads the certificate on `SIGHUP` or when the files change, until
+    /// `shutdown` is sent.
+    pub fn spawn_reloader(
+        self: Arc<Self>,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<JoinHandle<()>, ()> {
+        let mut hangup = signal(SignalKind::hangup()).map_err(|err| {
+            error!("Can't listen for SIGHUP: {}", err);
+        })?;
+
+        Ok(tokio::spawn(async move {
+            let mut interval = time::interval(self.reload_interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            // The first tick completes right away.
+            interval.tick().await;
+
+            loop {
+                tokio::select! {
+                    _ = hangup.recv() => self.reload(),
+                    _ = interval.tick() => {
+                        if self.changed() {
+                            self.reload();
+                        }
+                    }
+                    _ = shutdown.changed() => break,
+                }
+            }
+        }))
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+fn certified_key(cert_path: &Path, key_path: &Path) -> Result<CertifiedKey, ()> {
+    let certs = match CertificateDer::pem_file_iter(cert_path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+    {
+        Ok(certs) if !certs.is_empty() => certs,
+        Ok(_) => {
+            error!("No certificates found in {}", cert_path.display());
+            return Err(());
+        }
+        Err(err) => {
+            error!(
+                "Can't read the certificates from {}: {}",
+                cert_path.display(),
+                err
+            );
+            return Err(());
+        }
+    };
+
+    let key = match PrivateKeyDer::from_pem_file(key_path) {
+        Ok(key) => key,
+        Err(err) => {
+            error!(
+                "Can't read the private key from {}: {}",
+                key_path.display(),
+                err
+            );
+            return Err(());
+        }
+    };
+
+    let key = match ring::sign::any_supported_type(&key) {
+        Ok(key) => key,
+        Err(err) => {
+            error!("The private key isn't supported: {}", err);
+            return Err(());
+        }
+    };
+
+    let certified_key = CertifiedKey::new(certs, key);
+
+    if let Err(err) = certified_key.keys_match() {
+        error!("The private key doesn't belong to the certificate: {}", err);
+        return Err(());
+    }
+
+    Ok(certified_key)
+}
@@ -0,0 +1,291 @@
+//! Versions of the API.
+//!
+//! The API is served under `/api/v1`. The paths it had before versioning
+//! are kept as aliases, whose responses carry `Deprecation`, `Sunset` and a
+//! `Link` to their successor. Probes, metrics and the OpenAPI document are
+//! operational and stay unversioned, and so does `/graphql`, whose schema
+//! deprecates fields instead.
+//!
+//! Clients can ask for a version with `Accept: application/vnd.todo.v1+json`
+//! and get it back as the `Content-Type`. Asking only for versions which
+//! aren't served gets 406, so a `/api/v2` can be added next to this one
+//! without older clients silently getting the wrong shapes.
+
+use std::future::{Future, Ready, ready};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse, Transform, forward_ready};
+use actix_web::http::header::{
+    self, HeaderMap, HeaderName, HeaderValue, HttpDate, TryIntoHeaderValue,
+};
+use actix_web::{Error, HttpResponse};
+use serde::Serialize;
+
+use crate::config::ApiConfig;
+
+pub const API_V1: &str = "/api/v1";
+
+const VERSION: u32 = 1;
+const MEDIA_TYPE_PREFIX: &str = "application/vnd.todo.v";
+const MEDIA_TYPE_SUFFIX: &str = "+json";
+
+#[derive(Serialize)]
+struct NotAcceptable {
+    error: &'static str,
+    supported: &'static [&'static str],
+}
+
+/// When the unversioned paths were deprecated and when they go away.
+#[derive(Clone, Copy)]
+pub struct Legacy {
+    deprecated: SystemTime,
+    sunset: SystemTime,
+}
+
+impl Legacy {
+    /// `None` when the aliases are turned off. The configuration has to be
+    /// validated already.
+    pub fn new(config: &ApiConfig) -> Option<Self> {
+        config.legacy_paths.then(|| Legacy {
+            deprecated: HttpDate::from_str(&config.legacy_deprecated)
+                .expect("The configuration is validated")
+                .into(),
+            sunset: HttpDate::from_str(&config.legacy_sunset)
+                .expect("The configuration is validated")
+                .into(),
+        })
+    }
+}
+
+pub struct ApiVersion {
+    legacy: Option<Legacy>,
+}
+
+impl ApiVersion {
+    pub fn current() -> Self {
+        ApiVersion { legacy: None }
+    }
+
+    /// For the aliases of the current version's paths.
+    pub fn legacy(legacy: Legacy) -> Self {
+        ApiVersion {
+            legacy: Some(legacy),
+        }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ApiVersion
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ApiVersionMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ApiVersionMiddleware {
+            service: Rc::new(service),
+            legacy: self.legacy,
+        }))
+    }
+}
+
+pub struct ApiVersionMiddleware<S> {
+    service: Rc<S>,
+    legacy: Option<Legacy>,
+}
+
+impl<S, B> Service<ServiceRequest> for ApiVersionMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = self.service.clone();
+        // The aliases' empty prefix also catches paths nothing is routed
+        // for, those aren't deprecated.
+        let legacy = self.legacy.filter(|_| req.match_pattern().is_some());
+        let requested = requested_versions(req.headers());
+        let successor = format!("<{}{}>; rel=\"successor-version\"", API_V1, req.path());
+
+        Box::pin(async move {
+            let mut res = if !requested.is_empty() && !requested.contains(&VERSION) {
+                let res = HttpResponse::NotAcceptable().json(NotAcceptable {
+                    error: "None of the requested API versions is served",
+                    supported: &["application/vnd.todo.v1+json"],
+                });
+                req.into_response(res).map_into_right_body()
+            } else {
+                service.call(req).await?.map_into_left_body()
+            };
+
+            let headers = res.headers_mut();
+            headers.insert(
+                HeaderName::from_static("api-version"),
+                HeaderValue::from(VERSION),
+            );
+            headers.append(header::VARY, HeaderValue::from_static("Accept"));
+            if requested.contains(&VERSION) && is_json(headers) {
+                headers.insert(
+                    header::CONTENT_TYPE,
+                    HeaderValue::from_static("application/vnd.todo.v1+json"),
+                );
+            }
+
+            if let Some(legacy) = legacy {
+                insert_deprecation(headers, &legacy, &successor);
+            }
+
+            Ok(res)
+        })
+    }
+}
+
+/// The versions in the `Accept` header's vendor media types, empty when
+/// none was asked for.
+fn requested_versions(headers: &HeaderMap) -> Vec<u32> {
+    headers
+        .get_all(header::ACCEPT)
+        .filter_map(|accept| accept.to_str().ok())
+        .flat_map(|accept| accept.split(','))
+        .filter_map(|media_type| {
+            media_type
+                .split(';')
+                .next()?
+                .trim()
+                .to_ascii_lowercase()
+                .strip_prefix(MEDIA_TYPE_PREFIX)?
+                .strip_suffix(MEDIA_TYPE_SUFFIX)?
+                .parse()
+                .ok()
+        })
+        .collect()
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .is_some_and(|content_type| content_type.starts_with("application/json"))
+}
+
+fn insert_deprecation(headers: &mut HeaderMap, legacy: &Legacy, successor: &str) {
+    let deprecated = legacy
+        .deprecated
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    headers.insert(
+        HeaderName::from_static("deprecation"),
+        HeaderValue::from_str(&format!("@{}", deprecated)).unwrap(),
+    );
+    if let Ok(sunset) = HttpDate::from(legacy.sunset).try_into_value() {
+        headers.insert(HeaderName::from_static("sunset"), sunset);
+    }
+    if let Ok(successor) = HeaderValue::from_str(successor) {
+        headers.append(header::LINK, successor);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::test::{TestRequest, call_service, init_service};
+    use actix_web::{App, HttpResponse, web};
+
+    use super::{API_V1, ApiVersion, Legacy};
+    use crate::config::ApiConfig;
+
+    fn routes(config: &mut web::ServiceConfig) {
+        config.route(
+            "/todo/",
+            web::get().to(|| async { HttpResponse::Ok().json(Vec::<u32>::new()) }),
+        );
+    }
+
+    #[actix_web::test]
+    async fn only_routed_legacy_paths_are_deprecated() {
+        let legacy = Legacy::new(&ApiConfig::default()).unwrap();
+        let app = init_service(
+            App::new()
+                .service(
+                    web::scope(API_V1)
+                        .wrap(ApiVersion::current())
+                        .configure(routes),
+                )
+                .service(
+                    web::scope("")
+                        .wrap(ApiVersion::legacy(legacy))
+                        .configure(routes),
+                ),
+        )
+        .await;
+
+        let res = call_service(&app, TestRequest::get().uri("/todo/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        let headers = res.headers();
+        assert_eq!(headers.get("deprecation").unwrap(), "@1792368000");
+        assert_eq!(
+            headers.get("sunset").unwrap(),
+            "Mon, 19 Apr 2027 00:00:00 GMT"
+        );
+        assert_eq!(
+            headers.get("link").unwrap(),
+            "</api/v1/todo/>; rel=\"successor-version\""
+        );
+
+        let res = call_service(&app, TestRequest::get().uri("/api/v1/todo/").to_request()).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(res.headers().get("api-version").unwrap(), "1");
+        assert!(!res.headers().contains_key("deprecation"));
+
+        let res = call_service(&app, TestRequest::get().uri("/nothing").to_request()).await;
+        assert_eq!(res.status(), StatusCode::NOT_FOUND);
+        assert!(!res.headers().contains_key("deprecation"));
+        assert!(!res.headers().contains_key("link"));
+    }
+
+    #[actix_web::test]
+    async fn versions_are_negotiated_with_accept() {
+        let app = init_service(
+            App::new().service(
+                web::scope(API_V1)
+                    .wrap(ApiVersion::current())
+                    .configure(routes),
+            ),
+        )
+        .await;
+
+        let req = TestRequest::get()
+            .uri("/api/v1/todo/")
+            .insert_header(("Accept", "application/vnd.todo.v1+json"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(
+            res.headers().get("content-type").unwrap(),
+            "application/vnd.todo.v1+json"
+        );
+
+        let req = TestRequest::get()
+            .uri("/api/v1/todo/")
+            .insert_header(("Accept", "application/vnd.todo.v2+json"))
+            .to_request();
+        let res = call_service(&app, req).await;
+        assert_eq!(res.status(), StatusCode::NOT_ACCEPTABLE);
+    }
+}
@@ -0,0 +1,1213 @@
+//! ```sh
+//! cargo run --release -- --config config.toml --port 8081 --print-config
+//! ```
+//!
+//! The configuration is read from a TOML file, then from environment
+//! variables and then from command line flags, each overriding the one
+//! before. Without `--config` the file is `config.toml`, when it exists.
+//! `config.example.toml` lists every setting with the environment variable
+//! which overrides it.
+//!
+//! Everything is validated before the server starts, every problem found is
+//! reported at once. `--print-config` prints the resulting configuration with
+//! its secrets redacted.
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use actix_web::http::header::HttpDate;
+use argon2::Params;
+use clap::Parser;
+use oauth2::url::Url;
+use serde::{Deserialize, Serialize, Serializer};
+use tracing_subscriber::EnvFilter;
+
+/// Read when no configuration file is given, if it exists.
+const DEFAULT_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Parser)]
+#[command(version, about = "The API of the todo app")]
+pub struct Cli {
+    /// The TOML configuration file.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Print the configuration with its secrets redacted and exit.
+    #[arg(long)]
+    pub print_config: bool,
+    /// The address to listen on.
+    #[arg(long)]
+    address: Option<String>,
+    /// The port to listen on.
+    #[arg(long)]
+    port: Option<u16>,
+    /// The origin of the frontend.
+    #[arg(long)]
+    allowed_origin: Option<String>,
+    /// The path of the SQLite database.
+    #[arg(long, value_name = "PATH")]
+    database: Option<String>,
+    /// The most connections to the database at once.
+    #[arg(long)]
+    max_connections: Option<u32>,
+    /// Run in development mode, see `server.dev_mode`.
+    #[arg(long)]
+    dev: bool,
+}
+
+/// A value which is never printed or logged.
+#[derive(Clone, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[redacted]")
+    }
+}
+
+impl Serialize for Secret {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str("[redacted]")
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub logging: LoggingConfig,
+    pub otlp: OtlpConfig,
+    pub server: ServerConfig,
+    pub tls: TlsConfig,
+    pub database: DatabaseConfig,
+    pub github: GitHubConfig,
+    pub sessions: SessionsConfig,
+    pub webauthn: WebAuthnConfig,
+    pub argon2: Argon2Config,
+    pub admission: AdmissionConfig,
+    pub admins: AdminsConfig,
+    pub accounts: AccountsConfig,
+    pub sweeper: SweeperConfig,
+    pub todo: TodoConfig,
+    pub metrics: MetricsConfig,
+    pub rate_limit: RateLimitConfig,
+    pub docs: DocsConfig,
+    pub api: ApiConfig,
+    pub graphql: GraphQlConfig,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    /// `RUST_LOG` directives, like `info,todo_app_api::database=debug`.
+    pub filter: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            format: LogFormat::Pretty,
+            filter: String::from("info"),
+        }
+    }
+}
+
+/// Spans are exported when an endpoint is given, which needs the `otel`
+/// feature.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OtlpConfig {
+    /// The collector's OTLP/HTTP base URL, `/v1/traces` is appended.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    pub service_name: String,
+    /// The share of the traces starting here which are exported. Traces
+    /// continued from a caller follow the caller's decision.
+    pub sample_ratio: f64,
+}
+
+impl Default for OtlpConfig {
+    fn default() -> Self {
+        OtlpConfig {
+            endpoint: None,
+            service_name: String::from(env!("CARGO_PKG_NAME")),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// Human readable lines.
+    Pretty,
+    /// One JSON object per line, with the spans of every event.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    pub address: String,
+    pub port: u16,
+    /// The origin of the frontend, the only one allowed to make requests
+    /// with credentials.
+    pub allowed_origin: String,
+    /// How long requests in flight, and then background tasks, get to finish
+    /// when shutting down.
+    pub shutdown_timeout_secs: u64,
+    /// The largest JSON body accepted, larger ones get 413.
+    pub json_limit_bytes: usize,
+    /// Lets the server start without a session secret, with a random key
+    /// which doesn't survive a restart. Never use it in production.
+    pub dev_mode: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            address: String::from("0.0.0.0"),
+            port: 8080,
+            allowed_origin: String::from("https://todo.celarye.dev"),
+            shutdown_timeout_secs: 30,
+            json_limit_bytes: 64 * 1024,
+            dev_mode: false,
+        }
+    }
+}
+
+/// HTTPS is served on the server's port when a certificate and key are
+/// given.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// A PEM file with the certificate chain, leaf first.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_path: Option<PathBuf>,
+    /// A PEM file with the private key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_path: Option<PathBuf>,
+    /// How often the files are checked for changes. They are also reloaded
+    /// on `SIGHUP`.
+    pub reload_interval_secs: u64,
+    /// A plain HTTP port redirecting every request to HTTPS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redirect_port: Option<u16>,
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        TlsConfig {
+            cert_path: None,
+            key_path: None,
+            reload_interval_secs: 60,
+            redirect_port: None,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: String,
+    pub max_connections: u32,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        DatabaseConfig {
+            path: String::from("database.sqlite3"),
+            max_connections: 5,
+        }
+    }
+}
+
+/// GitHub sign-in is turned off without a client.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitHubConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<Secret>,
+    pub redirect_url: String,
+}
+
+impl Default for GitHubConfig {
+    fn default() -> Self {
+        GitHubConfig {
+            client_id: None,
+            client_secret: None,
+            redirect_url: String::from("https://todo.celarye.dev"),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// Required unless `server.dev_mode` is on, which uses a random key
+    /// so sessions don't survive a restart.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret: Option<Secret>,
+    pub sliding_expiration: bool,
+    pub absolute_max_secs: u64,
+    pub cookie_same_site: SameSitePolicy,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        SessionsConfig {
+            secret: None,
+            sliding_expiration: false,
+            absolute_max_secs: 60 * 60 * 24 * 7,
+            cookie_same_site: SameSitePolicy::Lax,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub enum SameSitePolicy {
+    Strict,
+    Lax,
+    None,
+}
+
+impl FromStr for SameSitePolicy {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "Strict" => Ok(SameSitePolicy::Strict),
+            "Lax" => Ok(SameSitePolicy::Lax),
+            "None" => Ok(SameSitePolicy::None),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebAuthnConfig {
+    pub rp_id: String,
+    pub rp_name: String,
+    pub origin: String,
+    pub user_verification: UserVerification,
+}
+
+impl Default for WebAuthnConfig {
+    fn default() -> Self {
+        WebAuthnConfig {
+            rp_id: String::from("todo.celarye.dev"),
+            rp_name: String::from("Todo App"),
+            origin: String::from("https://todo.celarye.dev"),
+            user_verification: UserVerification::Preferred,
+        }
+    }
+}
+
+/// Whether the authenticator has to verify the user, with a PIN or
+/// biometrics, on top of checking they are present.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UserVerification {
+    /// Ceremonies without the user verified flag are rejected.
+    Required,
+    Preferred,
+    Discouraged,
+}
+
+impl FromStr for UserVerification {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "required" => Ok(UserVerification::Required),
+            "preferred" => Ok(UserVerification::Preferred),
+            "discouraged" => Ok(UserVerification::Discouraged),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Argon2Config {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl Default for Argon2Config {
+    fn default() -> Self {
+        Argon2Config {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdmissionConfig {
+    pub github_allowed_orgs: Vec<String>,
+    /// `org/team` slugs.
+    pub github_allowed_teams: Vec<String>,
+    pub allowed_email_domains: Vec<String>,
+    pub invite_only: bool,
+    /// Lets local accounts sign up without an invite while the rules above
+    /// are set, with only the domain of their unverified email checked.
+    pub allow_local_signup: bool,
+}
+
+/// Admins are named by their GitHub user id, which unlike a username can't
+/// be claimed by someone else before the admin signs up or after a rename.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdminsConfig {
+    pub github_ids: Vec<u32>,
+}
+
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountsConfig {
+    /// Accounts are deleted right away without one.
+    pub deletion_grace_days: u64,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SweeperConfig {
+    pub interval_secs: u64,
+}
+
+impl Default for SweeperConfig {
+    fn default() -> Self {
+        SweeperConfig {
+            interval_secs: 60 * 5,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TodoConfig {
+    /// In characters, after trimming and normalization.
+    pub max_content_chars: usize,
+    pub max_items_per_user: u32,
+    pub max_lists_per_user: u32,
+}
+
+impl Default for TodoConfig {
+    fn default() -> Self {
+        TodoConfig {
+            max_content_chars: 1000,
+            max_items_per_user: 1000,
+            max_lists_per_user: 100,
+        }
+    }
+}
+
+/// `/metrics` is served on the server's port unless a port of its own is
+/// given, and only with the bearer token when one is set.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token: Option<Secret>,
+    /// Only used with a port.
+    pub address: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+}
+
+impl Default for MetricsConfig {
+    fn default() -> Self {
+        MetricsConfig {
+            token: None,
+            address: String::from("127.0.0.1"),
+            port: None,
+        }
+    }
+}
+
+/// Token buckets, per IP address for anonymous requests and the sign-in
+/// endpoints, per user for sessions and per personal access token.
+#[derive(Clone, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    pub enabled: bool,
+    /// Takes the client's address from `Forwarded` or `X-Forwarded-For`,
+    /// only safe behind a proxy which sets them. Also applies to the
+    /// addresses in sessions, the audit log and the request log.
+    pub trust_proxy: bool,
+    pub anonymous: RateBudget,
+    pub authenticated: RateBudget,
+    /// Everything under `/user/auth/`, always per IP address.
+    pub auth: RateBudget,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            enabled: true,
+            trust_proxy: false,
+            anonymous: RateBudget {
+                burst: 30,
+                per_minute: 60,
+            },
+            authenticated: RateBudget {
+                burst: 60,
+                per_minute: 300,
+            },
+            auth: RateBudget {
+                burst: 10,
+                per_minute: 10,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateBudget {
+    /// The size of the bucket, the requests which can be made at once.
+    pub burst: u32,
+    /// How fast the bucket refills.
+    pub per_minute: u32,
+}
+
+/// `/openapi.json` is always served, Swagger UI on `/docs/` only when
+/// turned on in a build with the `docs-ui` feature.
+#[derive(Default, Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DocsConfig {
+    pub ui: bool,
+}
+
+/// The API is served under `/api/v1`, and on the paths it had before as
+/// long as the legacy paths are kept.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    pub legacy_paths: bool,
+    /// HTTP dates, like `Mon, 19 Oct 2026 00:00:00 GMT`, sent in the
+    /// `Deprecation` and `Sunset` headers of the legacy paths.
+    pub legacy_deprecated: String,
+    pub legacy_sunset: String,
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        ApiConfig {
+            legacy_paths: true,
+            legacy_deprecated: String::from("Mon, 19 Oct 2026 00:00:00 GMT"),
+            legacy_sunset: String::from("Mon, 19 Apr 2027 00:00:00 GMT"),
+        }
+    }
+}
+
+/// `/graphql` rejects operations nested deeper or costing more than this
+/// before running them. Every field costs 1, a list of items as much as
+/// the items it asks for. The usual introspection query is nested 13 deep.
+#[derive(Deserialize, Serialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GraphQlConfig {
+    pub enabled: bool,
+    pub max_depth: usize,
+    pub max_complexity: usize,
+}
+
+impl Default for GraphQlConfig {
+    fn default() -> Self {
+        GraphQlConfig {
+            enabled: true,
+            max_depth: 15,
+            max_complexity: 1000,
+        }
+    }
+}
+
+impl Config {
+    /// Reads the file, environment variables and flags, without validating
+    /// the result.
+    pub fn load(cli: &Cli) -> Result<Self, Vec<String>> {
+        let mut config = match &cli.config {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                Config::from_file(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => Config::default(),
+        };
+
+        let mut errors = Vec::new();
+        config.apply_env(&mut errors);
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        config.apply_cli(cli);
+        Ok(config)
+    }
+
+    fn from_file(path: &Path) -> Result<Self, Vec<String>> {
+        let contents = fs::read_to_string(path).map_err(|err| {
+            vec![format!(
+                "Can't read the configuration file {}: {}",
+                path.display(),
+                err
+            )]
+        })?;
+
+        toml::from_str(&contents).map_err(|err| {
+            vec![format!(
+                "The configuration file {} is invalid: {}",
+                path.display(),
+                err
+            )]
+        })
+    }
+
+    fn apply_env(&mut self, errors: &mut Vec<String>) {
+        env_var("LOG_FORMAT", &mut self.logging.format, errors, parse);
+        env_var("RUST_LOG", &mut self.logging.filter, errors, parse);
+
+        env_var(
+            "OTEL_EXPORTER_OTLP_ENDPOINT",
+            &mut self.otlp.endpoint,
+            errors,
+            |value| Some(Some(value.to_string())),
+        );
+        env_var(
+            "OTEL_SERVICE_NAME",
+            &mut self.otlp.service_name,
+            errors,
+            parse,
+        );
+        env_var(
+            "OTEL_TRACES_SAMPLER_ARG",
+            &mut self.otlp.sample_ratio,
+            errors,
+            parse,
+        );
+
+        env_var("BIND_ADDRESS", &mut self.server.address, errors, parse);
+        env_var("PORT", &mut self.server.port, errors, parse);
+        env_var(
+            "ALLOWED_ORIGIN",
+            &mut self.server.allowed_origin,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "SHUTDOWN_TIMEOUT_SECS",
+            &mut self.server.shutdown_timeout_secs,
+            errors,
+            parse,
+        );
+        env_var(
+            "JSON_LIMIT_BYTES",
+            &mut self.server.json_limit_bytes,
+            errors,
+            parse,
+        );
+        env_var("DEV_MODE", &mut self.server.dev_mode, errors, parse_bool);
+
+        env_var("TLS_CERT_PATH", &mut self.tls.cert_path, errors, |value| {
+            Some(Some(PathBuf::from(value)))
+        });
+        env_var("TLS_KEY_PATH", &mut self.tls.key_path, errors, |value| {
+            Some(Some(PathBuf::from(value)))
+        });
+        env_var(
+            "TLS_RELOAD_INTERVAL_SECS",
+            &mut self.tls.reload_interval_secs,
+            errors,
+            parse,
+        );
+        env_var(
+            "TLS_REDIRECT_PORT",
+            &mut self.tls.redirect_port,
+            errors,
+            |value| value.parse().ok().map(Some),
+        );
+
+        env_var("DATABASE_PATH", &mut self.database.path, errors, parse);
+        env_var(
+            "DATABASE_MAX_CONNECTIONS",
+            &mut self.database.max_connections,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "GITHUB_CLIENT_ID",
+            &mut self.github.client_id,
+            errors,
+            |value| Some(Some(value.to_string())),
+        );
+        env_var(
+            "GITHUB_CLIENT_SECRET",
+            &mut self.github.client_secret,
+            errors,
+            |value| Some(Some(Secret(value.to_string()))),
+        );
+        env_var(
+            "GITHUB_REDIRECT_URL",
+            &mut self.github.redirect_url,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "SESSION_SECRET",
+            &mut self.sessions.secret,
+            errors,
+            |value| Some(Some(Secret(value.to_string()))),
+        );
+        env_var(
+            "SESSION_SLIDING_EXPIRATION",
+            &mut self.sessions.sliding_expiration,
+            errors,
+            parse_bool,
+        );
+        env_var(
+            "SESSION_ABSOLUTE_MAX_SECS",
+            &mut self.sessions.absolute_max_secs,
+            errors,
+            parse,
+        );
+        env_var(
+            "SESSION_COOKIE_SAME_SITE",
+            &mut self.sessions.cookie_same_site,
+            errors,
+            parse,
+        );
+
+        env_var("WEBAUTHN_RP_ID", &mut self.webauthn.rp_id, errors, parse);
+        env_var(
+            "WEBAUTHN_RP_NAME",
+            &mut self.webauthn.rp_name,
+            errors,
+            parse,
+        );
+        env_var("WEBAUTHN_ORIGIN", &mut self.webauthn.origin, errors, parse);
+        env_var(
+            "WEBAUTHN_USER_VERIFICATION",
+            &mut self.webauthn.user_verification,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "ARGON2_MEMORY_KIB",
+            &mut self.argon2.memory_kib,
+            errors,
+            parse,
+        );
+        env_var(
+            "ARGON2_ITERATIONS",
+            &mut self.argon2.iterations,
+            errors,
+            parse,
+        );
+        env_var(
+            "ARGON2_PARALLELISM",
+            &mut self.argon2.parallelism,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "GITHUB_ALLOWED_ORGS",
+            &mut self.admission.github_allowed_orgs,
+            errors,
+            parse_list,
+        );
+        env_var(
+            "GITHUB_ALLOWED_TEAMS",
+            &mut self.admission.github_allowed_teams,
+            errors,
+            parse_list,
+        );
+        env_var(
+            "ALLOWED_EMAIL_DOMAINS",
+            &mut self.admission.allowed_email_domains,
+            errors,
+            parse_list,
+        );
+        env_var(
+            "INVITE_ONLY",
+            &mut self.admission.invite_only,
+            errors,
+            parse_bool,
+        );
+        env_var(
+            "ALLOW_LOCAL_SIGNUP",
+            &mut self.admission.allow_local_signup,
+            errors,
+            parse_bool,
+        );
+
+        env_var(
+            "ADMIN_GITHUB_IDS",
+            &mut self.admins.github_ids,
+            errors,
+            |value| {
+                parse_list(value)?
+                    .iter()
+                    .map(|id| id.parse().ok())
+                    .collect()
+            },
+        );
+        if env::var_os("ADMIN_USERNAMES").is_some() {
+            errors.push(String::from(
+                "ADMIN_USERNAMES is no longer supported, list the admins' GitHub user ids in ADMIN_GITHUB_IDS",
+            ));
+        }
+
+        env_var(
+            "ACCOUNT_DELETION_GRACE_DAYS",
+            &mut self.accounts.deletion_grace_days,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "SWEEP_INTERVAL_SECS",
+            &mut self.sweeper.interval_secs,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "TODO_MAX_CONTENT_CHARS",
+            &mut self.todo.max_content_chars,
+            errors,
+            parse,
+        );
+        env_var(
+            "TODO_MAX_ITEMS_PER_USER",
+            &mut self.todo.max_items_per_user,
+            errors,
+            parse,
+        );
+        env_var(
+            "TODO_MAX_LISTS_PER_USER",
+            &mut self.todo.max_lists_per_user,
+            errors,
+            parse,
+        );
+
+        env_var("METRICS_TOKEN", &mut self.metrics.token, errors, |value| {
+            Some(Some(Secret(value.to_string())))
+        });
+        env_var("METRICS_ADDRESS", &mut self.metrics.address, errors, parse);
+        env_var("METRICS_PORT", &mut self.metrics.port, errors, |value| {
+            value.parse().ok().map(Some)
+        });
+
+        env_var(
+            "RATE_LIMIT_ENABLED",
+            &mut self.rate_limit.enabled,
+            errors,
+            parse_bool,
+        );
+        env_var(
+            "RATE_LIMIT_TRUST_PROXY",
+            &mut self.rate_limit.trust_proxy,
+            errors,
+            parse_bool,
+        );
+        for (name, budget) in [
+            ("ANONYMOUS", &mut self.rate_limit.anonymous),
+            ("AUTHENTICATED", &mut self.rate_limit.authenticated),
+            ("AUTH", &mut self.rate_limit.auth),
+        ] {
+            env_var(
+                &format!("RATE_LIMIT_{}_BURST", name),
+                &mut budget.burst,
+                errors,
+                parse,
+            );
+            env_var(
+                &format!("RATE_LIMIT_{}_PER_MINUTE", name),
+                &mut budget.per_minute,
+                errors,
+                parse,
+            );
+        }
+
+        env_var("DOCS_UI", &mut self.docs.ui, errors, parse_bool);
+
+        env_var(
+            "API_LEGACY_PATHS",
+            &mut self.api.legacy_paths,
+            errors,
+            parse_bool,
+        );
+        env_var(
+            "API_LEGACY_DEPRECATED",
+            &mut self.api.legacy_deprecated,
+            errors,
+            parse,
+        );
+        env_var(
+            "API_LEGACY_SUNSET",
+            &mut self.api.legacy_sunset,
+            errors,
+            parse,
+        );
+
+        env_var(
+            "GRAPHQL_ENABLED",
+            &mut self.graphql.enabled,
+            errors,
+            parse_bool,
+        );
+        env_var(
+            "GRAPHQL_MAX_DEPTH",
+            &mut self.graphql.max_depth,
+            errors,
+            parse,
+        );
+        env_var(
+            "GRAPHQL_MAX_COMPLEXITY",
+            &mut self.graphql.max_complexity,
+            errors,
+            parse,
+        );
+    }
+
+    fn apply_cli(&mut self, cli: &Cli) {
+        if let Some(address) = &cli.address {
+            self.server.address = address.clone();
+        }
+        if let Some(port) = cli.port {
+            self.server.port = port;
+        }
+        if let Some(allowed_origin) = &cli.allowed_origin {
+            self.server.allowed_origin = allowed_origin.clone();
+        }
+        if let Some(database) = &cli.database {
+            self.database.path = database.clone();
+        }
+        if let Some(max_connections) = cli.max_connections {
+            self.database.max_connections = max_connections;
+        }
+        if cli.dev {
+            self.server.dev_mode = true;
+        }
+    }
+
+    /// Every problem with the configuration, empty when it is valid.
+    pub fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = EnvFilter::try_new(&self.logging.filter) {
+            errors.push(format!("logging.filter (RUST_LOG) is invalid: {}", err));
+        }
+
+        if let Some(endpoint) = &self.otlp.endpoint {
+            if !cfg!(feature = "otel") {
+                errors.push(String::from(
+                    "otlp.endpoint (OTEL_EXPORTER_OTLP_ENDPOINT) needs a build with the otel feature",
+                ));
+            }
+            if !Url::parse(endpoint).is_ok_and(|url| matches!(url.scheme(), "http" | "https")) {
+                errors.push(String::from(
+                    "otlp.endpoint (OTEL_EXPORTER_OTLP_ENDPOINT) must be an http or https URL",
+                ));
+            }
+        }
+        if !(0.0..=1.0).contains(&self.otlp.sample_ratio) {
+            errors.push(String::from(
+                "otlp.sample_ratio (OTEL_TRACES_SAMPLER_ARG) must be between 0 and 1",
+            ));
+        }
+
+        if !is_origin(&self.server.allowed_origin) {
+            errors.push(String::from(
+                "server.allowed_origin (ALLOWED_ORIGIN) must be an origin like https://todo.example.com",
+            ));
+        }
+        if self.server.shutdown_timeout_secs == 0 {
+            errors.push(String::from(
+                "server.shutdown_timeout_secs (SHUTDOWN_TIMEOUT_SECS) must be at least 1",
+            ));
+        }
+        if self.server.json_limit_bytes < 1024 {
+            errors.push(String::from(
+                "server.json_limit_bytes (JSON_LIMIT_BYTES) must be at least 1024",
+            ));
+        }
+
+        match (&self.tls.cert_path, &self.tls.key_path) {
+            (Some(_), None) => errors.push(String::from(
+                "TLS needs tls.key_path (TLS_KEY_PATH) as well as tls.cert_path",
+            )),
+            (None, Some(_)) => errors.push(String::from(
+                "TLS needs tls.cert_path (TLS_CERT_PATH) as well as tls.key_path",
+            )),
+            (None, None) if self.tls.redirect_port.is_some() => errors.push(String::from(
+                "tls.redirect_port (TLS_REDIRECT_PORT) needs TLS to be configured",
+            )),
+            _ => {}
+        }
+        if self.tls.reload_interval_secs == 0 {
+            errors.push(String::from(
+                "tls.reload_interval_secs (TLS_RELOAD_INTERVAL_SECS) must be at least 1",
+            ));
+        }
+        if self.tls.redirect_port == Some(self.server.port) {
+            errors.push(String::from(
+                "tls.redirect_port (TLS_REDIRECT_PORT) can't be the same as server.port",
+            ));
+        }
+
+        if self.database.path.is_empty() {
+            errors.push(String::from("database.path (DATABASE_PATH) can't be empty"));
+        }
+        if self.database.max_connections == 0 {
+            errors.push(String::from(
+                "database.max_connections (DATABASE_MAX_CONNECTIONS) must be at least 1",
+            ));
+        }
+
+        match (&self.github.client_id, &self.github.client_secret) {
+            (Some(_), None) => errors.push(String::from(
+                "GitHub sign-in needs github.client_secret (GITHUB_CLIENT_SECRET) as well as github.client_id",
+            )),
+            (None, Some(_)) => errors.push(String::from(
+                "GitHub sign-in needs github.client_id (GITHUB_CLIENT_ID) as well as github.client_secret",
+            )),
+            _ => {}
+        }
+        if Url::parse(&self.github.redirect_url).is_err() {
+            errors.push(String::from(
+                "github.redirect_url (GITHUB_REDIRECT_URL) must be a URL",
+            ));
+        }
+
+        match &self.sessions.secret {
+            None if !self.server.dev_mode => errors.push(String::from(
+                "sessions.secret (SESSION_SECRET) is required, only server.dev_mode (DEV_MODE, --dev) can go without one",
+            )),
+            Some(secret) if secret.expose().len() < 32 => errors.push(String::from(
+                "sessions.secret (SESSION_SECRET) must be at least 32 bytes",
+            )),
+            _ => {}
+        }
+        if self.sessions.absolute_max_secs == 0 {
+            errors.push(String::from(
+                "sessions.absolute_max_secs (SESSION_ABSOLUTE_MAX_SECS) must be at least 1",
+            ));
+        }
+
+        let webauthn_host = Url::parse(&self.webauthn.origin)
+            .ok()
+            .filter(|_| is_origin(&self.webauthn.origin))
+            .and_then(|origin| origin.host_str().map(String::from));
+        match webauthn_host {
+            None => errors.push(String::from(
+                "webauthn.origin (WEBAUTHN_ORIGIN) must be an origin like https://todo.example.com",
+            )),
+            Some(host)
+                if host != self.webauthn.rp_id
+                    && !host.ends_with(&format!(".{}", self.webauthn.rp_id)) =>
+            {
+                errors.push(String::from(
+                    "webauthn.rp_id (WEBAUTHN_RP_ID) must be the host of webauthn.origin or one of its parent domains",
+                ))
+            }
+            Some(_) => {}
+        }
+
+        if let Err(err) = Params::new(
+            self.argon2.memory_kib,
+            self.argon2.iterations,
+            self.argon2.parallelism,
+            None,
+        ) {
+            errors.push(format!("The argon2 parameters are invalid: {}", err));
+        }
+
+        if self.admission.github_allowed_teams.iter().any(|team| {
+            team.split_once('/')
+                .is_none_or(|(org, team)| org.is_empty() || team.is_empty())
+        }) {
+            errors.push(String::from(
+                "admission.github_allowed_teams (GITHUB_ALLOWED_TEAMS) must be org/team slugs",
+            ));
+        }
+
+        if self.sweeper.interval_secs == 0 {
+            errors.push(String::from(
+                "sweeper.interval_secs (SWEEP_INTERVAL_SECS) must be at least 1",
+            ));
+        }
+
+        if self.todo.max_content_chars == 0 {
+            errors.push(String::from(
+                "todo.max_content_chars (TODO_MAX_CONTENT_CHARS) must be at least 1",
+            ));
+        }
+        if self.todo.max_items_per_user == 0 {
+            errors.push(String::from(
+                "todo.max_items_per_user (TODO_MAX_ITEMS_PER_USER) must be at least 1",
+            ));
+        }
+        if self.todo.max_lists_per_user == 0 {
+            errors.push(String::from(
+                "todo.max_lists_per_user (TODO_MAX_LISTS_PER_USER) must be at least 1",
+            ));
+        }
+
+        if self
+            .metrics
+            .token
+            .as_ref()
+            .is_some_and(|token| token.expose().len() < 16)
+        {
+            errors.push(String::from(
+                "metrics.token (METRICS_TOKEN) must be at least 16 bytes",
+            ));
+        }
+        if let Some(port) = self.metrics.port
+            && (port == self.server.port || Some(port) == self.tls.redirect_port)
+        {
+            errors.push(String::from(
+                "metrics.port (METRICS_PORT) can't be the same as server.port or tls.redirect_port",
+            ));
+        }
+
+        for (name, budget) in [
+            ("anonymous", &self.rate_limit.anonymous),
+            ("authenticated", &self.rate_limit.authenticated),
+            ("auth", &self.rate_limit.auth),
+        ] {
+            if budget.burst == 0 || budget.per_minute == 0 {
+                errors.push(format!(
+                    "rate_limit.{} (RATE_LIMIT_{}_BURST, RATE_LIMIT_{}_PER_MINUTE) must be at least 1",
+                    name,
+                    name.to_uppercase(),
+                    name.to_uppercase()
+                ));
+            }
+        }
+
+        if self.docs.ui && !cfg!(feature = "docs-ui") {
+            errors.push(String::from(
+                "docs.ui (DOCS_UI) needs a build with the docs-ui feature",
+            ));
+        }
+
+        let legacy_deprecated = HttpDate::from_str(&self.api.legacy_deprecated);
+        let legacy_sunset = HttpDate::from_str(&self.api.legacy_sunset);
+        if legacy_deprecated.is_err() {
+            errors.push(String::from(
+                "api.legacy_deprecated (API_LEGACY_DEPRECATED) must be an HTTP date like Mon, 19 Oct 2026 00:00:00 GMT",
+            ));
+        }
+        if legacy_sunset.is_err() {
+            errors.push(String::from(
+                "api.legacy_sunset (API_LEGACY_SUNSET) must be an HTTP date like Mon, 19 Apr 2027 00:00:00 GMT",
+            ));
+        }
+        if let (Ok(deprecated), Ok(sunset)) = (legacy_deprecated, legacy_sunset)
+            && sunset < deprecated
+        {
+            errors.push(String::from(
+                "api.legacy_sunset (API_LEGACY_SUNSET) can't be before api.legacy_deprecated",
+            ));
+        }
+
+        if self.graphql.max_depth == 0 {
+            errors.push(String::from(
+                "graphql.max_depth (GRAPHQL_MAX_DEPTH) must be at least 1",
+            ));
+        }
+        if self.graphql.max_complexity == 0 {
+            errors.push(String::from(
+                "graphql.max_complexity (GRAPHQL_MAX_COMPLEXITY) must be at least 1",
+            ));
+        }
+
+        errors
+    }
+
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|err| err.to_string())
+    }
+}
+
+/// Overrides `target` with the environment variable, when it is set.
+fn env_var<T>(
+    name: &str,
+    target: &mut T,
+    errors: &mut Vec<String>,
+    parse: impl FnOnce(&str) -> Option<T>,
+) {
+    let Ok(value) = env::var(name) else {
+        return;
+    };
+
+    match parse(&value) {
+        Some(value) => *target = value,
+        None => errors.push(format!("The {} environment variable is invalid", name)),
+    }
+}
+
+fn parse<T: FromStr>(value: &str) -> Option<T> {
+    value.parse().ok()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    match value {
+        "true" | "1" => Some(true),
+        "false" | "0" => Some(false),
+        _ => None,
+    }
+}
+
+fn parse_list(value: &str) -> Option<Vec<String>> {
+    Some(
+        value
+            .split(',')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .map(String::from)
+            .collect(),
+    )
+}
+
+/// Whether the value is a scheme, host and optional port, nothing more.
+fn is_origin(value: &str) -> bool {
+    Url::parse(value).is_ok_and(|url| {
+        matches!(url.scheme(), "http" | "https") && url.origin().ascii_serialization() == value
+    })
+}
@@ -1,19 +1,45 @@
+mod account;
+mod admin;
+mod integrity;
+mod invites;
+mod lists;
+mod maintenance;
+mod migrations;
+mod sessions;
+mod tokens;
+mod totp;
+mod webauthn;
+
+use std::str::FromStr;
 use std::time::SystemTime;
 
 use oauth2::CsrfToken;
 use sqlx::{
     Row, Sqlite,
     migrate::MigrateDatabase,
-    sqlite::{SqlitePool, SqlitePoolOptions},
+    sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqlitePoolOptions, SqliteRow},
 };
-use tracing::{error, info};
+use tracing::{error, info, instrument};
 
+use crate::Role;
 use crate::app::handlers::{TodoItem, User};
+use crate::metrics;
+use migrations::MIGRATIONS;
 
 pub struct Database {
     connection_pool: SqlitePool,
 }
 
+/// What became of a new account.
+#[derive(Debug, PartialEq)]
+pub enum NewUser {
+    Added(u32),
+    /// The username or email address is taken.
+    Taken,
+    /// The invite code is unknown, used up or expired.
+    InvalidInvite,
+}
+
 impl Database {
     pub async fn connect(database_path: &str, max_connections: u32) -> Result<Self, ()> {
         if !Sqlite::database_exists(database_path)
@@ -24,19 +50,28 @@ impl Database {
             Database::create(database_path).await?
         }
 
-        match SqlitePoolOptions::new()
-            .max_connections(max_connections)
-            .connect_lazy(database_path)
-        {
-            Ok(connection_pool) => Ok(Database { connection_pool }),
+        let options = match SqliteConnectOptions::from_str(database_path) {
+            // Every connection of the pool is opened with these options.
+            // The write-ahead log lets reads go on during a write.
+            Ok(options) => options
+                .foreign_keys(true)
+                .journal_mode(SqliteJournalMode::Wal),
             Err(err) => {
-                error!(
-                    "Something went wrong while creating a connection pool to the database: {}",
-                    &err
-                );
+                error!("The database path is invalid: {}", &err);
                 return Err(());
             }
-        }
+        };
+
+        let database = Database {
+            connection_pool: SqlitePoolOptions::new()
+                .max_connections(max_connections)
+                .connect_lazy_with(options),
+        };
+
+        database.migrate().await?;
+        database.repair_orphans().await?;
+
+        Ok(database)
     }
 
     async fn create(database_path: &str) -> Result<(), ()> {
@@ -85,25 +120,125 @@ impl Database {
         Ok(())
     }
 
+    async fn schema_version(&self) -> Result<usize, ()> {
+        match sqlx::query("PRAGMA user_version;")
+            .fetch_one(&self.connection_pool)
+            .await
+        {
+            Ok(row) => Ok(row.get::<u32, _>(0) as usize),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the schema version from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// The open connections, how many of them are idle, and the most the
+    /// pool opens.
+    pub fn pool_usage(&self) -> (u32, usize, u32) {
+        (
+            self.connection_pool.size(),
+            self.connection_pool.num_idle(),
+            self.connection_pool.options().get_max_connections(),
+        )
+    }
+
+    /// The number of migrations this build knows about which haven't been
+    /// applied, also checking that a connection can be used.
+    #[instrument(skip_all)]
+    pub async fn pending_migrations(&self) -> Result<usize, ()> {
+        let _timer = metrics::time_query("pending_migrations");
+
+        Ok(MIGRATIONS
+            .len()
+            .saturating_sub(self.schema_version().await?))
+    }
+
+    async fn migrate(&self) -> Result<(), ()> {
+        let version = self.schema_version().await?;
+
+        for (index, statements) in MIGRATIONS.iter().enumerate().skip(version) {
+            let result = async {
+                let mut transaction = self.connection_pool.begin().await?;
+
+                for statement in statements.iter() {
+                    sqlx::query(statement).execute(&mut *transaction).await?;
+                }
+
+                sqlx::query(&format!("PRAGMA user_version = {};", index + 1))
+                    .execute(&mut *transaction)
+                    .await?;
+
+                transaction.commit().await
+            }
+            .await;
+
+            if let Err(err) = result {
+                error!(
+                    "Something went wrong while applying database migration {}: {}",
+                    index + 1,
+                    &err
+                );
+                return Err(());
+            }
+
+            info!("Applied database migration {}", index + 1);
+        }
+
+        Ok(())
+    }
+
+    /// Checkpoints the write-ahead log into the database file and closes
+    /// every connection, waiting for the ones in use.
+    pub async fn close(&self) -> Result<(), ()> {
+        let result = sqlx::query("PRAGMA wal_checkpoint(TRUNCATE);")
+            .fetch_one(&self.connection_pool)
+            .await;
+
+        self.connection_pool.close().await;
+
+        match result {
+            Ok(row) if row.get::<i64, _>(0) == 0 => Ok(()),
+            Ok(_) => {
+                error!("The write-ahead log couldn't be checkpointed, the database was busy");
+                Err(())
+            }
+            Err(err) => {
+                error!(
+                    "Something went wrong while checkpointing the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
     pub async fn user_count(&self) -> Result<u32, ()> {
+        let _timer = metrics::time_query("user_count");
+
         match sqlx::query("SELECT COUNT() FROM users;")
             .fetch_one(&self.connection_pool)
             .await
         {
-            Ok(row) => {
-                return Ok(row.get::<u32, _>(0));
-            }
+            Ok(row) => Ok(row.get::<u32, _>(0)),
             Err(err) => {
                 error!(
                     "Something went wrong while retrieving the user count from the database: {}",
                     &err
                 );
-                return Err(());
+                Err(())
             }
-        };
+        }
     }
 
+    #[instrument(skip_all)]
     pub async fn add_csrf_token(&self, csrf_token: CsrfToken) -> Result<(), ()> {
+        let _timer = metrics::time_query("add_csrf_token");
+
         if let Err(err) = sqlx::query("INSERT INTO csrf_tokens (value, expires) VALUES (?1, ?2);")
             .bind(csrf_token.secret())
             .bind(
@@ -127,7 +262,10 @@ impl Database {
         Ok(())
     }
 
-    pub async fn get_csrf_token(&self, csrf_token: &String) -> Result<(), ()> {
+    #[instrument(skip_all)]
+    pub async fn get_csrf_token(&self, csrf_token: &str) -> Result<(), ()> {
+        let _timer = metrics::time_query("get_csrf_token");
+
         match sqlx::query("SELECT expires FROM csrf_tokens WHERE value = ?1;")
             .bind(csrf_token)
             .fetch_one(&self.connection_pool)
@@ -151,19 +289,22 @@ impl Database {
                 }
 
                 self.delete_csrf_token(csrf_token).await.unwrap();
-                return Ok(());
+                Ok(())
             }
             Err(err) => {
                 error!(
                     "Something went wrong while retrieving the csrf token from the database: {}",
                     &err
                 );
-                return Err(());
+                Err(())
             }
-        };
+        }
     }
 
-    pub async fn delete_csrf_token(&self, csrf_token: &String) -> Result<(), ()> {
+    #[instrument(skip_all)]
+    pub async fn delete_csrf_token(&self, csrf_token: &str) -> Result<(), ()> {
+        let _timer = metrics::time_query("delete_csrf_token");
+
         if let Err(err) = sqlx::query("DELETE FROM csrf_tokens WHERE value = ?1;")
             .bind(csrf_token)
             .execute(&self.connection_pool)
@@ -179,55 +320,112 @@ impl Database {
         Ok(())
     }
 
-    pub async fn add_session(&self, user_id: u32, session: String, expires: u64) -> Result<(), ()> {
-        if let Err(err) = sqlx::query(
-            "INSERT INTO user_sessions (user_id, session, expires) VALUES (?1, ?2, ?3);",
-        )
-        .bind(user_id)
-        .bind(session)
-        .bind(expires.to_string())
-        .execute(&self.connection_pool)
-        .await
-        {
-            error!(
-                "Something went wrong while inserting the session into the database: {}",
-                &err
-            );
-            return Err(());
+    /// Counts a use of `invite`, the hash of an invite code, in the same
+    /// transaction.
+    #[instrument(skip_all)]
+    pub async fn add_user(
+        &self,
+        github_id: u32,
+        username: String,
+        email: String,
+        profile_picture_url: String,
+        invite: Option<String>,
+    ) -> Result<NewUser, ()> {
+        let _timer = metrics::time_query("add_user");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+
+            if let Some(invite) = invite
+                && !invites::use_invite(&mut transaction, &invite).await?
+            {
+                return Ok(NewUser::InvalidInvite);
+            }
+
+            let user_id = sqlx::query(
+                "INSERT INTO users (github_id, username, email, profile_picture_url) VALUES (?1, ?2, ?3, ?4);",
+            )
+            .bind(github_id)
+            .bind(username)
+            .bind(email)
+            .bind(profile_picture_url)
+            .execute(&mut *transaction)
+            .await?
+            .last_insert_rowid();
+
+            transaction.commit().await?;
+
+            Ok::<_, sqlx::Error>(NewUser::Added(u32::try_from(user_id).unwrap()))
         }
+        .await;
 
-        Ok(())
+        match result {
+            Ok(new_user) => Ok(new_user),
+            Err(err) if is_unique_violation(&err) => Ok(NewUser::Taken),
+            Err(err) => {
+                error!(
+                    "Something went wrong while inserting the user into the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
     }
 
-    pub async fn get_session(&self, session: String) -> Result<u32, ()> {
-        match sqlx::query("SELECT user_id, expires FROM user_sessions WHERE session = ?1;")
-            .bind(&session)
-            .fetch_one(&self.connection_pool)
-            .await
-        {
-            Ok(row) => {
-                let expires_str: String = row.get(1);
-                let expires_timestamp: u64 = expires_str.parse().unwrap_or(0);
+    /// Counts a use of `invite` like `add_user`. The username or email
+    /// address can be taken even after `user_exists` for concurrent
+    /// sign-ups.
+    #[instrument(skip_all)]
+    pub async fn add_local_user(
+        &self,
+        username: String,
+        email: String,
+        password_hash: String,
+        invite: Option<String>,
+    ) -> Result<NewUser, ()> {
+        let _timer = metrics::time_query("add_local_user");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+
+            if let Some(invite) = invite
+                && !invites::use_invite(&mut transaction, &invite).await?
+            {
+                return Ok(NewUser::InvalidInvite);
+            }
 
-                if expires_timestamp
-                    < SystemTime::now()
+            let user_id = sqlx::query("INSERT INTO users (username, email) VALUES (?1, ?2);")
+                .bind(username)
+                .bind(email)
+                .execute(&mut *transaction)
+                .await?
+                .last_insert_rowid();
+
+            sqlx::query("INSERT INTO user_passwords (user_id, hash, updated) VALUES (?1, ?2, ?3);")
+                .bind(user_id)
+                .bind(password_hash)
+                .bind(
+                    SystemTime::now()
                         .duration_since(SystemTime::UNIX_EPOCH)
                         .unwrap()
                         .as_secs()
-                {
-                    error!("The session has expired");
+                        .to_string(),
+                )
+                .execute(&mut *transaction)
+                .await?;
 
-                    self.delete_session(session).await.unwrap();
+            transaction.commit().await?;
 
-                    Err(())
-                } else {
-                    let user_id: u32 = row.get(0);
-                    Ok(user_id)
-                }
-            }
+            Ok::<_, sqlx::Error>(NewUser::Added(u32::try_from(user_id).unwrap()))
+        }
+        .await;
+
+        match result {
+            Ok(new_user) => Ok(new_user),
+            Err(err) if is_unique_violation(&err) => Ok(NewUser::Taken),
             Err(err) => {
                 error!(
-                    "Something went wrong while retrieving the session from the database: {}",
+                    "Something went wrong while inserting the local user into the database: {}",
                     &err
                 );
                 Err(())
@@ -235,153 +433,300 @@ impl Database {
         }
     }
 
-    pub async fn delete_session(&self, session: String) -> Result<(), ()> {
-        if let Err(err) = sqlx::query("DELETE FROM user_sessions WHERE value = ?1;")
-            .bind(session)
-            .execute(&self.connection_pool)
+    #[instrument(skip_all)]
+    pub async fn user_exists(&self, username: &str, email: &str) -> Result<bool, ()> {
+        let _timer = metrics::time_query("user_exists");
+
+        match sqlx::query("SELECT COUNT() FROM users WHERE username = ?1 OR email = ?2;")
+            .bind(username)
+            .bind(email)
+            .fetch_one(&self.connection_pool)
             .await
         {
-            error!(
-                "Something went wrong while deleting the session from the database: {}",
-                &err
-            );
-            return Err(());
-        };
-
-        Ok(())
+            Ok(row) => Ok(row.get::<u32, _>(0) > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while checking if the user exists in the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
     }
 
-    pub async fn add_user(
-        &self,
-        github_id: u32,
-        username: String,
-        email: String,
-        profile_picture_url: String,
-    ) -> Result<u32, ()> {
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_user(&self, user_id: u32) -> Result<User, ()> {
+        let _timer = metrics::time_query("get_user");
+
         match sqlx::query(
-            "INSERT INTO users (github_id, username, email, profile_picture_url) VALUES (?1, ?2, ?3, ?4);",
+            "SELECT id, github_id, username, email, profile_picture_url, role, disabled, deletion_scheduled FROM users WHERE id = ?1;",
         )
-        .bind(github_id)
-        .bind(username)
-        .bind(email)
-        .bind(profile_picture_url)
-        .execute(&self.connection_pool)
+        .bind(user_id)
+        .fetch_one(&self.connection_pool)
         .await
         {
-            Ok(row) => Ok(u32::try_from(row.last_insert_rowid()).unwrap()),
+            Ok(row) => Ok(Database::user_from_row(&row)),
             Err(err) => {
-            error!(
-                "Something went wrong while inserting the user into the database: {}",
-                &err
-            );
-            Err(())
+                error!(
+                    "Something went wrong while retrieving the user from the database: {}",
+                    &err
+                );
+                Err(())
             }
         }
     }
 
-    pub async fn get_user(&self, user_id: u32) -> Result<User, ()> {
-        match sqlx::query("SELECT * FROM users WHERE id = ?1;")
-            .bind(user_id)
-            .fetch_one(&self.connection_pool)
-            .await
+    #[instrument(skip_all)]
+    pub async fn get_user_by_github_id(&self, github_id: u32) -> Result<User, ()> {
+        let _timer = metrics::time_query("get_user_by_github_id");
+
+        match sqlx::query(
+            "SELECT id, github_id, username, email, profile_picture_url, role, disabled, deletion_scheduled FROM users WHERE github_id = ?1;",
+        )
+        .bind(github_id)
+        .fetch_one(&self.connection_pool)
+        .await
         {
-            Ok(row) => {
-                let (id, github_id, username, email, profile_picture_url): (
-                    u32,
-                    u32,
-                    String,
-                    String,
-                    String,
-                ) = (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4));
-
-                return Ok(User {
-                    id,
-                    github_id,
-                    username,
-                    email,
-                    profile_picture_url,
-                });
-            }
+            Ok(row) => Ok(Database::user_from_row(&row)),
             Err(err) => {
                 error!(
                     "Something went wrong while retrieving the user from the database: {}",
                     &err
                 );
-                return Err(());
+                Err(())
             }
-        };
+        }
     }
 
-    pub async fn get_user_by_github_id(&self, github_id: u32) -> Result<User, ()> {
-        match sqlx::query("SELECT * FROM users WHERE github_id = ?1;")
-            .bind(github_id)
-            .fetch_one(&self.connection_pool)
-            .await
+    #[instrument(skip_all)]
+    pub async fn get_user_by_username(&self, username: &str) -> Result<User, ()> {
+        let _timer = metrics::time_query("get_user_by_username");
+
+        match sqlx::query(
+            "SELECT id, github_id, username, email, profile_picture_url, role, disabled, deletion_scheduled FROM users WHERE username = ?1;",
+        )
+        .bind(username)
+        .fetch_one(&self.connection_pool)
+        .await
         {
-            Ok(row) => {
-                let (id, github_id, username, email, profile_picture_url): (
-                    u32,
-                    u32,
-                    String,
-                    String,
-                    String,
-                ) = (row.get(0), row.get(1), row.get(2), row.get(3), row.get(4));
-
-                return Ok(User {
-                    id,
-                    github_id,
-                    username,
-                    email,
-                    profile_picture_url,
-                });
-            }
+            Ok(row) => Ok(Database::user_from_row(&row)),
             Err(err) => {
                 error!(
                     "Something went wrong while retrieving the user from the database: {}",
                     &err
                 );
-                return Err(());
+                Err(())
             }
-        };
+        }
+    }
+
+    fn user_from_row(row: &SqliteRow) -> User {
+        User {
+            id: row.get(0),
+            github_id: row.get(1),
+            username: row.get(2),
+            email: row.get(3),
+            profile_picture_url: row.get(4),
+            role: Role::parse(row.get(5)).unwrap_or(Role::User),
+            disabled: row.get(6),
+            deletion_scheduled: row
+                .get::<Option<String>, _>(7)
+                .and_then(|deletion| deletion.parse().ok()),
+        }
     }
 
-    pub async fn get_todo_items(&self, user_id: u32) -> Result<Vec<TodoItem>, sqlx::Error> {
-        sqlx::query_as::<_, TodoItem>("SELECT * FROM todo_items WHERE user_id = ?")
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_password_hash(&self, user_id: u32) -> Result<Option<String>, ()> {
+        let _timer = metrics::time_query("get_password_hash");
+
+        match sqlx::query("SELECT hash FROM user_passwords WHERE user_id = ?1;")
             .bind(user_id)
-            .fetch_all(&self.connection_pool)
+            .fetch_optional(&self.connection_pool)
             .await
+        {
+            Ok(row) => Ok(row.map(|row| row.get(0))),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the password hash from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
     }
 
-    pub async fn add_todo_item(&self, user_id: u32, content: String) -> Result<(), sqlx::Error> {
-        sqlx::query("INSERT INTO todo_items (content, done, user_id) VALUES (?, 0, ?)")
-            .bind(content)
-            .bind(user_id)
-            .execute(&self.connection_pool)
-            .await?;
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn set_password_hash(&self, user_id: u32, password_hash: String) -> Result<(), ()> {
+        let _timer = metrics::time_query("set_password_hash");
+
+        if let Err(err) = sqlx::query(
+            "INSERT INTO user_passwords (user_id, hash, updated) VALUES (?1, ?2, ?3) ON CONFLICT (user_id) DO UPDATE SET hash = excluded.hash, updated = excluded.updated;",
+        )
+        .bind(user_id)
+        .bind(password_hash)
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string(),
+        )
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while updating the password hash in the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
         Ok(())
     }
 
+    /// Only the items on `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_todo_items(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+    ) -> Result<Vec<TodoItem>, sqlx::Error> {
+        let _timer = metrics::time_query("get_todo_items");
+
+        sqlx::query_as::<_, TodoItem>(
+            "SELECT * FROM todo_items WHERE user_id = ?1 AND (?2 IS NULL OR list_id = ?2)",
+        )
+        .bind(user_id)
+        .bind(list_id)
+        .fetch_all(&self.connection_pool)
+        .await
+    }
+
+    /// The number of items of the user, on `list_id` when it is given, and
+    /// how many of them are done.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn count_todo_items(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+    ) -> Result<(u32, u32), sqlx::Error> {
+        let _timer = metrics::time_query("count_todo_items");
+
+        sqlx::query_as(
+            "SELECT COUNT(*), COALESCE(SUM(done), 0) FROM todo_items WHERE user_id = ?1 AND (?2 IS NULL OR list_id = ?2)",
+        )
+        .bind(user_id)
+        .bind(list_id)
+        .fetch_one(&self.connection_pool)
+        .await
+    }
+
+    /// `None` when the user already has `max_items` items, counted in the
+    /// same statement so concurrent requests can't go over.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn add_todo_item(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+        content: String,
+        max_items: u32,
+    ) -> Result<Option<TodoItem>, sqlx::Error> {
+        let _timer = metrics::time_query("add_todo_item");
+
+        sqlx::query_as::<_, TodoItem>(
+            "INSERT INTO todo_items (content, done, user_id, list_id)
+            SELECT ?1, 0, ?2, ?3
+            WHERE (SELECT COUNT(*) FROM todo_items WHERE user_id = ?2) < ?4
+            RETURNING *",
+        )
+        .bind(content)
+        .bind(user_id)
+        .bind(list_id)
+        .bind(max_items)
+        .fetch_optional(&self.connection_pool)
+        .await
+    }
+
+    /// `None` when the user has no such item, on `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
     pub async fn update_todo_item(
         &self,
         user_id: u32,
+        list_id: Option<u32>,
         id: u32,
         done: bool,
-    ) -> Result<(), sqlx::Error> {
-        sqlx::query("UPDATE todo_items SET done = ? WHERE id = ? AND user_id = ?")
-            .bind(done)
-            .bind(id)
-            .bind(user_id)
-            .execute(&self.connection_pool)
-            .await?;
-        Ok(())
+    ) -> Result<Option<TodoItem>, sqlx::Error> {
+        let _timer = metrics::time_query("update_todo_item");
+
+        sqlx::query_as::<_, TodoItem>(
+            "UPDATE todo_items SET done = ?1 WHERE id = ?2 AND user_id = ?3 AND (?4 IS NULL OR list_id = ?4) RETURNING *",
+        )
+        .bind(done)
+        .bind(id)
+        .bind(user_id)
+        .bind(list_id)
+        .fetch_optional(&self.connection_pool)
+        .await
     }
 
-    pub async fn delete_todo_item(&self, user_id: u32, id: u32) -> Result<(), sqlx::Error> {
-        sqlx::query("DELETE FROM todo_items WHERE id = ? AND user_id = ?")
-            .bind(id)
-            .bind(user_id)
-            .execute(&self.connection_pool)
-            .await?;
-        Ok(())
+    /// The deleted item, `None` when the user has no such item, on
+    /// `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_todo_item(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+        id: u32,
+    ) -> Result<Option<TodoItem>, sqlx::Error> {
+        let _timer = metrics::time_query("delete_todo_item");
+
+        sqlx::query_as::<_, TodoItem>(
+            "DELETE FROM todo_items WHERE id = ?1 AND user_id = ?2 AND (?3 IS NULL OR list_id = ?3) RETURNING *",
+        )
+        .bind(id)
+        .bind(user_id)
+        .bind(list_id)
+        .fetch_optional(&self.connection_pool)
+        .await
+    }
+}
+
+fn is_unique_violation(err: &sqlx::Error) -> bool {
+    err.as_database_error()
+        .is_some_and(|err| err.is_unique_violation())
+}
+
+/// A fresh database file for tests, removed when dropped.
+#[cfg(test)]
+pub struct TestDatabase(std::path::PathBuf);
+
+#[cfg(test)]
+impl TestDatabase {
+    pub async fn connect(name: &str) -> (Self, Database) {
+        let path =
+            std::env::temp_dir().join(format!("todo-app-{}-{}.sqlite3", std::process::id(), name));
+        let test_database = TestDatabase(path);
+        test_database.remove();
+
+        let database = Database::connect(test_database.0.to_str().unwrap(), 1)
+            .await
+            .unwrap();
+
+        (test_database, database)
+    }
+
+    fn remove(&self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let mut path = self.0.clone().into_os_string();
+            path.push(suffix);
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDatabase {
+    fn drop(&mut self) {
+        self.remove();
     }
 }
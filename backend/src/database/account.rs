@@ -0,0 +1,129 @@
+use sqlx::Row;
+use tracing::{error, instrument};
+
+use crate::Database;
+use crate::app::handlers::AuditEntry;
+use crate::metrics;
+
+impl Database {
+    /// The entries of actions taken by or on the user, oldest first.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_user_audit_entries(&self, user_id: u32) -> Result<Vec<AuditEntry>, ()> {
+        let _timer = metrics::time_query("get_user_audit_entries");
+
+        match sqlx::query(
+            "SELECT id, actor_id, action, target_id, details, ip, created FROM audit_log WHERE actor_id = ?1 OR target_id = ?1 ORDER BY id;",
+        )
+        .bind(user_id)
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| AuditEntry {
+                    id: row.get(0),
+                    actor_id: row.get(1),
+                    action: row.get(2),
+                    target_id: row.get(3),
+                    details: row.get(4),
+                    ip: row.get(5),
+                    created: row.get::<String, _>(6).parse().unwrap_or(0),
+                })
+                .collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the audit entries of the user from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// `None` cancels a scheduled deletion. Returns whether anything changed.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn schedule_user_deletion(
+        &self,
+        user_id: u32,
+        deletion: Option<u64>,
+    ) -> Result<bool, ()> {
+        let _timer = metrics::time_query("schedule_user_deletion");
+
+        match sqlx::query(
+            "UPDATE users SET deletion_scheduled = ?1 WHERE id = ?2 AND deletion_scheduled IS NOT ?1;",
+        )
+        .bind(deletion.map(|deletion| deletion.to_string()))
+        .bind(user_id)
+        .execute(&self.connection_pool)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while scheduling the deletion of the user in the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// The users whose scheduled deletion is due.
+    #[instrument(skip_all)]
+    pub async fn get_due_user_deletions(&self, now: u64) -> Result<Vec<u32>, ()> {
+        let _timer = metrics::time_query("get_due_user_deletions");
+
+        match sqlx::query(
+            "SELECT id FROM users WHERE deletion_scheduled IS NOT NULL AND CAST(deletion_scheduled AS INTEGER) <= ?1;",
+        )
+        .bind(now as i64)
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows.iter().map(|row| row.get(0)).collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the due user deletions from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Removes every row belonging to the user through the foreign keys.
+    /// Audit entries are kept for the other users involved, but no longer
+    /// point at the user and lose the IP address of the actions the user took.
+    /// Returns whether the user existed.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_user(&self, user_id: u32) -> Result<bool, ()> {
+        let _timer = metrics::time_query("delete_user");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+
+            sqlx::query("UPDATE audit_log SET ip = NULL WHERE actor_id = ?1;")
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            let deleted = sqlx::query("DELETE FROM users WHERE id = ?1;")
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?
+                .rows_affected();
+
+            transaction.commit().await?;
+
+            Ok::<_, sqlx::Error>(deleted > 0)
+        }
+        .await;
+
+        result.map_err(|err| {
+            error!(
+                "Something went wrong while deleting the user from the database: {}",
+                &err
+            )
+        })
+    }
+}
@@ -0,0 +1,275 @@
+use std::time::SystemTime;
+
+use sqlx::Row;
+use tracing::{error, instrument};
+
+use crate::app::handlers::{AdminStats, AuditEntry, User};
+use crate::metrics;
+use crate::{Database, Role};
+
+impl Database {
+    /// Users whose username or email address contains the query, or all
+    /// users without one.
+    #[instrument(skip_all)]
+    pub async fn search_users(
+        &self,
+        query: Option<&str>,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<User>, ()> {
+        let _timer = metrics::time_query("search_users");
+
+        let pattern = format!(
+            "%{}%",
+            query
+                .unwrap_or_default()
+                .replace('\\', "\\\\")
+                .replace('%', "\\%")
+                .replace('_', "\\_")
+        );
+
+        match sqlx::query(
+            "SELECT id, github_id, username, email, profile_picture_url, role, disabled, deletion_scheduled FROM users WHERE username LIKE ?1 ESCAPE '\\' OR email LIKE ?1 ESCAPE '\\' ORDER BY id LIMIT ?2 OFFSET ?3;",
+        )
+        .bind(pattern)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows.iter().map(Database::user_from_row).collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while searching the users in the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn stats(&self) -> Result<AdminStats, ()> {
+        let _timer = metrics::time_query("stats");
+
+        match sqlx::query(
+            "SELECT
+                (SELECT COUNT() FROM users),
+                (SELECT COUNT() FROM users WHERE disabled = 1),
+                (SELECT COUNT() FROM users WHERE role = 'admin'),
+                (SELECT COUNT() FROM user_sessions WHERE pending = 0 AND CAST(expires AS INTEGER) >= ?1),
+                (SELECT COUNT() FROM access_tokens),
+                (SELECT COUNT() FROM todo_items),
+                (SELECT COUNT() FROM todo_items WHERE done = 1);",
+        )
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        )
+        .fetch_one(&self.connection_pool)
+        .await
+        {
+            Ok(row) => Ok(AdminStats {
+                users: row.get(0),
+                disabled_users: row.get(1),
+                admins: row.get(2),
+                active_sessions: row.get(3),
+                access_tokens: row.get(4),
+                todo_items: row.get(5),
+                completed_todo_items: row.get(6),
+            }),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the stats from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Returns how many of the users exist and how many of them didn't have
+    /// the role yet.
+    #[instrument(skip_all)]
+    pub async fn set_role_by_github_id(
+        &self,
+        github_ids: &[u32],
+        role: Role,
+    ) -> Result<(u64, u64), ()> {
+        let _timer = metrics::time_query("set_role_by_github_id");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+            let mut existing = 0;
+            let mut updated = 0;
+
+            for github_id in github_ids {
+                let current: Option<String> =
+                    sqlx::query_scalar("SELECT role FROM users WHERE github_id = ?1;")
+                        .bind(github_id)
+                        .fetch_optional(&mut *transaction)
+                        .await?;
+
+                match current {
+                    Some(current) if current == role.as_str() => existing += 1,
+                    Some(_) => {
+                        sqlx::query("UPDATE users SET role = ?1 WHERE github_id = ?2;")
+                            .bind(role.as_str())
+                            .bind(github_id)
+                            .execute(&mut *transaction)
+                            .await?;
+                        existing += 1;
+                        updated += 1;
+                    }
+                    None => {}
+                }
+            }
+
+            transaction.commit().await?;
+
+            Ok::<_, sqlx::Error>((existing, updated))
+        }
+        .await;
+
+        result.map_err(|err| {
+            error!(
+                "Something went wrong while updating the role of the user in the database: {}",
+                &err
+            );
+        })
+    }
+
+    /// Returns whether the user exists. Disabling a user also ends all of its
+    /// sessions.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn set_user_disabled(&self, user_id: u32, disabled: bool) -> Result<bool, ()> {
+        let _timer = metrics::time_query("set_user_disabled");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+
+            let updated = sqlx::query("UPDATE users SET disabled = ?1 WHERE id = ?2;")
+                .bind(disabled)
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?
+                .rows_affected();
+
+            if disabled {
+                sqlx::query("DELETE FROM user_sessions WHERE user_id = ?1;")
+                    .bind(user_id)
+                    .execute(&mut *transaction)
+                    .await?;
+            }
+
+            transaction.commit().await?;
+
+            Ok::<_, sqlx::Error>(updated > 0)
+        }
+        .await;
+
+        result.map_err(|err| {
+            error!(
+                "Something went wrong while updating the disabled state of the user in the database: {}",
+                &err
+            )
+        })
+    }
+
+    /// Returns the number of sessions which were ended.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_user_sessions(&self, user_id: u32) -> Result<u64, ()> {
+        let _timer = metrics::time_query("delete_user_sessions");
+
+        match sqlx::query("DELETE FROM user_sessions WHERE user_id = ?1;")
+            .bind(user_id)
+            .execute(&self.connection_pool)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while deleting the sessions of the user from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn add_audit_entry(
+        &self,
+        actor_id: Option<u32>,
+        action: &str,
+        target_id: Option<u32>,
+        details: Option<String>,
+        ip: Option<&str>,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("add_audit_entry");
+
+        if let Err(err) = sqlx::query(
+            "INSERT INTO audit_log (actor_id, action, target_id, details, ip, created) VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+        )
+        .bind(actor_id)
+        .bind(action)
+        .bind(target_id)
+        .bind(details)
+        .bind(ip)
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string(),
+        )
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while inserting the audit entry into the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    /// The newest entries first.
+    #[instrument(skip_all)]
+    pub async fn get_audit_entries(&self, limit: u32, offset: u32) -> Result<Vec<AuditEntry>, ()> {
+        let _timer = metrics::time_query("get_audit_entries");
+
+        match sqlx::query(
+            "SELECT id, actor_id, action, target_id, details, ip, created FROM audit_log ORDER BY id DESC LIMIT ?1 OFFSET ?2;",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| AuditEntry {
+                    id: row.get(0),
+                    actor_id: row.get(1),
+                    action: row.get(2),
+                    target_id: row.get(3),
+                    details: row.get(4),
+                    ip: row.get(5),
+                    created: row.get::<String, _>(6).parse().unwrap_or(0),
+                })
+                .collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the audit entries from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+}
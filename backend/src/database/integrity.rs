@@ -0,0 +1,113 @@
+use std::collections::BTreeMap;
+
+use sqlx::Row;
+use tracing::{error, info, warn};
+
+use crate::Database;
+
+/// How rows pointing at users which no longer exist are repaired, by table.
+/// Such rows can only appear when the database was changed without foreign
+/// keys being enforced.
+const ORPHAN_REPAIRS: &[(&str, &[&str])] = &[
+    (
+        "todo_items",
+        &["DELETE FROM todo_items WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "user_sessions",
+        &["DELETE FROM user_sessions WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "user_passwords",
+        &["DELETE FROM user_passwords WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "webauthn_credentials",
+        &["DELETE FROM webauthn_credentials WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "webauthn_challenges",
+        &["DELETE FROM webauthn_challenges WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "user_totp",
+        &["DELETE FROM user_totp WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "totp_recovery_codes",
+        &["DELETE FROM totp_recovery_codes WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "access_tokens",
+        &["DELETE FROM access_tokens WHERE user_id NOT IN (SELECT id FROM users);"],
+    ),
+    (
+        "audit_log",
+        &[
+            "UPDATE audit_log SET actor_id = NULL WHERE actor_id NOT IN (SELECT id FROM users);",
+            "UPDATE audit_log SET target_id = NULL WHERE target_id NOT IN (SELECT id FROM users);",
+        ],
+    ),
+    (
+        "invite_codes",
+        &[
+            "UPDATE invite_codes SET created_by = NULL WHERE created_by NOT IN (SELECT id FROM users);",
+        ],
+    ),
+];
+
+impl Database {
+    /// Reports rows violating a foreign key and repairs them, so they can't
+    /// make later deletes or updates fail.
+    pub(super) async fn repair_orphans(&self) -> Result<(), ()> {
+        let violations = match sqlx::query("PRAGMA foreign_key_check;")
+            .fetch_all(&self.connection_pool)
+            .await
+        {
+            Ok(rows) => rows.iter().fold(BTreeMap::new(), |mut tables, row| {
+                *tables.entry(row.get::<String, _>(0)).or_insert(0) += 1;
+                tables
+            }),
+            Err(err) => {
+                error!(
+                    "Something went wrong while checking the foreign keys of the database: {}",
+                    &err
+                );
+                return Err(());
+            }
+        };
+
+        if violations.is_empty() {
+            info!("No orphaned rows found in the database");
+            return Ok(());
+        }
+
+        for (table, count) in &violations {
+            warn!("Found {} orphaned rows in {}", count, table);
+
+            let Some((_, statements)) = ORPHAN_REPAIRS
+                .iter()
+                .find(|(repairable, _)| repairable == table)
+            else {
+                error!(
+                    "Don't know how to repair the orphaned rows in {}, fix them by hand",
+                    table
+                );
+                return Err(());
+            };
+
+            for statement in statements.iter() {
+                if let Err(err) = sqlx::query(statement).execute(&self.connection_pool).await {
+                    error!(
+                        "Something went wrong while repairing the orphaned rows in {}: {}",
+                        table, &err
+                    );
+                    return Err(());
+                }
+            }
+        }
+
+        info!("Repaired the orphaned rows");
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use std::time::SystemTime;
+
+use sqlx::{Row, SqliteConnection};
+use tracing::{error, instrument};
+
+use crate::Database;
+use crate::app::handlers::InviteInfo;
+use crate::metrics;
+
+impl Database {
+    #[instrument(skip_all)]
+    pub async fn add_invite(
+        &self,
+        code_hash: String,
+        created_by: u32,
+        max_uses: u32,
+        expires: Option<u64>,
+    ) -> Result<u32, ()> {
+        let _timer = metrics::time_query("add_invite");
+
+        match sqlx::query(
+            "INSERT INTO invite_codes (code_hash, created_by, created, expires, max_uses) VALUES (?1, ?2, ?3, ?4, ?5);",
+        )
+        .bind(code_hash)
+        .bind(created_by)
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string(),
+        )
+        .bind(expires.map(|expires| expires.to_string()))
+        .bind(max_uses)
+        .execute(&self.connection_pool)
+        .await
+        {
+            Ok(row) => Ok(u32::try_from(row.last_insert_rowid()).unwrap()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while inserting the invite code into the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn get_invites(&self) -> Result<Vec<InviteInfo>, ()> {
+        let _timer = metrics::time_query("get_invites");
+
+        match sqlx::query(
+            "SELECT id, created_by, created, expires, max_uses, uses FROM invite_codes ORDER BY id DESC;",
+        )
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| InviteInfo {
+                    id: row.get(0),
+                    created_by: row.get(1),
+                    created: row.get::<String, _>(2).parse().unwrap_or(0),
+                    expires: row
+                        .get::<Option<String>, _>(3)
+                        .and_then(|expires| expires.parse().ok()),
+                    max_uses: row.get(4),
+                    uses: row.get(5),
+                })
+                .collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the invite codes from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Returns whether the invite code existed.
+    #[instrument(skip_all)]
+    pub async fn delete_invite(&self, id: u32) -> Result<bool, ()> {
+        let _timer = metrics::time_query("delete_invite");
+
+        match sqlx::query("DELETE FROM invite_codes WHERE id = ?1;")
+            .bind(id)
+            .execute(&self.connection_pool)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while deleting the invite code from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+}
+
+/// Counts a use of the invite code and returns whether it was still valid.
+/// Runs in the transaction that adds the user, so a failed sign-up doesn't
+/// spend the invite.
+pub(super) async fn use_invite(
+    connection: &mut SqliteConnection,
+    code_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE invite_codes SET uses = uses + 1 WHERE code_hash = ?1 AND uses < max_uses AND (expires IS NULL OR CAST(expires AS INTEGER) >= ?2);",
+    )
+    .bind(code_hash)
+    .bind(
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64,
+    )
+    .execute(connection)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{NewUser, TestDatabase};
+
+    #[actix_web::test]
+    async fn failed_sign_ups_dont_spend_the_invite() {
+        let (_test_database, database) = TestDatabase::connect("invites").await;
+        assert_eq!(
+            database
+                .add_local_user(
+                    String::from("alice"),
+                    String::from("alice@example.com"),
+                    String::new(),
+                    None,
+                )
+                .await,
+            Ok(NewUser::Added(1))
+        );
+        database
+            .add_invite(String::from("hash"), 1, 1, None)
+            .await
+            .unwrap();
+
+        let taken = database
+            .add_local_user(
+                String::from("alice"),
+                String::from("bob@example.com"),
+                String::new(),
+                Some(String::from("hash")),
+            )
+            .await;
+        assert_eq!(taken, Ok(NewUser::Taken));
+        assert_eq!(database.get_invites().await.unwrap()[0].uses, 0);
+
+        let added = database
+            .add_user(
+                7,
+                String::from("bob"),
+                String::from("bob@example.com"),
+                String::new(),
+                Some(String::from("hash")),
+            )
+            .await;
+        assert_eq!(added, Ok(NewUser::Added(2)));
+
+        let used_up = database
+            .add_local_user(
+                String::from("carol"),
+                String::from("carol@example.com"),
+                String::new(),
+                Some(String::from("hash")),
+            )
+            .await;
+        assert_eq!(used_up, Ok(NewUser::InvalidInvite));
+        assert!(!database.user_exists("carol", "").await.unwrap());
+    }
+}
@@ -0,0 +1,199 @@
+use tracing::instrument;
+
+use crate::Database;
+use crate::app::handlers::{TodoItem, TodoList};
+use crate::metrics;
+
+impl Database {
+    /// Only `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_todo_lists(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+    ) -> Result<Vec<TodoList>, sqlx::Error> {
+        let _timer = metrics::time_query("get_todo_lists");
+
+        sqlx::query_as::<_, TodoList>(
+            "SELECT * FROM todo_lists WHERE user_id = ?1 AND (?2 IS NULL OR id = ?2) ORDER BY id",
+        )
+        .bind(user_id)
+        .bind(list_id)
+        .fetch_all(&self.connection_pool)
+        .await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn todo_list_exists(&self, user_id: u32, id: u32) -> Result<bool, sqlx::Error> {
+        let _timer = metrics::time_query("todo_list_exists");
+
+        sqlx::query_scalar(
+            "SELECT EXISTS (SELECT 1 FROM todo_lists WHERE id = ?1 AND user_id = ?2)",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_one(&self.connection_pool)
+        .await
+    }
+
+    /// `None` when the user already has `max_lists` lists, counted in the
+    /// same statement like the items.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn add_todo_list(
+        &self,
+        user_id: u32,
+        name: String,
+        max_lists: u32,
+    ) -> Result<Option<TodoList>, sqlx::Error> {
+        let _timer = metrics::time_query("add_todo_list");
+
+        sqlx::query_as::<_, TodoList>(
+            "INSERT INTO todo_lists (name, user_id)
+            SELECT ?1, ?2
+            WHERE (SELECT COUNT(*) FROM todo_lists WHERE user_id = ?2) < ?3
+            RETURNING *",
+        )
+        .bind(name)
+        .bind(user_id)
+        .bind(max_lists)
+        .fetch_optional(&self.connection_pool)
+        .await
+    }
+
+    /// Deletes the list with its items and the tokens restricted to it.
+    /// Returns the deleted items, `None` when the user has no such list.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_todo_list(
+        &self,
+        user_id: u32,
+        id: u32,
+    ) -> Result<Option<Vec<TodoItem>>, sqlx::Error> {
+        let _timer = metrics::time_query("delete_todo_list");
+
+        let mut transaction = self.connection_pool.begin().await?;
+
+        let items = sqlx::query_as::<_, TodoItem>(
+            "DELETE FROM todo_items WHERE list_id = ?1 AND user_id = ?2 RETURNING *",
+        )
+        .bind(id)
+        .bind(user_id)
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        let result = sqlx::query("DELETE FROM todo_lists WHERE id = ?1 AND user_id = ?2")
+            .bind(id)
+            .bind(user_id)
+            .execute(&mut *transaction)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            return Ok(None);
+        }
+
+        transaction.commit().await?;
+
+        Ok(Some(items))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{NewUser, TestDatabase};
+
+    #[actix_web::test]
+    async fn items_outside_the_list_are_out_of_reach() {
+        let (_test_database, database) = TestDatabase::connect("lists").await;
+        let user = database
+            .add_local_user(
+                String::from("alice"),
+                String::from("alice@example.com"),
+                String::new(),
+                None,
+            )
+            .await;
+        assert_eq!(user, Ok(NewUser::Added(1)));
+
+        let work = database
+            .add_todo_list(1, String::from("Work"), 1)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(
+            database
+                .add_todo_list(1, String::from("Home"), 1)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        let list_id = Some(work.id as u32);
+
+        database
+            .add_todo_item(1, list_id, String::from("on the list"), 10)
+            .await
+            .unwrap()
+            .unwrap();
+        let loose = database
+            .add_todo_item(1, None, String::from("on no list"), 10)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(database.get_todo_items(1, list_id).await.unwrap().len(), 1);
+        assert_eq!(database.get_todo_items(1, None).await.unwrap().len(), 2);
+        assert!(
+            database
+                .update_todo_item(1, list_id, loose.id as u32, true)
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            database
+                .delete_todo_item(1, list_id, loose.id as u32)
+                .await
+                .unwrap()
+                .is_none()
+        );
+
+        database
+            .add_access_token(
+                1,
+                String::from("work"),
+                String::from("hash"),
+                vec![String::from("todo:read")],
+                None,
+                list_id,
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            database
+                .get_access_token(String::from("hash"))
+                .await
+                .unwrap()
+                .list_id,
+            list_id
+        );
+
+        let deleted = database
+            .delete_todo_list(1, work.id as u32)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(deleted.len(), 1);
+        assert!(
+            database
+                .get_access_token(String::from("hash"))
+                .await
+                .is_err()
+        );
+        assert_eq!(database.get_todo_items(1, None).await.unwrap().len(), 1);
+        assert!(
+            database
+                .delete_todo_list(1, work.id as u32)
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,53 @@
+use tracing::{error, instrument};
+
+use crate::Database;
+use crate::metrics;
+
+/// The number of rows removed by a sweep, by table.
+pub struct Swept {
+    pub sessions: u64,
+    pub oauth_states: u64,
+    pub webauthn_challenges: u64,
+    pub access_tokens: u64,
+}
+
+impl Database {
+    /// Removes every row which expired before `now`.
+    #[instrument(skip_all)]
+    pub async fn delete_expired(&self, now: u64) -> Result<Swept, ()> {
+        let _timer = metrics::time_query("delete_expired");
+
+        let mut counts = [0; 4];
+
+        for (count, query) in counts.iter_mut().zip([
+            "DELETE FROM user_sessions WHERE CAST(expires AS INTEGER) < ?1;",
+            "DELETE FROM csrf_tokens WHERE CAST(expires AS INTEGER) < ?1;",
+            "DELETE FROM webauthn_challenges WHERE CAST(expires AS INTEGER) < ?1;",
+            "DELETE FROM access_tokens WHERE expires IS NOT NULL AND CAST(expires AS INTEGER) < ?1;",
+        ]) {
+            match sqlx::query(query)
+                .bind(now as i64)
+                .execute(&self.connection_pool)
+                .await
+            {
+                Ok(result) => *count = result.rows_affected(),
+                Err(err) => {
+                    error!(
+                        "Something went wrong while deleting the expired rows from the database: {}",
+                        &err
+                    );
+                    return Err(());
+                }
+            }
+        }
+
+        let [sessions, oauth_states, webauthn_challenges, access_tokens] = counts;
+
+        Ok(Swept {
+            sessions,
+            oauth_states,
+            webauthn_challenges,
+            access_tokens,
+        })
+    }
+}
@@ -0,0 +1,121 @@
+//! Schema changes applied on top of the tables created by `Database::create`.
+//!
+//! Every entry is run inside its own transaction, after which SQLite's
+//! `user_version` is set to the entry's index plus one. Only append to this
+//! list, never edit an entry which has already been released.
+//!
+//! Foreign keys are enforced while migrating. Dropping a table other tables
+//! reference deletes or updates their rows as if every row was deleted, so
+//! parent tables can't be rebuilt like the child tables in migration 10.
+
+pub const MIGRATIONS: &[&[&str]] = &[
+    // 1: Local username/password accounts
+    &[
+        "CREATE TABLE users_new (id INTEGER PRIMARY KEY, github_id INTEGER UNIQUE, username TEXT NOT NULL UNIQUE, email TEXT NOT NULL UNIQUE, profile_picture_url TEXT);",
+        "INSERT INTO users_new (id, github_id, username, email, profile_picture_url) SELECT id, github_id, username, email, profile_picture_url FROM users;",
+        "DROP TABLE users;",
+        "ALTER TABLE users_new RENAME TO users;",
+        "CREATE TABLE user_passwords (user_id INTEGER PRIMARY KEY NOT NULL, hash TEXT NOT NULL, updated TEXT NOT NULL);",
+    ],
+    // 2: WebAuthn credentials and ceremony challenges
+    &[
+        "CREATE TABLE webauthn_credentials (id TEXT PRIMARY KEY NOT NULL, user_id INTEGER NOT NULL, name TEXT NOT NULL, public_key BLOB NOT NULL, sign_count INTEGER NOT NULL, created TEXT NOT NULL, last_used TEXT) WITHOUT ROWID;",
+        "CREATE INDEX webauthn_credentials_user_id ON webauthn_credentials (user_id);",
+        "CREATE TABLE webauthn_challenges (value TEXT PRIMARY KEY NOT NULL, user_id INTEGER, expires TEXT NOT NULL) WITHOUT ROWID;",
+    ],
+    // 3: TOTP two-factor authentication
+    &[
+        "CREATE TABLE user_totp (user_id INTEGER PRIMARY KEY NOT NULL, secret TEXT NOT NULL, enabled INTEGER NOT NULL, last_step INTEGER NOT NULL);",
+        "CREATE TABLE totp_recovery_codes (user_id INTEGER NOT NULL, hash TEXT NOT NULL, PRIMARY KEY (user_id, hash)) WITHOUT ROWID;",
+        "ALTER TABLE user_sessions ADD COLUMN pending INTEGER NOT NULL DEFAULT 0;",
+    ],
+    // 4: Session metadata, sessions get an id so they can be listed and
+    // revoked without exposing their value
+    &[
+        "CREATE TABLE user_sessions_new (id INTEGER PRIMARY KEY, user_id INTEGER NOT NULL, session TEXT NOT NULL UNIQUE, expires TEXT NOT NULL, pending INTEGER NOT NULL DEFAULT 0, created TEXT NOT NULL, last_seen TEXT NOT NULL, user_agent TEXT, ip TEXT);",
+        "INSERT INTO user_sessions_new (user_id, session, expires, pending, created, last_seen) SELECT user_id, session, expires, pending, CAST(expires AS INTEGER) - 21600, CAST(expires AS INTEGER) - 21600 FROM user_sessions;",
+        "DROP TABLE user_sessions;",
+        "ALTER TABLE user_sessions_new RENAME TO user_sessions;",
+        "CREATE INDEX user_sessions_user_id ON user_sessions (user_id);",
+    ],
+    // 5: Sessions are stored as a keyed hash of their value from now on, the
+    // existing plaintext ones can't be converted and are ended
+    &["DELETE FROM user_sessions;"],
+    // 6: Personal access tokens
+    &[
+        "CREATE TABLE access_tokens (id INTEGER PRIMARY KEY, user_id INTEGER NOT NULL, name TEXT NOT NULL, token_hash TEXT NOT NULL UNIQUE, scopes TEXT NOT NULL, created TEXT NOT NULL, expires TEXT, last_used TEXT);",
+        "CREATE INDEX access_tokens_user_id ON access_tokens (user_id);",
+    ],
+    // 7: Roles, disabled accounts and the audit trail of admin actions
+    &[
+        "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'user';",
+        "ALTER TABLE users ADD COLUMN disabled INTEGER NOT NULL DEFAULT 0;",
+        "CREATE TABLE audit_log (id INTEGER PRIMARY KEY, actor_id INTEGER, action TEXT NOT NULL, target_id INTEGER, details TEXT, ip TEXT, created TEXT NOT NULL);",
+        "CREATE INDEX audit_log_target_id ON audit_log (target_id);",
+    ],
+    // 8: Invite codes for invite-only sign-up
+    &[
+        "CREATE TABLE invite_codes (id INTEGER PRIMARY KEY, code_hash TEXT NOT NULL UNIQUE, created_by INTEGER, created TEXT NOT NULL, expires TEXT, max_uses INTEGER NOT NULL, uses INTEGER NOT NULL DEFAULT 0);",
+    ],
+    // 9: Account deletion after a grace period
+    &["ALTER TABLE users ADD COLUMN deletion_scheduled TEXT;"],
+    // 10: Foreign keys to the users, existing rows of deleted users are
+    // dropped or lose the reference
+    &[
+        "CREATE TABLE todo_items_new (id INTEGER PRIMARY KEY, content TEXT NOT NULL, done INTEGER NOT NULL, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE);",
+        "INSERT INTO todo_items_new SELECT id, content, done, user_id FROM todo_items WHERE user_id IN (SELECT id FROM users);",
+        "DROP TABLE todo_items;",
+        "ALTER TABLE todo_items_new RENAME TO todo_items;",
+        "CREATE INDEX todo_items_user_id ON todo_items (user_id);",
+        "CREATE TABLE user_sessions_new (id INTEGER PRIMARY KEY, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, session TEXT NOT NULL UNIQUE, expires TEXT NOT NULL, pending INTEGER NOT NULL DEFAULT 0, created TEXT NOT NULL, last_seen TEXT NOT NULL, user_agent TEXT, ip TEXT);",
+        "INSERT INTO user_sessions_new SELECT id, user_id, session, expires, pending, created, last_seen, user_agent, ip FROM user_sessions WHERE user_id IN (SELECT id FROM users);",
+        "DROP TABLE user_sessions;",
+        "ALTER TABLE user_sessions_new RENAME TO user_sessions;",
+        "CREATE INDEX user_sessions_user_id ON user_sessions (user_id);",
+        "CREATE TABLE user_passwords_new (user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE, hash TEXT NOT NULL, updated TEXT NOT NULL);",
+        "INSERT INTO user_passwords_new SELECT user_id, hash, updated FROM user_passwords WHERE user_id IN (SELECT id FROM users);",
+        "DROP TABLE user_passwords;",
+        "ALTER TABLE user_passwords_new RENAME TO user_passwords;",
+        "CREATE TABLE webauthn_credentials_new (id TEXT PRIMARY KEY NOT NULL, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, name TEXT NOT NULL, public_key BLOB NOT NULL, sign_count INTEGER NOT NULL, created TEXT NOT NULL, last_used TEXT) WITHOUT ROWID;",
+        "INSERT INTO webauthn_credentials_new SELECT id, user_id, name, public_key, sign_count, created, last_used FROM webauthn_credentials WHERE user_id IN (SELECT id FROM users);",
+        "DROP TABLE webauthn_credentials;",
+        "ALTER TABLE webauthn_credentials_new RENAME TO webauthn_credentials;",
+        "CREATE INDEX webauthn_credentials_user_id ON webauthn_credentials (user_id);",
+        "CREATE TABLE webauthn_challenges_new (value TEXT PRIMARY KEY NOT NULL, user_id INTEGER REFERENCES users (id) ON DELETE CASCADE, expires TEXT NOT NULL) WITHOUT ROWID;",
+        "INSERT INTO webauthn_challenges_new SELECT value, user_id, expires FROM webauthn_challenges WHERE user_id IS NULL OR user_id IN (SELECT id FROM users);",
+        "DROP TABLE webauthn_challenges;",
+        "ALTER TABLE webauthn_challenges_new RENAME TO webauthn_challenges;",
+        "CREATE TABLE user_totp_new (user_id INTEGER PRIMARY KEY NOT NULL REFERENCES users (id) ON DELETE CASCADE, secret TEXT NOT NULL, enabled INTEGER NOT NULL, last_step INTEGER NOT NULL);",
+        "INSERT INTO user_totp_new SELECT user_id, secret, enabled, last_step FROM user_totp WHERE user_id IN (SELECT id FROM users);",
+        "DROP TABLE user_totp;",
+        "ALTER TABLE user_totp_new RENAME TO user_totp;",
+        "CREATE TABLE totp_recovery_codes_new (user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, hash TEXT NOT NULL, PRIMARY KEY (user_id, hash)) WITHOUT ROWID;",
+        "INSERT INTO totp_recovery_codes_new SELECT user_id, hash FROM totp_recovery_codes WHERE user_id IN (SELECT id FROM users);",
+        "DROP TABLE totp_recovery_codes;",
+        "ALTER TABLE totp_recovery_codes_new RENAME TO totp_recovery_codes;",
+        "CREATE TABLE access_tokens_new (id INTEGER PRIMARY KEY, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE, name TEXT NOT NULL, token_hash TEXT NOT NULL UNIQUE, scopes TEXT NOT NULL, created TEXT NOT NULL, expires TEXT, last_used TEXT);",
+        "INSERT INTO access_tokens_new SELECT id, user_id, name, token_hash, scopes, created, expires, last_used FROM access_tokens WHERE user_id IN (SELECT id FROM users);",
+        "DROP TABLE access_tokens;",
+        "ALTER TABLE access_tokens_new RENAME TO access_tokens;",
+        "CREATE INDEX access_tokens_user_id ON access_tokens (user_id);",
+        "CREATE TABLE audit_log_new (id INTEGER PRIMARY KEY, actor_id INTEGER REFERENCES users (id) ON DELETE SET NULL, action TEXT NOT NULL, target_id INTEGER REFERENCES users (id) ON DELETE SET NULL, details TEXT, ip TEXT, created TEXT NOT NULL);",
+        "INSERT INTO audit_log_new SELECT id, (SELECT id FROM users WHERE id = actor_id), action, (SELECT id FROM users WHERE id = target_id), details, ip, created FROM audit_log;",
+        "DROP TABLE audit_log;",
+        "ALTER TABLE audit_log_new RENAME TO audit_log;",
+        "CREATE INDEX audit_log_actor_id ON audit_log (actor_id);",
+        "CREATE INDEX audit_log_target_id ON audit_log (target_id);",
+        "CREATE TABLE invite_codes_new (id INTEGER PRIMARY KEY, code_hash TEXT NOT NULL UNIQUE, created_by INTEGER REFERENCES users (id) ON DELETE SET NULL, created TEXT NOT NULL, expires TEXT, max_uses INTEGER NOT NULL, uses INTEGER NOT NULL DEFAULT 0);",
+        "INSERT INTO invite_codes_new SELECT id, code_hash, (SELECT id FROM users WHERE id = created_by), created, expires, max_uses, uses FROM invite_codes;",
+        "DROP TABLE invite_codes;",
+        "ALTER TABLE invite_codes_new RENAME TO invite_codes;",
+    ],
+    // 11: Todo lists, items can be put on one and personal access tokens
+    // restricted to one
+    &[
+        "CREATE TABLE todo_lists (id INTEGER PRIMARY KEY, name TEXT NOT NULL, user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE);",
+        "CREATE INDEX todo_lists_user_id ON todo_lists (user_id);",
+        "ALTER TABLE todo_items ADD COLUMN list_id INTEGER REFERENCES todo_lists (id) ON DELETE CASCADE;",
+        "CREATE INDEX todo_items_list_id ON todo_items (list_id);",
+        "ALTER TABLE access_tokens ADD COLUMN list_id INTEGER REFERENCES todo_lists (id) ON DELETE CASCADE;",
+    ],
+];
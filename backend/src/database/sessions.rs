@@ -0,0 +1,270 @@
+use std::time::SystemTime;
+
+use sqlx::Row;
+use tracing::{error, instrument};
+
+use crate::Database;
+use crate::app::handlers::SessionInfo;
+use crate::metrics;
+
+pub struct StoredSession {
+    pub user_id: u32,
+    /// Set while the session waits for a second factor.
+    pub pending: bool,
+    pub created: u64,
+    pub last_seen: u64,
+}
+
+impl Database {
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn add_session(
+        &self,
+        user_id: u32,
+        session_hash: String,
+        expires: u64,
+        pending: bool,
+        user_agent: Option<String>,
+        ip: String,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("add_session");
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            .to_string();
+
+        if let Err(err) = sqlx::query(
+            "INSERT INTO user_sessions (user_id, session, expires, pending, created, last_seen, user_agent, ip) VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6, ?7);",
+        )
+        .bind(user_id)
+        .bind(session_hash)
+        .bind(expires.to_string())
+        .bind(pending)
+        .bind(now)
+        .bind(user_agent)
+        .bind(ip)
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while inserting the session into the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    /// Looked up by the keyed hash, which can't be computed without the key,
+    /// so the timing of the lookup tells nothing about valid tokens.
+    #[instrument(skip_all)]
+    pub async fn get_session(&self, session_hash: String) -> Result<StoredSession, ()> {
+        let _timer = metrics::time_query("get_session");
+
+        match sqlx::query(
+            "SELECT user_id, expires, pending, created, last_seen FROM user_sessions WHERE session = ?1;",
+        )
+        .bind(&session_hash)
+        .fetch_one(&self.connection_pool)
+        .await
+        {
+            Ok(row) => {
+                let expires_str: String = row.get(1);
+                let expires_timestamp: u64 = expires_str.parse().unwrap_or(0);
+
+                if expires_timestamp
+                    < SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs()
+                {
+                    error!("The session has expired");
+
+                    self.delete_session(session_hash).await?;
+
+                    Err(())
+                } else {
+                    Ok(StoredSession {
+                        user_id: row.get(0),
+                        pending: row.get(2),
+                        created: row.get::<String, _>(3).parse().unwrap_or(0),
+                        last_seen: row.get::<String, _>(4).parse().unwrap_or(0),
+                    })
+                }
+            }
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the session from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Lists the sessions of the user which haven't expired, `current` marks
+    /// the one belonging to the given session value.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_sessions(
+        &self,
+        user_id: u32,
+        current_session_hash: String,
+    ) -> Result<Vec<SessionInfo>, ()> {
+        let _timer = metrics::time_query("get_sessions");
+
+        match sqlx::query(
+            "SELECT id, created, last_seen, expires, user_agent, ip, session = ?2 FROM user_sessions WHERE user_id = ?1 AND pending = 0 AND CAST(expires AS INTEGER) >= ?3 ORDER BY CAST(last_seen AS INTEGER) DESC;",
+        )
+        .bind(user_id)
+        .bind(current_session_hash)
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs() as i64,
+        )
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| SessionInfo {
+                    id: row.get(0),
+                    created: row.get::<String, _>(1).parse().unwrap_or(0),
+                    last_seen: row.get::<String, _>(2).parse().unwrap_or(0),
+                    expires: row.get::<String, _>(3).parse().unwrap_or(0),
+                    user_agent: row.get(4),
+                    ip: row.get(5),
+                    current: row.get(6),
+                })
+                .collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the sessions from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Updates the last seen time and, for sliding expiration, the expiry.
+    #[instrument(skip_all)]
+    pub async fn touch_session(
+        &self,
+        session_hash: String,
+        last_seen: u64,
+        expires: Option<u64>,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("touch_session");
+
+        if let Err(err) = sqlx::query(
+            "UPDATE user_sessions SET last_seen = ?1, expires = COALESCE(?2, expires) WHERE session = ?3;",
+        )
+        .bind(last_seen.to_string())
+        .bind(expires.map(|expires| expires.to_string()))
+        .bind(session_hash)
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while updating the session in the database: {}",
+                &err
+            );
+            return Err(());
+        };
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
+    pub async fn complete_session(&self, session_hash: String, expires: u64) -> Result<(), ()> {
+        let _timer = metrics::time_query("complete_session");
+
+        if let Err(err) =
+            sqlx::query("UPDATE user_sessions SET pending = 0, expires = ?1 WHERE session = ?2;")
+                .bind(expires.to_string())
+                .bind(session_hash)
+                .execute(&self.connection_pool)
+                .await
+        {
+            error!(
+                "Something went wrong while completing the session in the database: {}",
+                &err
+            );
+            return Err(());
+        };
+
+        Ok(())
+    }
+
+    /// Returns whether the session existed.
+    #[instrument(skip_all)]
+    pub async fn delete_session(&self, session_hash: String) -> Result<bool, ()> {
+        let _timer = metrics::time_query("delete_session");
+
+        match sqlx::query("DELETE FROM user_sessions WHERE session = ?1;")
+            .bind(session_hash)
+            .execute(&self.connection_pool)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while deleting the session from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Returns whether a session of the user was deleted.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_session_by_id(&self, user_id: u32, id: u32) -> Result<bool, ()> {
+        let _timer = metrics::time_query("delete_session_by_id");
+
+        match sqlx::query("DELETE FROM user_sessions WHERE id = ?1 AND user_id = ?2;")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.connection_pool)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while deleting the session from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_other_sessions(
+        &self,
+        user_id: u32,
+        session_hash: String,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("delete_other_sessions");
+
+        if let Err(err) =
+            sqlx::query("DELETE FROM user_sessions WHERE user_id = ?1 AND session != ?2;")
+                .bind(user_id)
+                .bind(session_hash)
+                .execute(&self.connection_pool)
+                .await
+        {
+            error!(
+                "Something went wrong while deleting the other sessions from the database: {}",
+                &err
+            );
+            return Err(());
+        };
+
+        Ok(())
+    }
+}
@@ -0,0 +1,195 @@
+use std::time::SystemTime;
+
+use sqlx::Row;
+use tracing::{error, instrument};
+
+use crate::Database;
+use crate::app::handlers::AccessTokenInfo;
+use crate::metrics;
+
+pub struct StoredToken {
+    pub id: u32,
+    pub user_id: u32,
+    pub scopes: Vec<String>,
+    pub last_used: Option<u64>,
+    pub list_id: Option<u32>,
+}
+
+impl Database {
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn add_access_token(
+        &self,
+        user_id: u32,
+        name: String,
+        token_hash: String,
+        scopes: Vec<String>,
+        expires: Option<u64>,
+        list_id: Option<u32>,
+    ) -> Result<u32, ()> {
+        let _timer = metrics::time_query("add_access_token");
+
+        match sqlx::query(
+            "INSERT INTO access_tokens (user_id, name, token_hash, scopes, created, expires, list_id) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7);",
+        )
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .bind(scopes.join(" "))
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string(),
+        )
+        .bind(expires.map(|expires| expires.to_string()))
+        .bind(list_id)
+        .execute(&self.connection_pool)
+        .await
+        {
+            Ok(row) => Ok(u32::try_from(row.last_insert_rowid()).unwrap()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while inserting the access token into the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Looked up by the keyed hash, like sessions.
+    #[instrument(skip_all)]
+    pub async fn get_access_token(&self, token_hash: String) -> Result<StoredToken, ()> {
+        let _timer = metrics::time_query("get_access_token");
+
+        match sqlx::query(
+            "SELECT id, user_id, scopes, expires, last_used, list_id FROM access_tokens WHERE token_hash = ?1;",
+        )
+        .bind(token_hash)
+        .fetch_one(&self.connection_pool)
+        .await
+        {
+            Ok(row) => {
+                let expires: Option<u64> = row
+                    .get::<Option<String>, _>(3)
+                    .and_then(|expires| expires.parse().ok());
+
+                if expires.is_some_and(|expires| {
+                    expires
+                        < SystemTime::now()
+                            .duration_since(SystemTime::UNIX_EPOCH)
+                            .unwrap()
+                            .as_secs()
+                }) {
+                    error!("The access token has expired");
+                    return Err(());
+                }
+
+                Ok(StoredToken {
+                    id: row.get(0),
+                    user_id: row.get(1),
+                    scopes: row
+                        .get::<String, _>(2)
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect(),
+                    last_used: row
+                        .get::<Option<String>, _>(4)
+                        .and_then(|last_used| last_used.parse().ok()),
+                    list_id: row.get(5),
+                })
+            }
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the access token from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_access_tokens(&self, user_id: u32) -> Result<Vec<AccessTokenInfo>, ()> {
+        let _timer = metrics::time_query("get_access_tokens");
+
+        match sqlx::query(
+            "SELECT id, name, scopes, created, expires, last_used, list_id FROM access_tokens WHERE user_id = ?1 ORDER BY id;",
+        )
+        .bind(user_id)
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| AccessTokenInfo {
+                    id: row.get(0),
+                    name: row.get(1),
+                    scopes: row
+                        .get::<String, _>(2)
+                        .split_whitespace()
+                        .map(String::from)
+                        .collect(),
+                    created: row.get::<String, _>(3).parse().unwrap_or(0),
+                    expires: row
+                        .get::<Option<String>, _>(4)
+                        .and_then(|expires| expires.parse().ok()),
+                    last_used: row
+                        .get::<Option<String>, _>(5)
+                        .and_then(|last_used| last_used.parse().ok()),
+                    list_id: row.get(6),
+                })
+                .collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the access tokens from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn touch_access_token(&self, id: u32, last_used: u64) -> Result<(), ()> {
+        let _timer = metrics::time_query("touch_access_token");
+
+        if let Err(err) = sqlx::query("UPDATE access_tokens SET last_used = ?1 WHERE id = ?2;")
+            .bind(last_used.to_string())
+            .bind(id)
+            .execute(&self.connection_pool)
+            .await
+        {
+            error!(
+                "Something went wrong while updating the access token in the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether a token of the user was deleted.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_access_token(&self, user_id: u32, id: u32) -> Result<bool, ()> {
+        let _timer = metrics::time_query("delete_access_token");
+
+        match sqlx::query("DELETE FROM access_tokens WHERE id = ?1 AND user_id = ?2;")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.connection_pool)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while deleting the access token from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use sqlx::Row;
+use tracing::{error, instrument};
+
+use crate::Database;
+use crate::metrics;
+
+impl Database {
+    /// Returns the secret, whether it is enabled and the last used time step.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_totp(&self, user_id: u32) -> Result<Option<(String, bool, u64)>, ()> {
+        let _timer = metrics::time_query("get_totp");
+
+        match sqlx::query("SELECT secret, enabled, last_step FROM user_totp WHERE user_id = ?1;")
+            .bind(user_id)
+            .fetch_optional(&self.connection_pool)
+            .await
+        {
+            Ok(row) => Ok(row.map(|row| (row.get(0), row.get(1), row.get::<i64, _>(2) as u64))),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the TOTP secret from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn totp_enabled(&self, user_id: u32) -> Result<bool, ()> {
+        let _timer = metrics::time_query("totp_enabled");
+
+        Ok(matches!(self.get_totp(user_id).await?, Some((_, true, _))))
+    }
+
+    /// Stores a secret which isn't enabled until a code for it is verified.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn set_totp_secret(&self, user_id: u32, secret: String) -> Result<(), ()> {
+        let _timer = metrics::time_query("set_totp_secret");
+
+        if let Err(err) = sqlx::query(
+            "INSERT INTO user_totp (user_id, secret, enabled, last_step) VALUES (?1, ?2, 0, 0) ON CONFLICT (user_id) DO UPDATE SET secret = excluded.secret, enabled = 0, last_step = 0;",
+        )
+        .bind(user_id)
+        .bind(secret)
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while inserting the TOTP secret into the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn enable_totp(
+        &self,
+        user_id: u32,
+        last_step: u64,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("enable_totp");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+
+            sqlx::query("UPDATE user_totp SET enabled = 1, last_step = ?1 WHERE user_id = ?2;")
+                .bind(last_step as i64)
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            Database::insert_recovery_codes(&mut transaction, user_id, recovery_code_hashes)
+                .await?;
+
+            transaction.commit().await
+        }
+        .await;
+
+        if let Err(err) = result {
+            error!(
+                "Something went wrong while enabling TOTP in the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn set_totp_last_step(&self, user_id: u32, last_step: u64) -> Result<(), ()> {
+        let _timer = metrics::time_query("set_totp_last_step");
+
+        if let Err(err) = sqlx::query("UPDATE user_totp SET last_step = ?1 WHERE user_id = ?2;")
+            .bind(last_step as i64)
+            .bind(user_id)
+            .execute(&self.connection_pool)
+            .await
+        {
+            error!(
+                "Something went wrong while updating the TOTP time step in the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_totp(&self, user_id: u32) -> Result<(), ()> {
+        let _timer = metrics::time_query("delete_totp");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+
+            sqlx::query("DELETE FROM user_totp WHERE user_id = ?1;")
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?1;")
+                .bind(user_id)
+                .execute(&mut *transaction)
+                .await?;
+
+            transaction.commit().await
+        }
+        .await;
+
+        if let Err(err) = result {
+            error!(
+                "Something went wrong while deleting TOTP from the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn replace_recovery_codes(
+        &self,
+        user_id: u32,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("replace_recovery_codes");
+
+        let result = async {
+            let mut transaction = self.connection_pool.begin().await?;
+            Database::insert_recovery_codes(&mut transaction, user_id, recovery_code_hashes)
+                .await?;
+            transaction.commit().await
+        }
+        .await;
+
+        if let Err(err) = result {
+            error!(
+                "Something went wrong while replacing the recovery codes in the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the recovery code and returns whether it existed.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn use_recovery_code(&self, user_id: u32, hash: &str) -> Result<bool, ()> {
+        let _timer = metrics::time_query("use_recovery_code");
+
+        match sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?1 AND hash = ?2;")
+            .bind(user_id)
+            .bind(hash)
+            .execute(&self.connection_pool)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while using the recovery code in the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    async fn insert_recovery_codes(
+        transaction: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+        user_id: u32,
+        recovery_code_hashes: Vec<String>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM totp_recovery_codes WHERE user_id = ?1;")
+            .bind(user_id)
+            .execute(&mut **transaction)
+            .await?;
+
+        for hash in recovery_code_hashes {
+            sqlx::query("INSERT INTO totp_recovery_codes (user_id, hash) VALUES (?1, ?2);")
+                .bind(user_id)
+                .bind(hash)
+                .execute(&mut **transaction)
+                .await?;
+        }
+
+        Ok(())
+    }
+}
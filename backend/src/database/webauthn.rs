@@ -0,0 +1,257 @@
+use std::time::SystemTime;
+
+use sqlx::Row;
+use tracing::{error, instrument};
+
+use crate::Database;
+use crate::logic::{NewCredential, StoredCredential};
+use crate::metrics;
+
+impl Database {
+    #[instrument(skip_all)]
+    pub async fn add_webauthn_challenge(
+        &self,
+        challenge: &str,
+        user_id: Option<u32>,
+        expires: u64,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("add_webauthn_challenge");
+
+        if let Err(err) = sqlx::query(
+            "INSERT INTO webauthn_challenges (value, user_id, expires) VALUES (?1, ?2, ?3);",
+        )
+        .bind(challenge)
+        .bind(user_id)
+        .bind(expires.to_string())
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while inserting the WebAuthn challenge into the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the challenge, so it can only be used once, and returns the
+    /// user it was issued for.
+    #[instrument(skip_all)]
+    pub async fn take_webauthn_challenge(&self, challenge: &str) -> Result<Option<u32>, ()> {
+        let _timer = metrics::time_query("take_webauthn_challenge");
+
+        match sqlx::query(
+            "DELETE FROM webauthn_challenges WHERE value = ?1 RETURNING user_id, expires;",
+        )
+        .bind(challenge)
+        .fetch_one(&self.connection_pool)
+        .await
+        {
+            Ok(row) => {
+                let expires_str: String = row.get(1);
+                let expires_timestamp: u64 = expires_str.parse().unwrap_or(0);
+
+                if expires_timestamp
+                    < SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                        .as_secs()
+                {
+                    error!("The WebAuthn challenge has expired");
+                    return Err(());
+                }
+
+                Ok(row.get(0))
+            }
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the WebAuthn challenge from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn add_webauthn_credential(
+        &self,
+        user_id: u32,
+        name: String,
+        credential: NewCredential,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("add_webauthn_credential");
+
+        if let Err(err) = sqlx::query(
+            "INSERT INTO webauthn_credentials (id, user_id, name, public_key, sign_count, created) VALUES (?1, ?2, ?3, ?4, ?5, ?6);",
+        )
+        .bind(credential.id)
+        .bind(user_id)
+        .bind(name)
+        .bind(credential.public_key)
+        .bind(credential.sign_count)
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string(),
+        )
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while inserting the WebAuthn credential into the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    /// Returns the owner, public key and signature counter of a credential.
+    #[instrument(skip_all)]
+    pub async fn get_webauthn_credential(&self, id: &str) -> Result<(u32, Vec<u8>, u32), ()> {
+        let _timer = metrics::time_query("get_webauthn_credential");
+
+        match sqlx::query(
+            "SELECT user_id, public_key, sign_count FROM webauthn_credentials WHERE id = ?1;",
+        )
+        .bind(id)
+        .fetch_one(&self.connection_pool)
+        .await
+        {
+            Ok(row) => Ok((row.get(0), row.get(1), row.get(2))),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the WebAuthn credential from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_webauthn_credentials(
+        &self,
+        user_id: u32,
+    ) -> Result<Vec<StoredCredential>, ()> {
+        let _timer = metrics::time_query("get_webauthn_credentials");
+
+        match sqlx::query(
+            "SELECT id, name, created, last_used FROM webauthn_credentials WHERE user_id = ?1 ORDER BY created;",
+        )
+        .bind(user_id)
+        .fetch_all(&self.connection_pool)
+        .await
+        {
+            Ok(rows) => Ok(rows
+                .iter()
+                .map(|row| StoredCredential {
+                    id: row.get(0),
+                    name: row.get(1),
+                    created: row.get::<String, _>(2).parse().unwrap_or(0),
+                    last_used: row
+                        .get::<Option<String>, _>(3)
+                        .and_then(|last_used| last_used.parse().ok()),
+                })
+                .collect()),
+            Err(err) => {
+                error!(
+                    "Something went wrong while retrieving the WebAuthn credentials from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    #[instrument(skip_all)]
+    pub async fn update_webauthn_credential_usage(
+        &self,
+        id: &str,
+        sign_count: u32,
+    ) -> Result<(), ()> {
+        let _timer = metrics::time_query("update_webauthn_credential_usage");
+
+        if let Err(err) = sqlx::query(
+            "UPDATE webauthn_credentials SET sign_count = ?1, last_used = ?2 WHERE id = ?3;",
+        )
+        .bind(sign_count)
+        .bind(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                .to_string(),
+        )
+        .bind(id)
+        .execute(&self.connection_pool)
+        .await
+        {
+            error!(
+                "Something went wrong while updating the WebAuthn credential in the database: {}",
+                &err
+            );
+            return Err(());
+        }
+
+        Ok(())
+    }
+
+    /// Returns whether a credential of the user was renamed.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn rename_webauthn_credential(
+        &self,
+        user_id: u32,
+        id: &str,
+        name: String,
+    ) -> Result<bool, ()> {
+        let _timer = metrics::time_query("rename_webauthn_credential");
+
+        match sqlx::query(
+            "UPDATE webauthn_credentials SET name = ?1 WHERE id = ?2 AND user_id = ?3;",
+        )
+        .bind(name)
+        .bind(id)
+        .bind(user_id)
+        .execute(&self.connection_pool)
+        .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while renaming the WebAuthn credential in the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+
+    /// Returns whether a credential of the user was deleted.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_webauthn_credential(&self, user_id: u32, id: &str) -> Result<bool, ()> {
+        let _timer = metrics::time_query("delete_webauthn_credential");
+
+        match sqlx::query("DELETE FROM webauthn_credentials WHERE id = ?1 AND user_id = ?2;")
+            .bind(id)
+            .bind(user_id)
+            .execute(&self.connection_pool)
+            .await
+        {
+            Ok(result) => Ok(result.rows_affected() > 0),
+            Err(err) => {
+                error!(
+                    "Something went wrong while deleting the WebAuthn credential from the database: {}",
+                    &err
+                );
+                Err(())
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Logs go to stdout, human readable or as JSON. Every request runs in a
+//! `request` span carrying its id, route and user, and the `Logic` and
+//! `Database` methods it calls get spans of their own, so an error logged
+//! deep down can be tied to the request which caused it. The same spans
+//! can be exported over OTLP, see `telemetry`.
+
+use tracing::error;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, fmt};
+
+use crate::config::{LogFormat, LoggingConfig, OtlpConfig};
+use crate::telemetry::{self, Telemetry};
+
+/// An invalid filter falls back to `info`, validating the configuration
+/// reports it afterwards. Fails when spans should be exported but can't be,
+/// after which logging works nonetheless.
+pub fn init(config: &LoggingConfig, otlp: &OtlpConfig) -> Result<Telemetry, ()> {
+    let filter = EnvFilter::try_new(&config.filter).unwrap_or_else(|_| EnvFilter::new("info"));
+    let (exporter, telemetry) = match telemetry::init(otlp) {
+        Ok((exporter, telemetry)) => (exporter, Ok(telemetry)),
+        Err(err) => (None, Err(err)),
+    };
+    let registry = tracing_subscriber::registry().with(filter).with(exporter);
+
+    match config.format {
+        LogFormat::Pretty => registry.with(fmt::layer()).init(),
+        LogFormat::Json => registry
+            .with(
+                fmt::layer()
+                    .json()
+                    .with_current_span(false)
+                    .with_span_list(true),
+            )
+            .init(),
+    }
+
+    telemetry.map_err(|err| error!("{}", err))
+}
@@ -1,4 +1,22 @@
+mod account;
+mod admin;
 mod auth;
 mod core;
+mod sweeper;
+mod todo;
 
-pub use core::Logic;
+pub use account::AccountError;
+pub use admin::AdminError;
+pub use auth::admission::AdmissionError;
+pub use auth::github::GitHubError;
+pub use auth::local::LocalAuthError;
+pub use auth::principal::{Principal, Role};
+pub use auth::session::ClientInfo;
+pub use auth::token::{Credential, Scope};
+pub use auth::totp::TotpError;
+pub use auth::webauthn::{
+    AuthenticationCredential, NewCredential, RegistrationCredential, StoredCredential,
+};
+pub use core::{Logic, NewSession};
+pub use sweeper::Sweeper;
+pub use todo::{TodoChange, TodoError};
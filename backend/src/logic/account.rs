@@ -0,0 +1,220 @@
+//! ```sh
+//! ACCOUNT_DELETION_GRACE_DAYS=14 make run_release
+//! ```
+//!
+//! Users can export everything stored about them and delete their account.
+//!
+//! Deleting needs a confirmation token, requested separately, which only
+//! works for the same session and expires after a few minutes. Without a
+//! grace period the account is deleted right away, with one it is only
+//! scheduled and keeps working until then, so the user can still change
+//! their mind.
+
+use subtle::ConstantTimeEq;
+
+use crate::config::AccountsConfig;
+use crate::logic::auth::session::SessionConfig;
+
+/// How long a deletion confirmation token can be used.
+pub const CONFIRMATION_SECS: u64 = 60 * 10;
+
+/// Bumped whenever the layout of the data export changes.
+pub const EXPORT_VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum AccountError {
+    InvalidConfirmation,
+    Internal,
+}
+
+impl From<()> for AccountError {
+    fn from(_: ()) -> Self {
+        AccountError::Internal
+    }
+}
+
+pub struct AccountDeletion {
+    pub grace_secs: u64,
+}
+
+impl AccountDeletion {
+    pub fn new(config: &AccountsConfig) -> Self {
+        AccountDeletion {
+            grace_secs: config.deletion_grace_days * 60 * 60 * 24,
+        }
+    }
+
+    /// A token confirming the deletion, bound to the session it was requested
+    /// with.
+    pub fn confirmation_token(
+        &self,
+        sessions: &SessionConfig,
+        session_hash: &str,
+        expires: u64,
+    ) -> String {
+        format!(
+            "{}.{}",
+            expires,
+            sessions.hash_token(&format!("delete:{}:{}", session_hash, expires))
+        )
+    }
+
+    pub fn verify_confirmation_token(
+        &self,
+        sessions: &SessionConfig,
+        session_hash: &str,
+        token: &str,
+        now: u64,
+    ) -> bool {
+        let Some(expires) = token
+            .split_once('.')
+            .and_then(|(expires, _)| expires.parse::<u64>().ok())
+        else {
+            return false;
+        };
+
+        expires >= now
+            && self
+                .confirmation_token(sessions, session_hash, expires)
+                .as_bytes()
+                .ct_eq(token.as_bytes())
+                .into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccountDeletion, AccountError};
+    use crate::config::{AccountsConfig, Config, SessionsConfig};
+    use crate::database::{NewUser, TestDatabase};
+    use crate::logic::auth::session::SessionConfig;
+    use crate::{Logic, Principal, Role, Scope};
+
+    const NOW: u64 = 1_000_000;
+
+    fn session(session: &str) -> Principal {
+        Principal {
+            user_id: 1,
+            roles: vec![Role::User],
+            scopes: Scope::ALL.to_vec(),
+            session: Some(session.to_string()),
+            token_id: None,
+            list_id: None,
+        }
+    }
+
+    async fn logic(name: &str, deletion_grace_days: u64) -> (TestDatabase, Logic) {
+        let (test_database, database) = TestDatabase::connect(name).await;
+        let user = database
+            .add_local_user(
+                String::from("alice"),
+                String::from("alice@example.com"),
+                String::from("password hash"),
+                None,
+            )
+            .await;
+        assert_eq!(user, Ok(NewUser::Added(1)));
+
+        let config = Config {
+            accounts: AccountsConfig {
+                deletion_grace_days,
+            },
+            ..Config::default()
+        };
+
+        (test_database, Logic::new(database, &config))
+    }
+
+    #[test]
+    fn confirmations_are_bound_to_their_session_and_expire() {
+        let deletion = AccountDeletion::new(&AccountsConfig::default());
+        let sessions = SessionConfig::new(&SessionsConfig::default());
+        let token = deletion.confirmation_token(&sessions, "session", NOW + 60);
+
+        assert!(deletion.verify_confirmation_token(&sessions, "session", &token, NOW));
+        assert!(!deletion.verify_confirmation_token(&sessions, "other", &token, NOW));
+        assert!(!deletion.verify_confirmation_token(&sessions, "session", &token, NOW + 61));
+
+        let (_, signature) = token.split_once('.').unwrap();
+        let extended = format!("{}.{}", NOW + 3600, signature);
+        assert!(!deletion.verify_confirmation_token(&sessions, "session", &extended, NOW));
+        assert!(!deletion.verify_confirmation_token(&sessions, "session", "", NOW));
+    }
+
+    #[actix_web::test]
+    async fn accounts_are_deleted_right_away_without_a_grace_period() {
+        let (_test_database, logic) = logic("account-delete", 0).await;
+        let principal = session("session");
+        logic.add_item(1, None, "Buy milk").await.unwrap();
+
+        assert!(matches!(
+            logic.delete_account(&principal, "1.abc", "192.0.2.1").await,
+            Err(AccountError::InvalidConfirmation)
+        ));
+
+        let (token, _) = logic.request_account_deletion(&principal);
+        assert!(matches!(
+            logic
+                .delete_account(&session("other"), &token, "192.0.2.1")
+                .await,
+            Err(AccountError::InvalidConfirmation)
+        ));
+        assert!(matches!(
+            logic.delete_account(&principal, &token, "192.0.2.1").await,
+            Ok(None)
+        ));
+        assert!(logic.get_user(1).await.is_err());
+        assert_eq!(logic.count_items(1, None).await, Ok((0, 0)));
+    }
+
+    #[actix_web::test]
+    async fn scheduled_deletions_keep_their_date_until_cancelled() {
+        let (_test_database, logic) = logic("account-schedule", 14).await;
+        let principal = session("session");
+        let (token, _) = logic.request_account_deletion(&principal);
+
+        let Ok(Some(deletion)) = logic.delete_account(&principal, &token, "192.0.2.1").await else {
+            panic!("The deletion wasn't scheduled");
+        };
+        assert_eq!(
+            logic.get_user(1).await.unwrap().deletion_scheduled,
+            Some(deletion)
+        );
+        assert!(matches!(
+            logic.delete_account(&principal, &token, "192.0.2.1").await,
+            Ok(Some(again)) if again == deletion
+        ));
+
+        assert_eq!(
+            logic.cancel_account_deletion(&principal, "192.0.2.1").await,
+            Ok(true)
+        );
+        assert_eq!(logic.get_user(1).await.unwrap().deletion_scheduled, None);
+        assert_eq!(
+            logic.cancel_account_deletion(&principal, "192.0.2.1").await,
+            Ok(false)
+        );
+    }
+
+    #[actix_web::test]
+    async fn exports_leave_out_secrets() {
+        let (_test_database, logic) = logic("account-export", 0).await;
+        let list = logic.add_list(1, "Groceries").await.unwrap();
+        logic
+            .add_item(1, Some(list.id as u32), "Buy milk")
+            .await
+            .unwrap();
+
+        let export = logic
+            .export_data(&session("session"), "192.0.2.1")
+            .await
+            .unwrap();
+        assert_eq!(export.user.username, "alice");
+        assert_eq!(export.todo_items.len(), 1);
+        assert_eq!(export.todo_lists.len(), 1);
+
+        let json = serde_json::to_string(&export).unwrap();
+        assert!(!json.contains("password hash"));
+        assert!(json.contains("account.export"));
+    }
+}
@@ -0,0 +1,26 @@
+//! ```sh
+//! ADMIN_GITHUB_IDS=583231,9919 make run_release
+//! ```
+//!
+//! The accounts of the listed GitHub users are made admins when the server
+//! starts, so they have to sign in with GitHub before then. Usernames aren't
+//! used, a local account could be registered under a listed name which
+//! isn't taken yet. Admins can't be demoted through the API, remove them
+//! from the list and change their role in the database instead.
+
+/// The most users or audit entries returned at once.
+pub const MAX_PAGE_SIZE: u32 = 200;
+
+#[derive(Debug)]
+pub enum AdminError {
+    NotFound,
+    /// Admins can't disable or log out themselves.
+    OwnAccount,
+    Internal,
+}
+
+impl From<()> for AdminError {
+    fn from(_: ()) -> Self {
+        AdminError::Internal
+    }
+}
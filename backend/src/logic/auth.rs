@@ -1 +1,9 @@
+pub mod admission;
 pub mod github;
+pub mod local;
+pub mod principal;
+pub mod session;
+pub mod throttle;
+pub mod token;
+pub mod totp;
+pub mod webauthn;
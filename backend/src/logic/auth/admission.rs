@@ -0,0 +1,258 @@
+//! ```sh
+//! GITHUB_ALLOWED_ORGS=acme GITHUB_ALLOWED_TEAMS=acme/backend,acme/frontend ALLOWED_EMAIL_DOMAINS=acme.com INVITE_ONLY=true make run_release
+//! ```
+//!
+//! Who may sign in. Without any of the variables everyone can.
+//!
+//! With allowed organizations or teams, GitHub sign-ins need to be a member
+//! of at least one of them, checked on every sign-in, so leaving the
+//! organization locks the account out.
+//!
+//! With allowed email domains the account's email address has to be in one
+//! of them, for GitHub the primary address also has to be verified.
+//!
+//! Local accounts can't prove a membership and their email address isn't
+//! verified, so while organizations, teams or email domains are set they
+//! can only sign up with an invite code, or when `ALLOW_LOCAL_SIGNUP` says
+//! checking the domain of the address they give is enough.
+//!
+//! In invite-only mode new accounts need an invite code created by an admin,
+//! so the first admin has to sign up before the mode is turned on.
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::config::AdmissionConfig;
+
+const INVITE_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+#[derive(Debug)]
+pub enum AdmissionError {
+    NotAMember,
+    EmailNotVerified,
+    EmailDomain,
+    InviteRequired,
+    InvalidInvite,
+    LocalSignupClosed,
+}
+
+pub struct Admission {
+    github_orgs: Vec<String>,
+    github_teams: Vec<String>,
+    email_domains: Vec<String>,
+    pub invite_only: bool,
+    allow_local_signup: bool,
+}
+
+impl Admission {
+    pub fn new(config: &AdmissionConfig) -> Self {
+        Admission {
+            github_orgs: lowercase(&config.github_allowed_orgs),
+            github_teams: lowercase(&config.github_allowed_teams),
+            email_domains: lowercase(&config.allowed_email_domains),
+            invite_only: config.invite_only,
+            allow_local_signup: config.allow_local_signup,
+        }
+    }
+
+    /// Whether GitHub sign-ins need their memberships checked.
+    pub fn requires_membership(&self) -> bool {
+        !self.github_orgs.is_empty() || !self.github_teams.is_empty()
+    }
+
+    /// Takes the lowercased organizations and `org/team` slugs of the user.
+    pub fn check_membership(
+        &self,
+        orgs: &[String],
+        teams: &[String],
+    ) -> Result<(), AdmissionError> {
+        if !self.requires_membership()
+            || self.github_orgs.iter().any(|org| orgs.contains(org))
+            || self.github_teams.iter().any(|team| teams.contains(team))
+        {
+            Ok(())
+        } else {
+            Err(AdmissionError::NotAMember)
+        }
+    }
+
+    /// Invite codes are checked when the account is created.
+    pub fn check_local_signup(&self) -> Result<(), AdmissionError> {
+        let has_rules = self.requires_membership() || !self.email_domains.is_empty();
+
+        if has_rules && !self.invite_only && !self.allow_local_signup {
+            Err(AdmissionError::LocalSignupClosed)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn check_email(&self, email: &str, verified: bool) -> Result<(), AdmissionError> {
+        if self.email_domains.is_empty() {
+            return Ok(());
+        }
+
+        if !verified {
+            return Err(AdmissionError::EmailNotVerified);
+        }
+
+        let domain = email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default();
+
+        if self.email_domains.contains(&domain) {
+            Ok(())
+        } else {
+            Err(AdmissionError::EmailDomain)
+        }
+    }
+}
+
+pub fn new_invite_code() -> String {
+    let mut rng = rand::rng();
+
+    let code: String = (0..16)
+        .map(|_| INVITE_CODE_ALPHABET[rng.random_range(0..INVITE_CODE_ALPHABET.len())] as char)
+        .collect();
+
+    format!(
+        "{}-{}-{}-{}",
+        &code[..4],
+        &code[4..8],
+        &code[8..12],
+        &code[12..]
+    )
+}
+
+/// Invite codes are random enough that a plain SHA-256 is sufficient.
+pub fn hash_invite_code(code: &str) -> String {
+    let normalized: String = code
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect();
+
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn lowercase(entries: &[String]) -> Vec<String> {
+    entries
+        .iter()
+        .map(|entry| entry.trim().to_lowercase())
+        .filter(|entry| !entry.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Admission, AdmissionError, hash_invite_code, new_invite_code};
+    use crate::config::AdmissionConfig;
+
+    fn strings(entries: &[&str]) -> Vec<String> {
+        entries.iter().map(|entry| entry.to_string()).collect()
+    }
+
+    #[test]
+    fn everyone_is_admitted_without_rules() {
+        let admission = Admission::new(&AdmissionConfig::default());
+
+        assert!(!admission.requires_membership());
+        assert!(admission.check_membership(&[], &[]).is_ok());
+        assert!(
+            admission
+                .check_email("anyone@anywhere.example", false)
+                .is_ok()
+        );
+        assert!(admission.check_local_signup().is_ok());
+    }
+
+    #[test]
+    fn members_of_an_allowed_org_or_team_are_admitted() {
+        let admission = Admission::new(&AdmissionConfig {
+            github_allowed_orgs: strings(&[" Acme "]),
+            github_allowed_teams: strings(&["other/Backend", ""]),
+            ..AdmissionConfig::default()
+        });
+
+        assert!(admission.requires_membership());
+        assert!(admission.check_membership(&strings(&["acme"]), &[]).is_ok());
+        assert!(
+            admission
+                .check_membership(&strings(&["other"]), &strings(&["other/backend"]))
+                .is_ok()
+        );
+        assert!(matches!(
+            admission.check_membership(&strings(&["other"]), &strings(&["other/frontend"])),
+            Err(AdmissionError::NotAMember)
+        ));
+    }
+
+    #[test]
+    fn email_domains_need_a_verified_address() {
+        let admission = Admission::new(&AdmissionConfig {
+            allowed_email_domains: strings(&["acme.com"]),
+            ..AdmissionConfig::default()
+        });
+
+        assert!(admission.check_email("alice@ACME.com", true).is_ok());
+        assert!(matches!(
+            admission.check_email("alice@acme.com", false),
+            Err(AdmissionError::EmailNotVerified)
+        ));
+        assert!(matches!(
+            admission.check_email("alice@acme.com.evil.example", true),
+            Err(AdmissionError::EmailDomain)
+        ));
+        assert!(matches!(
+            admission.check_email("alice", true),
+            Err(AdmissionError::EmailDomain)
+        ));
+    }
+
+    #[test]
+    fn local_sign_up_closes_under_rules() {
+        let rules = || AdmissionConfig {
+            allowed_email_domains: strings(&["acme.com"]),
+            ..AdmissionConfig::default()
+        };
+
+        assert!(matches!(
+            Admission::new(&rules()).check_local_signup(),
+            Err(AdmissionError::LocalSignupClosed)
+        ));
+        assert!(
+            Admission::new(&AdmissionConfig {
+                invite_only: true,
+                ..rules()
+            })
+            .check_local_signup()
+            .is_ok()
+        );
+        assert!(
+            Admission::new(&AdmissionConfig {
+                allow_local_signup: true,
+                ..rules()
+            })
+            .check_local_signup()
+            .is_ok()
+        );
+    }
+
+    #[test]
+    fn invite_codes_hash_regardless_of_formatting() {
+        let code = new_invite_code();
+        assert_eq!(code.len(), 19);
+        assert_ne!(code, new_invite_code());
+
+        assert_eq!(
+            hash_invite_code(&code),
+            hash_invite_code(&format!(" {} ", code.replace('-', "").to_uppercase()))
+        );
+        assert_ne!(hash_invite_code(&code), hash_invite_code(""));
+    }
+}
@@ -1,18 +1,27 @@
 //! ```sh
 //! GITHUB_CLIENT_ID=xxx GITHUB_CLIENT_SECRET=yyy make run_release
 //! ```
-
-use std::env;
+//!
+//! GitHub sign-in is turned off without a client ID and secret.
 
 use oauth2::basic::BasicClient;
 use oauth2::reqwest;
 use oauth2::url::Url;
 use oauth2::{
-    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, RedirectUrl, Scope,
-    TokenResponse, TokenUrl,
+    AuthUrl, AuthorizationCode, ClientId, ClientSecret, CsrfToken, EndpointNotSet, EndpointSet,
+    RedirectUrl, Scope, TokenResponse, TokenUrl,
 };
 use serde::Deserialize;
-use tracing::error;
+use serde::de::DeserializeOwned;
+use tracing::{Instrument, Span, error, info_span};
+
+use crate::config::GitHubConfig;
+use crate::logic::auth::admission::AdmissionError;
+
+const TOKEN_URL: &str = "https://github.com/login/oauth/access_token";
+const API_URL: &str = "https://api.github.com/";
+/// Caps the organizations and teams read to 1000 each.
+const MAX_PAGES: usize = 10;
 
 #[derive(Deserialize)]
 struct GitHubUser {
@@ -25,129 +34,312 @@ struct GitHubUser {
 struct GitHubEmail {
     email: String,
     primary: bool,
+    verified: bool,
 }
 
-// TODO: Make struct instance for the client
-
-pub fn init() -> (Url, CsrfToken) {
-    let github_client_id = ClientId::new(
-        env::var("GITHUB_CLIENT_ID").expect("Missing the GITHUB_CLIENT_ID environment variable."),
-    );
-    let github_client_secret = ClientSecret::new(
-        env::var("GITHUB_CLIENT_SECRET")
-            .expect("Missing the GITHUB_CLIENT_SECRET environment variable."),
-    );
-    let auth_url = AuthUrl::new("https://github.com/login/oauth/authorize".to_string())
-        .expect("Invalid authorization endpoint URL");
-    let token_url = TokenUrl::new("https://github.com/login/oauth/access_token".to_string())
-        .expect("Invalid token endpoint URL");
-
-    // Set up the config for the Github OAuth2 process.
-    let client = BasicClient::new(github_client_id)
-        .set_client_secret(github_client_secret)
-        .set_auth_uri(auth_url)
-        .set_token_uri(token_url)
-        .set_redirect_uri(
-            RedirectUrl::new(String::from("https://todo.celarye.dev"))
-                .expect("Invalid redirect URL"),
-        );
+#[derive(Deserialize)]
+struct GitHubOrg {
+    login: String,
+}
+
+#[derive(Deserialize)]
+struct GitHubTeam {
+    slug: String,
+    organization: GitHubOrg,
+}
+
+pub struct GitHubProfile {
+    pub id: u32,
+    pub login: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub avatar_url: String,
+    /// Only fetched when asked for, lowercased.
+    pub orgs: Vec<String>,
+    /// `org/team` slugs, only fetched when asked for, lowercased.
+    pub teams: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum GitHubError {
+    Unauthorized,
+    Rejected(AdmissionError),
+    /// Another account uses the primary email address of the new one.
+    EmailTaken,
+}
 
-    // Generate the authorization URL to which we'll redirect the user.
-    client
-        .authorize_url(CsrfToken::new_random)
-        .add_scope(Scope::new("read:user".to_string()))
-        .add_scope(Scope::new("user:email".to_string()))
-        .url()
+impl From<()> for GitHubError {
+    fn from(_: ()) -> Self {
+        GitHubError::Unauthorized
+    }
 }
 
-pub async fn success(code: String) -> Result<(u32, String, String, String), ()> {
-    let github_client_id = ClientId::new(
-        env::var("GITHUB_CLIENT_ID").expect("Missing the GITHUB_CLIENT_ID environment variable."),
-    );
-    let github_client_secret = ClientSecret::new(
-        env::var("GITHUB_CLIENT_SECRET")
-            .expect("Missing the GITHUB_CLIENT_SECRET environment variable."),
-    );
-    let auth_url = AuthUrl::new("https://github.com/login/oauth/authorize".to_string())
-        .expect("Invalid authorization endpoint URL");
-    let token_url = TokenUrl::new("https://github.com/login/oauth/access_token".to_string())
-        .expect("Invalid token endpoint URL");
-
-    let client = BasicClient::new(github_client_id)
-        .set_client_secret(github_client_secret)
-        .set_auth_uri(auth_url)
-        .set_token_uri(token_url)
-        .set_redirect_uri(
-            RedirectUrl::new(String::from("https://todo.celarye.dev"))
+impl From<AdmissionError> for GitHubError {
+    fn from(err: AdmissionError) -> Self {
+        GitHubError::Rejected(err)
+    }
+}
+
+pub struct GitHub {
+    client_id: ClientId,
+    client_secret: ClientSecret,
+    redirect_url: RedirectUrl,
+}
+
+impl GitHub {
+    /// `None` when no client is configured, which turns GitHub sign-in off.
+    /// The configuration has to be validated already.
+    pub fn new(config: &GitHubConfig) -> Option<Self> {
+        Some(GitHub {
+            client_id: ClientId::new(config.client_id.clone()?),
+            client_secret: ClientSecret::new(config.client_secret.as_ref()?.expose().to_string()),
+            redirect_url: RedirectUrl::new(config.redirect_url.clone())
                 .expect("Invalid redirect URL"),
-        );
+        })
+    }
+
+    fn client(
+        &self,
+    ) -> BasicClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointSet> {
+        let auth_url = AuthUrl::new("https://github.com/login/oauth/authorize".to_string())
+            .expect("Invalid authorization endpoint URL");
+        let token_url = TokenUrl::new(TOKEN_URL.to_string()).expect("Invalid token endpoint URL");
+
+        // Set up the config for the Github OAuth2 process.
+        BasicClient::new(self.client_id.clone())
+            .set_client_secret(self.client_secret.clone())
+            .set_auth_uri(auth_url)
+            .set_token_uri(token_url)
+            .set_redirect_uri(self.redirect_url.clone())
+    }
+
+    /// With `read_org` the organization and team memberships of the user can
+    /// be read, which admission rules may need.
+    pub fn init(&self, read_org: bool) -> (Url, CsrfToken) {
+        // Generate the authorization URL to which we'll redirect the user.
+        let client = self.client();
+        let mut request = client
+            .authorize_url(CsrfToken::new_random)
+            .add_scope(Scope::new("read:user".to_string()))
+            .add_scope(Scope::new("user:email".to_string()));
 
-    let http_client = reqwest::ClientBuilder::new()
-        // Following redirects opens the client up to SSRF vulnerabilities.
-        .redirect(reqwest::redirect::Policy::none())
-        .build()
-        .expect("Client should build");
-
-    // Exchange the code with a token.
-    let token_res = client
-        .exchange_code(AuthorizationCode::new(code))
-        .request_async(&http_client)
-        .await;
-
-    let Ok(token) = token_res else {
-        error!("No access token returned by GitHub");
-        return Err(());
-    };
-
-    let access_token = token.access_token().secret();
-
-    let p_user_res = http_client
-        .get("https://api.github.com/user")
-        .header("User-Agent", "celarye-todo-app")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send()
-        .await;
-
-    let user = match p_user_res {
-        Ok(user_res) => match user_res.json::<GitHubUser>().await {
-            Ok(user) => user,
+        if read_org {
+            request = request.add_scope(Scope::new("read:org".to_string()));
+        }
+
+        request.url()
+    }
+
+    pub async fn success(
+        &self,
+        code: String,
+        fetch_memberships: bool,
+    ) -> Result<GitHubProfile, ()> {
+        let client = self.client();
+
+        let http_client = reqwest::ClientBuilder::new()
+            // Following redirects opens the client up to SSRF vulnerabilities.
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .expect("Client should build");
+
+        // Exchange the code with a token.
+        let token_res = client
+            .exchange_code(AuthorizationCode::new(code))
+            .request_async(&http_client)
+            .instrument(request_span("POST", TOKEN_URL))
+            .await;
+
+        let Ok(token) = token_res else {
+            error!("No access token returned by GitHub");
+            return Err(());
+        };
+
+        let access_token = token.access_token().secret();
+
+        let p_user_res = http_client
+            .get("https://api.github.com/user")
+            .header("User-Agent", "celarye-todo-app")
+            .header("Authorization", format!("Bearer {}", access_token))
+            .send()
+            .instrument(request_span("GET", "https://api.github.com/user"))
+            .await;
+
+        let user = match p_user_res {
+            Ok(user_res) => match user_res.json::<GitHubUser>().await {
+                Ok(user) => user,
+                Err(err) => {
+                    error!("Failed to fetch the GitHub user profile: {}", err);
+                    return Err(());
+                }
+            },
             Err(err) => {
                 error!("Failed to fetch the GitHub user profile: {}", err);
                 return Err(());
             }
-        },
-        Err(err) => {
-            error!("Failed to fetch the GitHub user profile: {}", err);
-            return Err(());
-        }
-    };
-
-    let p_emails_res = http_client
-        .get("https://api.github.com/user/emails")
-        .header("User-Agent", "celarye-todo-app")
-        .header("Authorization", format!("Bearer {}", access_token))
-        .send()
-        .await;
-
-    let emails = match p_emails_res {
-        Ok(email_res) => match email_res.json::<Vec<GitHubEmail>>().await {
-            Ok(emails) => emails,
+        };
+
+        let p_emails_res = http_client
+            .get("https://api.github.com/user/emails")
+            .header("User-Agent", "celarye-todo-app")
+            .header("Authorization", format!("Bearer {}", access_token))
+            .send()
+            .instrument(request_span("GET", "https://api.github.com/user/emails"))
+            .await;
+
+        let emails = match p_emails_res {
+            Ok(email_res) => match email_res.json::<Vec<GitHubEmail>>().await {
+                Ok(emails) => emails,
+                Err(err) => {
+                    error!("Failed to fetch the GitHub email: {}", err);
+                    return Err(());
+                }
+            },
             Err(err) => {
                 error!("Failed to fetch the GitHub email: {}", err);
                 return Err(());
             }
-        },
-        Err(err) => {
-            error!("Failed to fetch the GitHub email: {}", err);
+        };
+
+        let (primary_email, email_verified) = emails
+            .into_iter()
+            .find(|e| e.primary)
+            .map(|e| (e.email, e.verified))
+            .unwrap_or_else(|| {
+                (
+                    String::from("https://avatars.githubusercontent.com/u/96624179"),
+                    false,
+                )
+            });
+
+        let (orgs, teams) = if fetch_memberships {
+            let orgs: Vec<GitHubOrg> = fetch(
+                &http_client,
+                access_token,
+                "https://api.github.com/user/orgs",
+            )
+            .await?;
+            let teams: Vec<GitHubTeam> = fetch(
+                &http_client,
+                access_token,
+                "https://api.github.com/user/teams",
+            )
+            .await?;
+
+            (
+                orgs.into_iter()
+                    .map(|org| org.login.to_lowercase())
+                    .collect(),
+                teams
+                    .into_iter()
+                    .map(|team| format!("{}/{}", team.organization.login, team.slug).to_lowercase())
+                    .collect(),
+            )
+        } else {
+            (Vec::new(), Vec::new())
+        };
+
+        Ok(GitHubProfile {
+            id: user.id,
+            login: user.login,
+            email: primary_email,
+            email_verified,
+            avatar_url: user.avatar_url,
+            orgs,
+            teams,
+        })
+    }
+}
+
+/// Follows the `Link` header through every page of a list endpoint, up to
+/// `MAX_PAGES`. Only GitHub API URLs are followed as redirects are.
+async fn fetch<T: DeserializeOwned>(
+    http_client: &reqwest::Client,
+    access_token: &str,
+    url: &str,
+) -> Result<Vec<T>, ()> {
+    let mut entries = Vec::new();
+    let mut next = Some(format!("{}?per_page=100", url));
+    let mut pages = 0;
+
+    while let Some(page_url) = next.take() {
+        if pages == MAX_PAGES {
+            error!("{} has more than {} pages on GitHub", url, MAX_PAGES);
             return Err(());
         }
-    };
+        pages += 1;
+
+        let response = http_client
+            .get(&page_url)
+            .header("User-Agent", "celarye-todo-app")
+            .header("Authorization", format!("Bearer {}", access_token))
+            .send()
+            .instrument(request_span("GET", url))
+            .await
+            .map_err(|err| {
+                error!("Failed to fetch {} from GitHub: {}", url, err);
+            })?;
 
-    let primary_email = emails
-        .into_iter()
-        .find(|e| e.primary)
-        .map(|e| e.email)
-        .unwrap_or_else(|| String::from("https://avatars.githubusercontent.com/u/96624179"));
+        next = response
+            .headers()
+            .get(reqwest::header::LINK)
+            .and_then(|link| link.to_str().ok())
+            .and_then(next_page)
+            .filter(|next| next.starts_with(API_URL));
+
+        match response.json::<Vec<T>>().await {
+            Ok(page) => entries.extend(page),
+            Err(err) => {
+                error!("Failed to fetch {} from GitHub: {}", url, err);
+                return Err(());
+            }
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Picks the `rel="next"` target out of a `Link` header.
+fn next_page(link: &str) -> Option<String> {
+    link.split(',').find_map(|entry| {
+        let (target, params) = entry.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == r#"rel="next""#)
+            .then(|| {
+                target
+                    .trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_string()
+            })
+    })
+}
+
+/// Exported as a client span, the access token is never recorded.
+fn request_span(method: &str, url: &str) -> Span {
+    info_span!(
+        "github_request",
+        otel.kind = "client",
+        http.request.method = method,
+        url.full = url,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::next_page;
+
+    #[test]
+    fn the_next_page_is_taken_from_the_link_header() {
+        let link = r#"<https://api.github.com/user/teams?page=1>; rel="prev", <https://api.github.com/user/teams?page=3>; rel="next", <https://api.github.com/user/teams?page=5>; rel="last""#;
+        assert_eq!(
+            next_page(link).as_deref(),
+            Some("https://api.github.com/user/teams?page=3")
+        );
 
-    Ok((user.id, user.login, primary_email, user.avatar_url))
+        let last = r#"<https://api.github.com/user/teams?page=4>; rel="prev", <https://api.github.com/user/teams?page=1>; rel="first""#;
+        assert_eq!(next_page(last), None);
+        assert_eq!(next_page(""), None);
+    }
 }
@@ -0,0 +1,370 @@
+//! ```sh
+//! ARGON2_MEMORY_KIB=19456 ARGON2_ITERATIONS=2 ARGON2_PARALLELISM=1 make run_release
+//! ```
+
+use std::time::Duration;
+
+use argon2::password_hash::{
+    PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng,
+};
+use argon2::{Algorithm, Argon2, Params, Version};
+use tracing::error;
+
+use crate::config::Argon2Config;
+use crate::logic::auth::admission::AdmissionError;
+use crate::logic::auth::throttle::Throttle;
+
+const MIN_PASSWORD_LENGTH: usize = 12;
+const MAX_PASSWORD_LENGTH: usize = 128;
+
+const COMMON_PASSWORDS: &[&str] = &[
+    "123456789012",
+    "password1234",
+    "passwordpassword",
+    "qwertyuiopas",
+    "iloveyou1234",
+    "letmeinletmein",
+    "correcthorsebatterystaple",
+];
+
+#[derive(Debug)]
+pub enum LocalAuthError {
+    InvalidCredentials,
+    Throttled(Duration),
+    InvalidUsername,
+    InvalidEmail,
+    WeakPassword(Vec<&'static str>),
+    UserExists,
+    NoPassword,
+    Disabled,
+    Rejected(AdmissionError),
+    Internal,
+}
+
+impl From<()> for LocalAuthError {
+    fn from(_: ()) -> Self {
+        LocalAuthError::Internal
+    }
+}
+
+impl From<AdmissionError> for LocalAuthError {
+    fn from(err: AdmissionError) -> Self {
+        LocalAuthError::Rejected(err)
+    }
+}
+
+pub struct LocalAuth {
+    argon2: Argon2<'static>,
+    dummy_hash: String,
+    pub account_throttle: Throttle,
+    pub ip_throttle: Throttle,
+}
+
+impl LocalAuth {
+    /// The parameters have to be validated already.
+    pub fn new(config: &Argon2Config) -> Self {
+        let params = Params::new(
+            config.memory_kib,
+            config.iterations,
+            config.parallelism,
+            None,
+        )
+        .expect("Invalid Argon2 parameters");
+
+        let argon2 = Argon2::new(Algorithm::Argon2id, Version::V0x13, params);
+
+        // Verified against when the username doesn't exist, so that the
+        // response time doesn't reveal which accounts exist.
+        let dummy_hash = argon2
+            .hash_password(b"dummy password", &SaltString::generate(&mut OsRng))
+            .expect("Hashing a static password should not fail")
+            .to_string();
+
+        LocalAuth {
+            argon2,
+            dummy_hash,
+            account_throttle: Throttle::new(
+                5,
+                Duration::from_secs(15 * 60),
+                Duration::from_secs(60),
+            ),
+            ip_throttle: Throttle::new(
+                20,
+                Duration::from_secs(15 * 60),
+                Duration::from_secs(5 * 60),
+            ),
+        }
+    }
+
+    pub async fn hash(&self, password: String) -> Result<String, ()> {
+        let argon2 = self.argon2.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            argon2
+                .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+                .map(|hash| hash.to_string())
+        })
+        .await;
+
+        match result {
+            Ok(Ok(hash)) => Ok(hash),
+            Ok(Err(err)) => {
+                error!("Something went wrong while hashing the password: {}", err);
+                Err(())
+            }
+            Err(err) => {
+                error!("The password hashing task failed: {}", err);
+                Err(())
+            }
+        }
+    }
+
+    /// Verifies the password against the stored hash, or against a dummy
+    /// hash when there is none.
+    pub async fn verify(&self, password: String, hash: Option<String>) -> Result<bool, ()> {
+        let argon2 = self.argon2.clone();
+        let has_hash = hash.is_some();
+        let hash = hash.unwrap_or_else(|| self.dummy_hash.clone());
+
+        let result = tokio::task::spawn_blocking(move || {
+            // The parameters stored in the hash are used, so hashes created
+            // with older parameters keep working.
+            PasswordHash::new(&hash)
+                .map(|hash| argon2.verify_password(password.as_bytes(), &hash).is_ok())
+        })
+        .await;
+
+        match result {
+            Ok(Ok(valid)) => Ok(valid && has_hash),
+            Ok(Err(err)) => {
+                error!("The stored password hash is invalid: {}", err);
+                Err(())
+            }
+            Err(err) => {
+                error!("The password verification task failed: {}", err);
+                Err(())
+            }
+        }
+    }
+}
+
+pub fn check_username(username: &str) -> Result<(), LocalAuthError> {
+    if (3..=39).contains(&username.chars().count())
+        && username
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        Ok(())
+    } else {
+        Err(LocalAuthError::InvalidUsername)
+    }
+}
+
+pub fn check_email(email: &str) -> Result<(), LocalAuthError> {
+    match email.split_once('@') {
+        Some((local, domain))
+            if !local.is_empty()
+                && domain.contains('.')
+                && !domain.starts_with('.')
+                && !domain.ends_with('.')
+                && email.len() <= 254
+                && !email.chars().any(|c| c.is_whitespace()) =>
+        {
+            Ok(())
+        }
+        _ => Err(LocalAuthError::InvalidEmail),
+    }
+}
+
+pub fn check_password_strength(
+    username: &str,
+    email: &str,
+    password: &str,
+) -> Result<(), LocalAuthError> {
+    let mut reasons = Vec::new();
+    let length = password.chars().count();
+    let lowercase = password.to_lowercase();
+
+    if length < MIN_PASSWORD_LENGTH {
+        reasons.push("The password must be at least 12 characters long");
+    }
+
+    if length > MAX_PASSWORD_LENGTH {
+        reasons.push("The password must be at most 128 characters long");
+    }
+
+    let mut distinct: Vec<char> = password.chars().collect();
+    distinct.sort_unstable();
+    distinct.dedup();
+    if distinct.len() < 5 {
+        reasons.push("The password must contain at least 5 different characters");
+    }
+
+    let email_local = email.split('@').next().unwrap_or_default().to_lowercase();
+    if lowercase.contains(&username.to_lowercase())
+        || (email_local.len() >= 3 && lowercase.contains(&email_local))
+    {
+        reasons.push("The password must not contain the username or email address");
+    }
+
+    if COMMON_PASSWORDS.contains(&lowercase.as_str()) {
+        reasons.push("The password is too common");
+    }
+
+    if reasons.is_empty() {
+        Ok(())
+    } else {
+        Err(LocalAuthError::WeakPassword(reasons))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LocalAuth, LocalAuthError, check_email, check_password_strength, check_username};
+    use crate::config::{Argon2Config, Config};
+    use crate::database::TestDatabase;
+    use crate::{ClientInfo, Logic};
+
+    /// The smallest parameters Argon2 accepts, hashing is slow enough as it
+    /// is in debug builds.
+    fn cheap_argon2() -> Argon2Config {
+        Argon2Config {
+            memory_kib: 8,
+            iterations: 1,
+            parallelism: 1,
+        }
+    }
+
+    fn client() -> ClientInfo {
+        ClientInfo {
+            ip: String::from("192.0.2.1"),
+            user_agent: None,
+        }
+    }
+
+    #[actix_web::test]
+    async fn hashes_verify_only_their_password() {
+        let local_auth = LocalAuth::new(&cheap_argon2());
+        let hash = local_auth
+            .hash(String::from("a long passphrase"))
+            .await
+            .unwrap();
+
+        assert!(hash.starts_with("$argon2id$"));
+        assert_eq!(
+            local_auth
+                .verify(String::from("a long passphrase"), Some(hash.clone()))
+                .await,
+            Ok(true)
+        );
+        assert_eq!(
+            local_auth
+                .verify(String::from("another passphrase"), Some(hash))
+                .await,
+            Ok(false)
+        );
+    }
+
+    #[actix_web::test]
+    async fn missing_hashes_never_verify() {
+        let local_auth = LocalAuth::new(&cheap_argon2());
+
+        // Not even the password of the dummy hash.
+        assert_eq!(
+            local_auth
+                .verify(String::from("dummy password"), None)
+                .await,
+            Ok(false)
+        );
+        assert_eq!(
+            local_auth
+                .verify(String::from("dummy password"), Some(String::from("plain")))
+                .await,
+            Err(())
+        );
+    }
+
+    #[test]
+    fn usernames_and_emails_are_checked() {
+        assert!(check_username("alice_01").is_ok());
+        assert!(check_username("al").is_err());
+        assert!(check_username("alice#1").is_err());
+        assert!(check_username(&"a".repeat(40)).is_err());
+
+        assert!(check_email("alice@example.com").is_ok());
+        assert!(check_email("alice@localhost").is_err());
+        assert!(check_email("@example.com").is_err());
+        assert!(check_email("alice@.example.com").is_err());
+        assert!(check_email("alice smith@example.com").is_err());
+    }
+
+    #[test]
+    fn weak_passwords_are_rejected_with_every_reason() {
+        assert!(check_password_strength("alice", "alice@example.com", "tidy lemon orbit").is_ok());
+
+        let Err(LocalAuthError::WeakPassword(reasons)) =
+            check_password_strength("alice", "alice@example.com", "aaaa")
+        else {
+            panic!("A short password was accepted");
+        };
+        assert_eq!(reasons.len(), 2);
+
+        for password in [
+            "my name is Alice!",
+            "mail me at alice now",
+            "CorrectHorseBatteryStaple",
+            &"abcdef".repeat(22),
+        ] {
+            assert!(
+                check_password_strength("alice", "alice@example.com", password).is_err(),
+                "{} was accepted",
+                password
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn failed_logins_lock_the_account_out() {
+        let (_test_database, database) = TestDatabase::connect("local-login").await;
+        let config = Config {
+            argon2: cheap_argon2(),
+            ..Config::default()
+        };
+        let logic = Logic::new(database, &config);
+
+        logic
+            .local_register(
+                "alice",
+                "alice@example.com",
+                String::from("tidy lemon orbit"),
+                None,
+                client(),
+            )
+            .await
+            .unwrap();
+
+        assert!(
+            logic
+                .local_login("alice", String::from("tidy lemon orbit"), client())
+                .await
+                .is_ok()
+        );
+
+        for _ in 0..5 {
+            assert!(matches!(
+                logic
+                    .local_login("Alice", String::from("wrong password"), client())
+                    .await,
+                Err(LocalAuthError::InvalidCredentials)
+            ));
+        }
+
+        // Even the right password has to wait.
+        assert!(matches!(
+            logic
+                .local_login("alice", String::from("tidy lemon orbit"), client())
+                .await,
+            Err(LocalAuthError::Throttled(_))
+        ));
+    }
+}
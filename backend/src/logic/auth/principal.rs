@@ -0,0 +1,107 @@
+use async_graphql::Enum;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+use crate::logic::auth::token::Scope;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, ToSchema, Enum)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    User,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Admin => "admin",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "user" => Some(Role::User),
+            "admin" => Some(Role::Admin),
+            _ => None,
+        }
+    }
+}
+
+/// The authenticated caller of a request.
+#[derive(Clone, Debug)]
+pub struct Principal {
+    pub user_id: u32,
+    pub roles: Vec<Role>,
+    pub scopes: Vec<Scope>,
+    /// The hash of the session the request was made with, `None` when a
+    /// personal access token was used.
+    pub session: Option<String>,
+    /// The id of the personal access token the request was made with,
+    /// `None` with a session.
+    pub token_id: Option<u32>,
+    /// The only list a personal access token reaches, `None` for every list.
+    pub list_id: Option<u32>,
+}
+
+impl Principal {
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&role)
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.iter().any(|granted| granted.grants(scope))
+    }
+
+    /// The list to work on when `requested` is asked for, which is always
+    /// the one the token is restricted to. `Err` for any other list.
+    pub fn list(&self, requested: Option<u32>) -> Result<Option<u32>, ()> {
+        match (self.list_id, requested) {
+            (Some(list_id), Some(requested)) if requested != list_id => Err(()),
+            (Some(list_id), _) => Ok(Some(list_id)),
+            (None, requested) => Ok(requested),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Principal, Role};
+    use crate::logic::auth::token::Scope;
+
+    fn token(scopes: Vec<Scope>, list_id: Option<u32>) -> Principal {
+        Principal {
+            user_id: 1,
+            roles: vec![Role::User],
+            scopes,
+            session: None,
+            token_id: Some(1),
+            list_id,
+        }
+    }
+
+    #[test]
+    fn writing_implies_reading() {
+        let write = token(vec![Scope::TodoWrite], None);
+        assert!(write.has_scope(Scope::TodoRead));
+        assert!(write.has_scope(Scope::TodoWrite));
+
+        let read = token(vec![Scope::TodoRead], None);
+        assert!(read.has_scope(Scope::TodoRead));
+        assert!(!read.has_scope(Scope::TodoWrite));
+
+        assert!(!token(Vec::new(), None).has_scope(Scope::TodoRead));
+    }
+
+    #[test]
+    fn restricted_tokens_only_reach_their_list() {
+        let restricted = token(vec![Scope::TodoRead], Some(3));
+        assert_eq!(restricted.list(None), Ok(Some(3)));
+        assert_eq!(restricted.list(Some(3)), Ok(Some(3)));
+        assert_eq!(restricted.list(Some(4)), Err(()));
+
+        let unrestricted = token(vec![Scope::TodoRead], None);
+        assert_eq!(unrestricted.list(None), Ok(None));
+        assert_eq!(unrestricted.list(Some(4)), Ok(Some(4)));
+    }
+}
@@ -0,0 +1,199 @@
+//! ```sh
+//! SESSION_SECRET=xxx SESSION_SLIDING_EXPIRATION=true SESSION_ABSOLUTE_MAX_SECS=604800 SESSION_COOKIE_SAME_SITE=Lax make run_release
+//! ```
+//!
+//! Session tokens are only stored as an HMAC-SHA256 keyed with
+//! `SESSION_SECRET`, so the database alone isn't enough to hijack a session.
+//! The variable is required, only in dev mode a random key is used without
+//! it and every session ends when the server restarts.
+//!
+//! Every session has a CSRF token, derived from the session token with the
+//! same key, which has to accompany requests that change something.
+
+use actix_web::cookie::SameSite;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use hmac::{Hmac, Mac};
+use rand::{TryRngCore, rngs::OsRng};
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+use tracing::{error, warn};
+
+use crate::config::{SameSitePolicy, SessionsConfig};
+
+/// The lifetime of a session, or with sliding expiration the time a session
+/// can be idle.
+pub const SESSION_SECS: u64 = 60 * 60 * 6;
+
+/// The last seen time of a session is only written when it is older than
+/// this, so not every request results in a write.
+pub const LAST_SEEN_RESOLUTION_SECS: u64 = 60;
+
+/// Where a session was created from, as reported by the client.
+pub struct ClientInfo {
+    pub ip: String,
+    pub user_agent: Option<String>,
+}
+
+/// The number of random bytes in a session token.
+const TOKEN_BYTES: usize = 32;
+
+pub struct SessionConfig {
+    pub sliding_expiration: bool,
+    pub absolute_max_secs: u64,
+    pub same_site: SameSite,
+    key: Vec<u8>,
+}
+
+impl SessionConfig {
+    pub fn new(config: &SessionsConfig) -> Self {
+        let key = match &config.secret {
+            Some(secret) => secret.expose().as_bytes().to_vec(),
+            None => {
+                warn!("No SESSION_SECRET set in dev mode, sessions won't survive a restart");
+                let mut key = vec![0u8; 32];
+                OsRng
+                    .try_fill_bytes(&mut key)
+                    .expect("The operating system RNG should be available");
+                key
+            }
+        };
+
+        SessionConfig {
+            key,
+            sliding_expiration: config.sliding_expiration,
+            absolute_max_secs: config.absolute_max_secs,
+            same_site: match config.cookie_same_site {
+                SameSitePolicy::Strict => SameSite::Strict,
+                SameSitePolicy::Lax => SameSite::Lax,
+                SameSitePolicy::None => SameSite::None,
+            },
+        }
+    }
+
+    /// A new session token from the operating system's CSPRNG.
+    pub fn new_token() -> Result<String, ()> {
+        let mut token = [0u8; TOKEN_BYTES];
+
+        if let Err(err) = OsRng.try_fill_bytes(&mut token) {
+            error!(
+                "Something went wrong while generating a session token: {}",
+                err
+            );
+            return Err(());
+        }
+
+        Ok(URL_SAFE_NO_PAD.encode(token))
+    }
+
+    /// The keyed hash under which the token is stored.
+    pub fn hash_token(&self, token: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC accepts keys of any size");
+        mac.update(token.as_bytes());
+
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    /// The CSRF token belonging to a session token.
+    pub fn csrf_token(&self, session: &str) -> String {
+        self.hash_token(&format!("csrf:{}", session))
+    }
+
+    pub fn verify_csrf_token(&self, session: &str, csrf_token: &str) -> bool {
+        self.csrf_token(session)
+            .as_bytes()
+            .ct_eq(csrf_token.as_bytes())
+            .into()
+    }
+
+    /// The `Max-Age` of the session cookie. With sliding expiration the
+    /// cookie lives as long as the session possibly can, the server decides
+    /// when it actually expires.
+    pub fn cookie_max_age(&self) -> u64 {
+        if self.sliding_expiration {
+            self.absolute_max_secs
+        } else {
+            SESSION_SECS
+        }
+    }
+
+    /// The expiry of a session created at `created` which is used at `now`.
+    /// A sliding session never outlives `absolute_max_secs`.
+    pub fn expires(&self, created: u64, now: u64) -> u64 {
+        if self.sliding_expiration {
+            (now + SESSION_SECS).min(created + self.absolute_max_secs)
+        } else {
+            now + SESSION_SECS
+        }
+    }
+
+    /// The new expiry of a session which was just used, if it slides.
+    pub fn slide(&self, created: u64, now: u64) -> Option<u64> {
+        self.sliding_expiration.then(|| self.expires(created, now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SESSION_SECS, SessionConfig};
+    use crate::config::SessionsConfig;
+
+    const CREATED: u64 = 1_000_000;
+
+    fn sessions(sliding_expiration: bool, absolute_max_secs: u64) -> SessionConfig {
+        SessionConfig::new(&SessionsConfig {
+            sliding_expiration,
+            absolute_max_secs,
+            ..SessionsConfig::default()
+        })
+    }
+
+    #[test]
+    fn fixed_sessions_dont_slide() {
+        let sessions = sessions(false, 60);
+
+        assert_eq!(sessions.expires(CREATED, CREATED), CREATED + SESSION_SECS);
+        assert_eq!(sessions.slide(CREATED, CREATED + 100), None);
+        assert_eq!(sessions.cookie_max_age(), SESSION_SECS);
+    }
+
+    #[test]
+    fn sliding_sessions_extend_up_to_the_absolute_maximum() {
+        let sessions = sessions(true, 24 * 60 * 60);
+
+        assert_eq!(sessions.expires(CREATED, CREATED), CREATED + SESSION_SECS);
+        assert_eq!(
+            sessions.slide(CREATED, CREATED + 3600),
+            Some(CREATED + 3600 + SESSION_SECS)
+        );
+        assert_eq!(
+            sessions.slide(CREATED, CREATED + 23 * 3600),
+            Some(CREATED + 24 * 3600)
+        );
+        assert_eq!(sessions.cookie_max_age(), 24 * 60 * 60);
+    }
+
+    #[test]
+    fn first_expiry_respects_a_short_absolute_maximum() {
+        let sessions = sessions(true, 3600);
+
+        assert_eq!(sessions.expires(CREATED, CREATED), CREATED + 3600);
+    }
+
+    #[test]
+    fn csrf_tokens_belong_to_their_session() {
+        let sessions = sessions(false, 60);
+        let session = SessionConfig::new_token().unwrap();
+        let other = SessionConfig::new_token().unwrap();
+
+        assert_ne!(session, other);
+        assert!(sessions.verify_csrf_token(&session, &sessions.csrf_token(&session)));
+        assert!(!sessions.verify_csrf_token(&session, &sessions.csrf_token(&other)));
+        assert!(!sessions.verify_csrf_token(&session, ""));
+        assert_ne!(sessions.hash_token(&session), session);
+    }
+}
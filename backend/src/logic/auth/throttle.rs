@@ -0,0 +1,140 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Entries are pruned once the map grows past this size, so that a flood of
+/// distinct usernames or addresses can't grow it without bound.
+const PRUNE_THRESHOLD: usize = 10_000;
+
+struct Attempts {
+    failures: u32,
+    window_start: Instant,
+    locked_until: Option<Instant>,
+}
+
+/// Counts failed login attempts per key and locks the key out once too many
+/// failures happen within a window. Every failure past the limit doubles the
+/// lockout, up to `max_lockout`.
+pub struct Throttle {
+    max_failures: u32,
+    window: Duration,
+    lockout: Duration,
+    max_lockout: Duration,
+    attempts: Mutex<HashMap<String, Attempts>>,
+}
+
+impl Throttle {
+    pub fn new(max_failures: u32, window: Duration, lockout: Duration) -> Self {
+        Throttle {
+            max_failures,
+            window,
+            lockout,
+            max_lockout: Duration::from_secs(60 * 60),
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns how long the caller has to wait if the key is locked out.
+    pub fn check(&self, key: &str) -> Result<(), Duration> {
+        let attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+
+        match attempts.get(key).and_then(|attempt| attempt.locked_until) {
+            Some(locked_until) if locked_until > now => Err(locked_until - now),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn fail(&self, key: &str) {
+        let mut attempts = self.attempts.lock().unwrap();
+        let now = Instant::now();
+
+        if attempts.len() > PRUNE_THRESHOLD {
+            let window = self.window;
+            attempts.retain(|_, attempt| {
+                now.duration_since(attempt.window_start) < window
+                    || attempt.locked_until.is_some_and(|until| until > now)
+            });
+        }
+
+        let attempt = attempts.entry(key.to_string()).or_insert(Attempts {
+            failures: 0,
+            window_start: now,
+            locked_until: None,
+        });
+
+        if now.duration_since(attempt.window_start) >= self.window
+            && attempt.locked_until.is_none_or(|until| until <= now)
+        {
+            attempt.failures = 0;
+            attempt.window_start = now;
+            attempt.locked_until = None;
+        }
+
+        attempt.failures += 1;
+
+        if attempt.failures >= self.max_failures {
+            let exponent = (attempt.failures - self.max_failures).min(16);
+            let lockout = self
+                .lockout
+                .saturating_mul(2u32.pow(exponent))
+                .min(self.max_lockout);
+            attempt.locked_until = Some(now + lockout);
+        }
+    }
+
+    pub fn reset(&self, key: &str) {
+        self.attempts.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::Throttle;
+
+    #[test]
+    fn locks_out_after_the_last_allowed_failure() {
+        let throttle = Throttle::new(3, Duration::from_secs(60), Duration::from_secs(10));
+
+        throttle.fail("alice");
+        throttle.fail("alice");
+        assert!(throttle.check("alice").is_ok());
+
+        throttle.fail("alice");
+        let wait = throttle.check("alice").unwrap_err();
+        assert!(wait > Duration::from_secs(9) && wait <= Duration::from_secs(10));
+        assert!(throttle.check("bob").is_ok());
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let throttle = Throttle::new(1, Duration::from_secs(60), Duration::from_secs(10));
+
+        throttle.fail("alice");
+        throttle.fail("alice");
+        let wait = throttle.check("alice").unwrap_err();
+        assert!(wait > Duration::from_secs(19) && wait <= Duration::from_secs(20));
+
+        for _ in 0..20 {
+            throttle.fail("alice");
+        }
+        assert!(throttle.check("alice").unwrap_err() <= Duration::from_secs(60 * 60));
+    }
+
+    #[test]
+    fn successes_and_new_windows_start_over() {
+        let throttle = Throttle::new(2, Duration::from_millis(50), Duration::from_secs(10));
+
+        throttle.fail("alice");
+        throttle.reset("alice");
+        throttle.fail("alice");
+        assert!(throttle.check("alice").is_ok());
+
+        thread::sleep(Duration::from_millis(60));
+        throttle.fail("alice");
+        assert!(throttle.check("alice").is_ok());
+    }
+}
@@ -0,0 +1,89 @@
+use actix_web::cookie::Cookie;
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use rand::{TryRngCore, rngs::OsRng};
+use tracing::error;
+
+/// Makes personal access tokens recognizable, e.g. for secret scanners.
+pub const TOKEN_PREFIX: &str = "todo_pat_";
+
+const TOKEN_BYTES: usize = 32;
+
+/// How the caller of a request authenticated itself.
+#[derive(Clone)]
+pub enum Credential {
+    Session(Cookie<'static>),
+    Token(String),
+}
+
+/// What a personal access token is allowed to do. Sessions are allowed
+/// everything.
+///
+/// Both scopes cover all of the user's items, unless the token is
+/// restricted to one list.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    TodoRead,
+    TodoWrite,
+}
+
+impl Scope {
+    pub const ALL: &[Scope] = &[Scope::TodoRead, Scope::TodoWrite];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::TodoRead => "todo:read",
+            Scope::TodoWrite => "todo:write",
+        }
+    }
+
+    pub fn parse(scope: &str) -> Option<Self> {
+        match scope {
+            "todo:read" => Some(Scope::TodoRead),
+            "todo:write" => Some(Scope::TodoWrite),
+            _ => None,
+        }
+    }
+
+    /// Whether a token with this scope may be used where `required` is.
+    /// Writing implies reading.
+    pub fn grants(&self, required: Scope) -> bool {
+        *self == required || (*self == Scope::TodoWrite && required == Scope::TodoRead)
+    }
+}
+
+pub fn new_token() -> Result<String, ()> {
+    let mut token = [0u8; TOKEN_BYTES];
+
+    if let Err(err) = OsRng.try_fill_bytes(&mut token) {
+        error!(
+            "Something went wrong while generating a personal access token: {}",
+            err
+        );
+        return Err(());
+    }
+
+    Ok(format!("{}{}", TOKEN_PREFIX, URL_SAFE_NO_PAD.encode(token)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Scope, TOKEN_PREFIX, new_token};
+
+    #[test]
+    fn scopes_round_trip() {
+        for scope in Scope::ALL {
+            assert_eq!(Scope::parse(scope.as_str()), Some(*scope));
+        }
+        assert_eq!(Scope::parse("todo:admin"), None);
+        assert_eq!(Scope::parse("TODO:READ"), None);
+    }
+
+    #[test]
+    fn tokens_are_prefixed_and_unique() {
+        let token = new_token().unwrap();
+        assert!(token.starts_with(TOKEN_PREFIX));
+        // 32 bytes in unpadded base64.
+        assert_eq!(token.len(), TOKEN_PREFIX.len() + 43);
+        assert_ne!(token, new_token().unwrap());
+    }
+}
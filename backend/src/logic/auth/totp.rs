@@ -0,0 +1,208 @@
+use std::time::{Duration, SystemTime};
+
+use rand::Rng;
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use totp_rs::{Algorithm, Secret, TOTP};
+use tracing::error;
+
+use crate::logic::auth::throttle::Throttle;
+
+const ISSUER: &str = "Todo App";
+const STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_ALPHABET: &[u8] = b"abcdefghjkmnpqrstuvwxyz23456789";
+
+/// How long a session waits for its second factor before it expires.
+pub const PENDING_SESSION_SECS: u64 = 10 * 60;
+
+#[derive(Debug)]
+pub enum TotpError {
+    Unauthorized,
+    InvalidCode,
+    Throttled(Duration),
+    AlreadyEnabled,
+    NotEnrolled,
+    Internal,
+}
+
+impl From<()> for TotpError {
+    fn from(_: ()) -> Self {
+        TotpError::Internal
+    }
+}
+
+pub struct Totp {
+    pub throttle: Throttle,
+}
+
+impl Totp {
+    pub fn new() -> Self {
+        Totp {
+            throttle: Throttle::new(5, Duration::from_secs(15 * 60), Duration::from_secs(60)),
+        }
+    }
+}
+
+/// Returns a new base32 encoded secret and its `otpauth://` provisioning URI.
+pub fn generate_secret(username: &str) -> Result<(String, String), ()> {
+    let Secret::Encoded(secret) = Secret::generate_secret().to_encoded() else {
+        unreachable!("to_encoded always returns an encoded secret");
+    };
+
+    let totp = totp(&secret, username)?;
+
+    Ok((secret, totp.get_url()))
+}
+
+/// Checks the code against the previous, current and next time step and
+/// returns the step it matched. Steps at or before `last_step` are rejected,
+/// so a code can't be replayed.
+pub fn verify(secret: &str, username: &str, code: &str, last_step: u64) -> Result<Option<u64>, ()> {
+    let totp = totp(secret, username)?;
+    let current_step = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        / STEP;
+
+    let code = code.trim();
+
+    Ok((current_step.saturating_sub(1)..=current_step + 1)
+        .filter(|step| *step > last_step)
+        .find(|step| {
+            totp.generate(step * STEP)
+                .as_bytes()
+                .ct_eq(code.as_bytes())
+                .into()
+        }))
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::rng();
+
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let code: String = (0..10)
+                .map(|_| {
+                    RECOVERY_CODE_ALPHABET[rng.random_range(0..RECOVERY_CODE_ALPHABET.len())]
+                        as char
+                })
+                .collect();
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Recovery codes are random enough that a plain SHA-256 is sufficient.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .trim()
+        .to_lowercase()
+        .chars()
+        .filter(|c| *c != '-' && !c.is_whitespace())
+        .collect();
+
+    Sha256::digest(normalized.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn totp(secret: &str, username: &str) -> Result<TOTP, ()> {
+    let secret = match Secret::Encoded(secret.to_string()).to_bytes() {
+        Ok(secret) => secret,
+        Err(err) => {
+            error!("The stored TOTP secret is invalid: {:?}", err);
+            return Err(());
+        }
+    };
+
+    TOTP::new(
+        Algorithm::SHA1,
+        6,
+        1,
+        STEP,
+        secret,
+        Some(ISSUER.to_string()),
+        username.to_string(),
+    )
+    .map_err(|err| error!("Something went wrong while creating the TOTP: {}", err))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use super::{STEP, generate_recovery_codes, generate_secret, hash_recovery_code, totp, verify};
+
+    fn current_step() -> u64 {
+        SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs()
+            / STEP
+    }
+
+    fn code(secret: &str, step: u64) -> String {
+        totp(secret, "alice").unwrap().generate(step * STEP)
+    }
+
+    #[test]
+    fn codes_of_neighbouring_steps_verify() {
+        let (secret, url) = generate_secret("alice").unwrap();
+        assert!(url.starts_with("otpauth://totp/"));
+
+        let step = current_step();
+        assert_eq!(
+            verify(&secret, "alice", &code(&secret, step), 0),
+            Ok(Some(step))
+        );
+        assert_eq!(
+            verify(&secret, "alice", &code(&secret, step - 1), 0),
+            Ok(Some(step - 1))
+        );
+
+        assert_eq!(
+            verify(&secret, "alice", &code(&secret, step - 3), 0),
+            Ok(None)
+        );
+        assert_eq!(verify(&secret, "alice", "abcdef", 0), Ok(None));
+        assert!(verify("not base32!", "alice", "123456", 0).is_err());
+    }
+
+    #[test]
+    fn used_steps_are_rejected() {
+        let (secret, _) = generate_secret("alice").unwrap();
+        let step = current_step();
+        let code = code(&secret, step);
+
+        let matched = verify(&secret, "alice", &format!(" {} ", code), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(verify(&secret, "alice", &code, matched), Ok(None));
+    }
+
+    #[test]
+    fn recovery_codes_are_unique_and_hashed_loosely() {
+        let codes = generate_recovery_codes();
+        assert_eq!(codes.len(), 10);
+        for code in &codes {
+            assert_eq!(code.len(), 11);
+            assert_eq!(&code[5..6], "-");
+        }
+        let mut distinct = codes.clone();
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), codes.len());
+
+        assert_eq!(
+            hash_recovery_code("abcde-fghjk"),
+            hash_recovery_code(" ABCDE FGHJK ")
+        );
+        assert_ne!(
+            hash_recovery_code("abcde-fghjk"),
+            hash_recovery_code("abcde-fghjm")
+        );
+    }
+}
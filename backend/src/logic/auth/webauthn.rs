@@ -0,0 +1,711 @@
+//! ```sh
+//! WEBAUTHN_RP_ID=todo.celarye.dev WEBAUTHN_ORIGIN=https://todo.celarye.dev make run_release
+//! ```
+//!
+//! The relying party side of the WebAuthn registration and authentication
+//! ceremonies. Only the `none` attestation conveyance is requested, so the
+//! attestation statement itself is not verified. Credentials using ES256 or
+//! RS256 are supported. With `WEBAUTHN_USER_VERIFICATION=required` both
+//! ceremonies also need the user verified flag.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ciborium::Value;
+use p256::ecdsa::{DerSignature, VerifyingKey as P256VerifyingKey, signature::Verifier};
+use rand::RngCore;
+use rsa::{BigUint, RsaPublicKey, pkcs1v15};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value as JsonValue, json};
+use sha2::{Digest, Sha256};
+use tracing::error;
+use utoipa::ToSchema;
+
+use crate::config::{UserVerification, WebAuthnConfig};
+
+const COSE_ALG_ES256: i64 = -7;
+const COSE_ALG_RS256: i64 = -257;
+
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+pub const CEREMONY_TIMEOUT_SECS: u64 = 5 * 60;
+
+#[derive(Deserialize)]
+struct ClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RegistrationCredential {
+    pub raw_id: String,
+    pub response: AttestationResponse,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuthenticationCredential {
+    pub raw_id: String,
+    pub response: AssertionResponse,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct StoredCredential {
+    pub id: String,
+    pub name: String,
+    pub created: u64,
+    pub last_used: Option<u64>,
+}
+
+/// A credential which passed the registration ceremony and can be stored.
+pub struct NewCredential {
+    pub id: String,
+    pub public_key: Vec<u8>,
+    pub sign_count: u32,
+}
+
+struct AuthenticatorData {
+    flags: u8,
+    sign_count: u32,
+}
+
+pub struct WebAuthn {
+    rp_id: String,
+    rp_name: String,
+    origin: String,
+    user_verification: UserVerification,
+}
+
+impl WebAuthn {
+    pub fn new(config: &WebAuthnConfig) -> Self {
+        WebAuthn {
+            rp_id: config.rp_id.clone(),
+            rp_name: config.rp_name.clone(),
+            origin: config.origin.clone(),
+            user_verification: config.user_verification,
+        }
+    }
+
+    pub fn new_challenge() -> String {
+        let mut challenge = [0u8; 32];
+        rand::rng().fill_bytes(&mut challenge);
+        URL_SAFE_NO_PAD.encode(challenge)
+    }
+
+    /// The `PublicKeyCredentialCreationOptions` handed to
+    /// `navigator.credentials.create()`.
+    pub fn creation_options(
+        &self,
+        challenge: &str,
+        user_id: u32,
+        username: &str,
+        exclude_credentials: &[String],
+    ) -> JsonValue {
+        json!({
+            "publicKey": {
+                "challenge": challenge,
+                "rp": { "id": self.rp_id, "name": self.rp_name },
+                "user": {
+                    "id": URL_SAFE_NO_PAD.encode(user_id.to_be_bytes()),
+                    "name": username,
+                    "displayName": username,
+                },
+                "pubKeyCredParams": [
+                    { "type": "public-key", "alg": COSE_ALG_ES256 },
+                    { "type": "public-key", "alg": COSE_ALG_RS256 },
+                ],
+                "excludeCredentials": exclude_credentials
+                    .iter()
+                    .map(|id| json!({ "type": "public-key", "id": id }))
+                    .collect::<Vec<_>>(),
+                "authenticatorSelection": {
+                    "residentKey": "required",
+                    "requireResidentKey": true,
+                    "userVerification": self.user_verification,
+                },
+                "attestation": "none",
+                "timeout": CEREMONY_TIMEOUT_SECS * 1000,
+            }
+        })
+    }
+
+    /// The `PublicKeyCredentialRequestOptions` handed to
+    /// `navigator.credentials.get()`. No credentials are listed, the
+    /// authenticator offers its discoverable credentials for this RP.
+    pub fn request_options(&self, challenge: &str) -> JsonValue {
+        json!({
+            "publicKey": {
+                "challenge": challenge,
+                "rpId": self.rp_id,
+                "userVerification": self.user_verification,
+                "timeout": CEREMONY_TIMEOUT_SECS * 1000,
+            }
+        })
+    }
+
+    /// Checks the client data of a ceremony and returns the challenge it was
+    /// signed for, which the caller still has to look up.
+    pub fn client_data(&self, client_data_json: &[u8], ceremony: &str) -> Result<String, ()> {
+        let client_data: ClientData = match serde_json::from_slice(client_data_json) {
+            Ok(client_data) => client_data,
+            Err(err) => {
+                error!("The WebAuthn client data is invalid: {}", err);
+                return Err(());
+            }
+        };
+
+        if client_data.ceremony != ceremony {
+            error!("The WebAuthn client data is for the wrong ceremony");
+            return Err(());
+        }
+
+        if client_data.origin != self.origin {
+            error!(
+                "The WebAuthn client data has an unexpected origin: {}",
+                client_data.origin
+            );
+            return Err(());
+        }
+
+        Ok(client_data.challenge)
+    }
+
+    pub fn verify_registration(
+        &self,
+        credential: &RegistrationCredential,
+    ) -> Result<NewCredential, ()> {
+        let attestation_object = decode(&credential.response.attestation_object)?;
+
+        let attestation: Value = match ciborium::de::from_reader(attestation_object.as_slice()) {
+            Ok(attestation) => attestation,
+            Err(err) => {
+                error!("The WebAuthn attestation object is invalid: {}", err);
+                return Err(());
+            }
+        };
+
+        let Some(auth_data) = map_get(&attestation, "authData").and_then(Value::as_bytes) else {
+            error!("The WebAuthn attestation object has no authenticator data");
+            return Err(());
+        };
+
+        let parsed = self.authenticator_data(auth_data)?;
+
+        if parsed.flags & FLAG_ATTESTED_CREDENTIAL_DATA == 0 {
+            error!("The WebAuthn authenticator data has no attested credential");
+            return Err(());
+        }
+
+        // AAGUID (16 bytes), credential id length (2 bytes), credential id,
+        // followed by the COSE encoded public key.
+        let attested = &auth_data[37..];
+        if attested.len() < 18 {
+            error!("The WebAuthn attested credential data is truncated");
+            return Err(());
+        }
+
+        let id_length = u16::from_be_bytes([attested[16], attested[17]]) as usize;
+        let Some(id) = attested.get(18..18 + id_length) else {
+            error!("The WebAuthn attested credential data is truncated");
+            return Err(());
+        };
+
+        let id = URL_SAFE_NO_PAD.encode(id);
+        if id != credential.raw_id.trim_end_matches('=') {
+            error!("The WebAuthn credential id doesn't match the attested credential");
+            return Err(());
+        }
+
+        let mut key_bytes = &attested[18 + id_length..];
+        let length_before = key_bytes.len();
+        let public_key: Value = match ciborium::de::from_reader(&mut key_bytes) {
+            Ok(public_key) => public_key,
+            Err(err) => {
+                error!("The WebAuthn credential public key is invalid: {}", err);
+                return Err(());
+            }
+        };
+        let public_key_bytes =
+            attested[18 + id_length..18 + id_length + length_before - key_bytes.len()].to_vec();
+
+        // Make sure the key can be used before it is stored.
+        CoseKey::parse(&public_key)?;
+
+        Ok(NewCredential {
+            id,
+            public_key: public_key_bytes,
+            sign_count: parsed.sign_count,
+        })
+    }
+
+    /// Verifies the assertion signature with the stored public key and
+    /// returns the new signature counter.
+    pub fn verify_authentication(
+        &self,
+        credential: &AuthenticationCredential,
+        public_key: &[u8],
+        stored_sign_count: u32,
+    ) -> Result<u32, ()> {
+        let auth_data = decode(&credential.response.authenticator_data)?;
+        let client_data_json = decode(&credential.response.client_data_json)?;
+        let signature = decode(&credential.response.signature)?;
+
+        let parsed = self.authenticator_data(&auth_data)?;
+
+        let public_key: Value = match ciborium::de::from_reader(public_key) {
+            Ok(public_key) => public_key,
+            Err(err) => {
+                error!("The stored WebAuthn public key is invalid: {}", err);
+                return Err(());
+            }
+        };
+
+        let mut signed = auth_data.clone();
+        signed.extend_from_slice(&Sha256::digest(&client_data_json));
+
+        CoseKey::parse(&public_key)?.verify(&signed, &signature)?;
+
+        // A counter which doesn't increase points to a cloned authenticator.
+        // Authenticators which don't implement a counter always send zero.
+        if (parsed.sign_count != 0 || stored_sign_count != 0)
+            && parsed.sign_count <= stored_sign_count
+        {
+            error!("The WebAuthn signature counter did not increase");
+            return Err(());
+        }
+
+        Ok(parsed.sign_count)
+    }
+
+    fn authenticator_data(&self, auth_data: &[u8]) -> Result<AuthenticatorData, ()> {
+        if auth_data.len() < 37 {
+            error!("The WebAuthn authenticator data is truncated");
+            return Err(());
+        }
+
+        if auth_data[..32] != Sha256::digest(self.rp_id.as_bytes())[..] {
+            error!("The WebAuthn authenticator data is for another relying party");
+            return Err(());
+        }
+
+        let flags = auth_data[32];
+        if flags & FLAG_USER_PRESENT == 0 {
+            error!("The WebAuthn authenticator data doesn't have the user present flag");
+            return Err(());
+        }
+
+        if matches!(self.user_verification, UserVerification::Required)
+            && flags & FLAG_USER_VERIFIED == 0
+        {
+            error!("The WebAuthn authenticator data doesn't have the user verified flag");
+            return Err(());
+        }
+
+        Ok(AuthenticatorData {
+            flags,
+            sign_count: u32::from_be_bytes([
+                auth_data[33],
+                auth_data[34],
+                auth_data[35],
+                auth_data[36],
+            ]),
+        })
+    }
+}
+
+enum CoseKey {
+    Es256(P256VerifyingKey),
+    Rs256(RsaPublicKey),
+}
+
+impl CoseKey {
+    fn parse(key: &Value) -> Result<Self, ()> {
+        let alg = cose_get(key, 3).and_then(Value::as_integer).map(i128::from);
+
+        match alg {
+            Some(alg) if alg == COSE_ALG_ES256 as i128 => {
+                let (Some(x), Some(y)) = (
+                    cose_get(key, -2).and_then(Value::as_bytes),
+                    cose_get(key, -3).and_then(Value::as_bytes),
+                ) else {
+                    error!("The ES256 WebAuthn public key is missing coordinates");
+                    return Err(());
+                };
+
+                let mut sec1 = vec![0x04];
+                sec1.extend_from_slice(x);
+                sec1.extend_from_slice(y);
+
+                P256VerifyingKey::from_sec1_bytes(&sec1)
+                    .map(CoseKey::Es256)
+                    .map_err(|err| error!("The ES256 WebAuthn public key is invalid: {}", err))
+            }
+            Some(alg) if alg == COSE_ALG_RS256 as i128 => {
+                let (Some(n), Some(e)) = (
+                    cose_get(key, -1).and_then(Value::as_bytes),
+                    cose_get(key, -2).and_then(Value::as_bytes),
+                ) else {
+                    error!("The RS256 WebAuthn public key is missing its modulus or exponent");
+                    return Err(());
+                };
+
+                RsaPublicKey::new(BigUint::from_bytes_be(n), BigUint::from_bytes_be(e))
+                    .map(CoseKey::Rs256)
+                    .map_err(|err| error!("The RS256 WebAuthn public key is invalid: {}", err))
+            }
+            _ => {
+                error!("The WebAuthn public key uses an unsupported algorithm");
+                Err(())
+            }
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), ()> {
+        let result = match self {
+            CoseKey::Es256(key) => DerSignature::try_from(signature)
+                .and_then(|signature| key.verify(message, &signature)),
+            CoseKey::Rs256(key) => pkcs1v15::Signature::try_from(signature).and_then(|signature| {
+                pkcs1v15::VerifyingKey::<Sha256>::new(key.clone()).verify(message, &signature)
+            }),
+        };
+
+        result.map_err(|_| error!("The WebAuthn assertion signature is invalid"))
+    }
+}
+
+pub fn decode(value: &str) -> Result<Vec<u8>, ()> {
+    URL_SAFE_NO_PAD
+        .decode(value.trim_end_matches('='))
+        .map_err(|err| error!("Invalid base64url in a WebAuthn response: {}", err))
+}
+
+fn map_get<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    value
+        .as_map()?
+        .iter()
+        .find(|(k, _)| k.as_text() == Some(key))
+        .map(|(_, v)| v)
+}
+
+fn cose_get(value: &Value, key: i64) -> Option<&Value> {
+    value
+        .as_map()?
+        .iter()
+        .find(|(k, _)| k.as_integer().map(i128::from) == Some(key as i128))
+        .map(|(_, v)| v)
+}
+
+/// The ceremonies run through `Logic` against a database in a temporary
+/// file, with a software authenticator holding an ES256 key.
+#[cfg(test)]
+mod tests {
+    use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+    use ciborium::Value;
+    use p256::ecdsa::{Signature, SigningKey, signature::Signer};
+    use serde_json::{Value as JsonValue, json};
+    use sha2::{Digest, Sha256};
+
+    use super::{
+        AssertionResponse, AttestationResponse, AuthenticationCredential, COSE_ALG_ES256,
+        FLAG_ATTESTED_CREDENTIAL_DATA, FLAG_USER_PRESENT, FLAG_USER_VERIFIED,
+        RegistrationCredential,
+    };
+    use crate::config::{Config, UserVerification};
+    use crate::database::{NewUser, TestDatabase};
+    use crate::{ClientInfo, Logic};
+
+    const ORIGIN: &str = "https://todo.celarye.dev";
+    const RP_ID: &str = "todo.celarye.dev";
+    struct Authenticator {
+        credential_id: Vec<u8>,
+        key: SigningKey,
+        user_verified: bool,
+    }
+
+    impl Authenticator {
+        /// Every seed gives another credential.
+        fn new(seed: u8, user_verified: bool) -> Self {
+            Authenticator {
+                credential_id: vec![seed; 16],
+                key: SigningKey::from_slice(&[seed; 32]).unwrap(),
+                user_verified,
+            }
+        }
+
+        fn auth_data(&self, sign_count: u32, attested: bool) -> Vec<u8> {
+            let mut flags = FLAG_USER_PRESENT;
+            if self.user_verified {
+                flags |= FLAG_USER_VERIFIED;
+            }
+            if attested {
+                flags |= FLAG_ATTESTED_CREDENTIAL_DATA;
+            }
+
+            let mut auth_data = Sha256::digest(RP_ID).to_vec();
+            auth_data.push(flags);
+            auth_data.extend_from_slice(&sign_count.to_be_bytes());
+
+            if attested {
+                let point = self.key.verifying_key().to_encoded_point(false);
+                let public_key = Value::Map(vec![
+                    (Value::from(1), Value::from(2)),
+                    (Value::from(3), Value::from(COSE_ALG_ES256)),
+                    (Value::from(-1), Value::from(1)),
+                    (Value::from(-2), Value::Bytes(point.x().unwrap().to_vec())),
+                    (Value::from(-3), Value::Bytes(point.y().unwrap().to_vec())),
+                ]);
+
+                auth_data.extend_from_slice(&[0; 16]);
+                auth_data.extend_from_slice(&(self.credential_id.len() as u16).to_be_bytes());
+                auth_data.extend_from_slice(&self.credential_id);
+                ciborium::ser::into_writer(&public_key, &mut auth_data).unwrap();
+            }
+
+            auth_data
+        }
+
+        fn create(&self, options: &JsonValue, origin: &str) -> RegistrationCredential {
+            let attestation = Value::Map(vec![
+                (Value::from("fmt"), Value::from("none")),
+                (Value::from("attStmt"), Value::Map(Vec::new())),
+                (
+                    Value::from("authData"),
+                    Value::Bytes(self.auth_data(0, true)),
+                ),
+            ]);
+            let mut attestation_object = Vec::new();
+            ciborium::ser::into_writer(&attestation, &mut attestation_object).unwrap();
+
+            RegistrationCredential {
+                raw_id: URL_SAFE_NO_PAD.encode(&self.credential_id),
+                response: AttestationResponse {
+                    client_data_json: client_data("webauthn.create", options, origin),
+                    attestation_object: URL_SAFE_NO_PAD.encode(attestation_object),
+                },
+            }
+        }
+
+        fn get(&self, options: &JsonValue, sign_count: u32) -> AuthenticationCredential {
+            let client_data_json = client_data("webauthn.get", options, ORIGIN);
+            let auth_data = self.auth_data(sign_count, false);
+
+            let mut signed = auth_data.clone();
+            signed.extend_from_slice(&Sha256::digest(
+                URL_SAFE_NO_PAD.decode(&client_data_json).unwrap(),
+            ));
+            let signature: Signature = self.key.sign(&signed);
+
+            AuthenticationCredential {
+                raw_id: URL_SAFE_NO_PAD.encode(&self.credential_id),
+                response: AssertionResponse {
+                    client_data_json,
+                    authenticator_data: URL_SAFE_NO_PAD.encode(auth_data),
+                    signature: URL_SAFE_NO_PAD.encode(signature.to_der()),
+                    user_handle: Some(URL_SAFE_NO_PAD.encode(1u32.to_be_bytes())),
+                },
+            }
+        }
+    }
+
+    fn client_data(ceremony: &str, options: &JsonValue, origin: &str) -> String {
+        URL_SAFE_NO_PAD.encode(
+            json!({
+                "type": ceremony,
+                "challenge": options["publicKey"]["challenge"],
+                "origin": origin,
+            })
+            .to_string(),
+        )
+    }
+
+    fn client() -> ClientInfo {
+        ClientInfo {
+            ip: String::from("127.0.0.1"),
+            user_agent: None,
+        }
+    }
+
+    /// A fresh database with a single user.
+    async fn logic(name: &str, user_verification: UserVerification) -> (TestDatabase, Logic) {
+        let (test_database, database) = TestDatabase::connect(&format!("webauthn-{}", name)).await;
+        let user_id = database
+            .add_local_user(
+                String::from("alice"),
+                String::from("alice@example.com"),
+                String::new(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(user_id, NewUser::Added(1));
+
+        let mut config = Config::default();
+        config.webauthn.user_verification = user_verification;
+
+        (test_database, Logic::new(database, &config))
+    }
+
+    async fn register(logic: &Logic, authenticator: &Authenticator) -> Result<(), ()> {
+        let options = logic.webauthn_register_init(1).await?;
+        logic
+            .webauthn_register_finish(1, "Key", authenticator.create(&options, ORIGIN))
+            .await
+    }
+
+    #[actix_web::test]
+    async fn ceremonies_round_trip() {
+        let (_database, logic) = logic("round-trip", UserVerification::Preferred).await;
+        let authenticator = Authenticator::new(1, false);
+
+        register(&logic, &authenticator).await.unwrap();
+
+        for sign_count in [1, 2] {
+            let options = logic.webauthn_login_init().await.unwrap();
+            logic
+                .webauthn_login_finish(authenticator.get(&options, sign_count), client())
+                .await
+                .unwrap();
+        }
+    }
+
+    #[actix_web::test]
+    async fn wrong_origin_is_rejected() {
+        let (_database, logic) = logic("origin", UserVerification::Preferred).await;
+        let authenticator = Authenticator::new(1, false);
+
+        let options = logic.webauthn_register_init(1).await.unwrap();
+        let credential = authenticator.create(&options, "https://todo.example.com");
+        assert!(
+            logic
+                .webauthn_register_finish(1, "Key", credential)
+                .await
+                .is_err()
+        );
+
+        register(&logic, &authenticator).await.unwrap();
+
+        let options = logic.webauthn_login_init().await.unwrap();
+        let mut credential = authenticator.get(&options, 1);
+        credential.response.client_data_json =
+            client_data("webauthn.get", &options, "https://todo.example.com");
+        assert!(
+            logic
+                .webauthn_login_finish(credential, client())
+                .await
+                .is_err()
+        );
+    }
+
+    #[actix_web::test]
+    async fn challenges_are_single_use() {
+        let (_database, logic) = logic("replay", UserVerification::Preferred).await;
+        let authenticator = Authenticator::new(1, false);
+
+        let options = logic.webauthn_register_init(1).await.unwrap();
+        logic
+            .webauthn_register_finish(1, "Key", authenticator.create(&options, ORIGIN))
+            .await
+            .unwrap();
+        let other = Authenticator::new(2, false);
+        assert!(
+            logic
+                .webauthn_register_finish(1, "Key", other.create(&options, ORIGIN))
+                .await
+                .is_err()
+        );
+
+        // A higher counter on the replay, so only the challenge can stop it.
+        let options = logic.webauthn_login_init().await.unwrap();
+        logic
+            .webauthn_login_finish(authenticator.get(&options, 1), client())
+            .await
+            .unwrap();
+        assert!(
+            logic
+                .webauthn_login_finish(authenticator.get(&options, 2), client())
+                .await
+                .is_err()
+        );
+    }
+
+    #[actix_web::test]
+    async fn counter_has_to_increase() {
+        let (_database, logic) = logic("counter", UserVerification::Preferred).await;
+        let authenticator = Authenticator::new(1, false);
+
+        register(&logic, &authenticator).await.unwrap();
+
+        let options = logic.webauthn_login_init().await.unwrap();
+        logic
+            .webauthn_login_finish(authenticator.get(&options, 5), client())
+            .await
+            .unwrap();
+
+        for sign_count in [5, 4] {
+            let options = logic.webauthn_login_init().await.unwrap();
+            assert!(
+                logic
+                    .webauthn_login_finish(authenticator.get(&options, sign_count), client())
+                    .await
+                    .is_err()
+            );
+        }
+    }
+
+    #[actix_web::test]
+    async fn required_user_verification_is_enforced() {
+        let (_database, logic) = logic("uv", UserVerification::Required).await;
+
+        let options = logic.webauthn_register_init(1).await.unwrap();
+        assert_eq!(
+            options["publicKey"]["authenticatorSelection"]["userVerification"],
+            "required"
+        );
+        assert!(
+            register(&logic, &Authenticator::new(1, false))
+                .await
+                .is_err()
+        );
+
+        register(&logic, &Authenticator::new(2, true))
+            .await
+            .unwrap();
+
+        let options = logic.webauthn_login_init().await.unwrap();
+        assert_eq!(options["publicKey"]["userVerification"], "required");
+        let credential = Authenticator::new(2, false).get(&options, 1);
+        assert!(
+            logic
+                .webauthn_login_finish(credential, client())
+                .await
+                .is_err()
+        );
+
+        let options = logic.webauthn_login_init().await.unwrap();
+        logic
+            .webauthn_login_finish(Authenticator::new(2, true).get(&options, 1), client())
+            .await
+            .unwrap();
+    }
+}
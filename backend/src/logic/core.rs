@@ -1,105 +1,1523 @@
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
-use actix_web::cookie::Cookie;
-use rand::{Rng, distr::Alphanumeric};
+use actix_web::cookie::{Cookie, SameSite};
+use serde_json::Value as JsonValue;
+use tokio::sync::broadcast;
+use tokio::time;
+use tracing::{error, info, instrument, warn};
 
 use crate::Database;
-use crate::app::handlers::{TodoItem, User};
-use crate::logic::auth::github;
+use crate::app::handlers::{
+    AccessTokenInfo, AdminStats, AuditEntry, Check, DataExport, FieldError, InviteInfo, Readiness,
+    SessionInfo, TodoItem, TodoList, User,
+};
+use crate::config::Config;
+use crate::database::NewUser;
+use crate::logic::account::{self, AccountDeletion, AccountError};
+use crate::logic::admin::{self, AdminError};
+use crate::logic::auth::admission::{self, Admission, AdmissionError};
+use crate::logic::auth::github::{GitHub, GitHubError};
+use crate::logic::auth::local::{self, LocalAuth, LocalAuthError};
+use crate::logic::auth::principal::{Principal, Role};
+use crate::logic::auth::session::{ClientInfo, LAST_SEEN_RESOLUTION_SECS, SessionConfig};
+use crate::logic::auth::token::{self, Credential, Scope};
+use crate::logic::auth::totp::{self, Totp, TotpError};
+use crate::logic::auth::webauthn::{
+    self, AuthenticationCredential, RegistrationCredential, StoredCredential, WebAuthn,
+};
+use crate::logic::todo::{EVENT_CAPACITY, TodoChange, TodoError, TodoEvent, TodoLimits};
+use crate::metrics::METRICS;
+
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+pub struct NewSession {
+    pub value: String,
+    pub csrf_token: String,
+    /// Set when the session has to be completed with a TOTP code first.
+    pub two_factor_required: bool,
+    pub max_age: u64,
+    pub same_site: SameSite,
+}
 
 pub struct Logic {
     database: Database,
+    account_deletion: AccountDeletion,
+    admins: Vec<u32>,
+    admission: Admission,
+    github: Option<GitHub>,
+    local_auth: LocalAuth,
+    webauthn: WebAuthn,
+    totp: Totp,
+    sessions: SessionConfig,
+    todo_limits: TodoLimits,
+    todo_events: broadcast::Sender<TodoEvent>,
 }
 
 impl Logic {
-    pub fn new(database: Database) -> Self {
-        Logic { database }
+    /// The configuration has to be validated already.
+    pub fn new(database: Database, config: &Config) -> Self {
+        Logic {
+            database,
+            account_deletion: AccountDeletion::new(&config.accounts),
+            admins: config.admins.github_ids.clone(),
+            admission: Admission::new(&config.admission),
+            github: GitHub::new(&config.github),
+            local_auth: LocalAuth::new(&config.argon2),
+            webauthn: WebAuthn::new(&config.webauthn),
+            totp: Totp::new(),
+            sessions: SessionConfig::new(&config.sessions),
+            todo_limits: TodoLimits::new(&config.todo),
+            todo_events: broadcast::Sender::new(EVENT_CAPACITY),
+        }
     }
 
+    /// Closes the database, after which nothing else can be done.
+    pub async fn close(&self) -> Result<(), ()> {
+        self.database.close().await
+    }
+
+    /// Whether requests can be served: a connection can be used within
+    /// `READINESS_TIMEOUT`, every migration is applied and GitHub sign-in,
+    /// when configured, has its client.
+    #[instrument(skip_all)]
+    pub async fn readiness(&self, shutting_down: bool) -> Readiness {
+        let (database, migrations) =
+            match time::timeout(READINESS_TIMEOUT, self.database.pending_migrations()).await {
+                Ok(Ok(0)) => (Check::Ok, Check::Ok),
+                Ok(Ok(pending)) => {
+                    warn!("{} database migrations are pending", pending);
+                    (Check::Ok, Check::Failed)
+                }
+                Ok(Err(_)) => (Check::Failed, Check::Failed),
+                Err(_) => {
+                    error!("No database connection became available in time");
+                    (Check::Failed, Check::Failed)
+                }
+            };
+        let github_oauth = match self.github {
+            Some(_) => Check::Ok,
+            None => Check::Disabled,
+        };
+        let shutdown = if shutting_down {
+            Check::Failed
+        } else {
+            Check::Ok
+        };
+
+        Readiness {
+            ready: [database, migrations, github_oauth, shutdown]
+                .iter()
+                .all(|check| *check != Check::Failed),
+            database,
+            migrations,
+            github_oauth,
+            shutdown,
+        }
+    }
+
+    /// Updates the gauges which are read from the database, right before the
+    /// metrics are scraped.
+    #[instrument(skip_all)]
+    pub async fn update_metrics(&self) -> Result<(), ()> {
+        let (size, idle, max) = self.database.pool_usage();
+        METRICS
+            .db_connections
+            .with_label_values(&["idle"])
+            .set(idle as i64);
+        METRICS
+            .db_connections
+            .with_label_values(&["in_use"])
+            .set(size as i64 - idle as i64);
+        METRICS.db_max_connections.set(max as i64);
+
+        let stats = self.database.stats().await?;
+        METRICS.active_sessions.set(stats.active_sessions as i64);
+        METRICS.users.set(stats.users as i64);
+        METRICS
+            .todo_items
+            .with_label_values(&["open"])
+            .set(stats.todo_items as i64 - stats.completed_todo_items as i64);
+        METRICS
+            .todo_items
+            .with_label_values(&["done"])
+            .set(stats.completed_todo_items as i64);
+
+        Ok(())
+    }
+
+    #[instrument(skip_all)]
     pub async fn user_count(&self) -> Result<u32, ()> {
         self.database.user_count().await
     }
 
-    pub async fn validate(&self, session: Cookie<'_>) -> Result<u32, ()> {
-        self.database.get_session(session.value().to_string()).await
+    /// Resolves a session or personal access token into the caller. Sessions
+    /// waiting for their second factor aren't accepted.
+    #[instrument(skip_all)]
+    pub async fn authenticate(&self, credential: Credential) -> Result<Principal, ()> {
+        match credential {
+            Credential::Session(session) => self.authenticate_session(session).await,
+            Credential::Token(token) => self.authenticate_token(&token).await,
+        }
     }
 
+    async fn authenticate_session(&self, session: Cookie<'_>) -> Result<Principal, ()> {
+        let session_hash = self.sessions.hash_token(session.value());
+        let stored = self.database.get_session(session_hash.clone()).await?;
+
+        if stored.pending {
+            return Err(());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        if now.saturating_sub(stored.last_seen) >= LAST_SEEN_RESOLUTION_SECS {
+            self.database
+                .touch_session(
+                    session_hash.clone(),
+                    now,
+                    self.sessions.slide(stored.created, now),
+                )
+                .await?;
+        }
+
+        let user = self.database.get_user(stored.user_id).await?;
+
+        if user.disabled {
+            return Err(());
+        }
+
+        Ok(Principal {
+            user_id: stored.user_id,
+            roles: match user.role {
+                Role::User => vec![Role::User],
+                Role::Admin => vec![Role::User, Role::Admin],
+            },
+            scopes: Scope::ALL.to_vec(),
+            session: Some(session_hash),
+            token_id: None,
+            list_id: None,
+        })
+    }
+
+    async fn authenticate_token(&self, token: &str) -> Result<Principal, ()> {
+        let stored = self
+            .database
+            .get_access_token(self.sessions.hash_token(token))
+            .await?;
+
+        if self.database.get_user(stored.user_id).await?.disabled {
+            return Err(());
+        }
+
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        if stored
+            .last_used
+            .is_none_or(|last_used| now.saturating_sub(last_used) >= LAST_SEEN_RESOLUTION_SECS)
+        {
+            self.database.touch_access_token(stored.id, now).await?;
+        }
+
+        // Admin access needs a session, personal access tokens never carry
+        // the admin role. A token restricted to a list only reaches the items
+        // on it.
+        Ok(Principal {
+            user_id: stored.user_id,
+            roles: vec![Role::User],
+            scopes: stored
+                .scopes
+                .iter()
+                .filter_map(|scope| Scope::parse(scope))
+                .collect(),
+            session: None,
+            token_id: Some(stored.id),
+            list_id: stored.list_id,
+        })
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
     pub async fn get_user(&self, user_id: u32) -> Result<User, ()> {
         self.database.get_user(user_id).await
     }
 
+    #[instrument(skip_all)]
     pub async fn github_init(&self) -> Result<String, ()> {
-        let (redirect_url, csrf_token) = github::init();
+        let github = self.github()?;
+
+        let (redirect_url, csrf_token) = github.init(self.admission.requires_membership());
         self.database.add_csrf_token(csrf_token).await?;
 
         Ok(redirect_url.to_string())
     }
 
-    pub async fn github_success(&self, code: &String, csrf_token: &String) -> Result<String, ()> {
+    #[instrument(skip_all)]
+    pub async fn github_success(
+        &self,
+        code: &str,
+        csrf_token: &str,
+        invite_code: Option<&str>,
+        client: ClientInfo,
+    ) -> Result<NewSession, GitHubError> {
         self.database.get_csrf_token(csrf_token).await?;
 
         self.database.delete_csrf_token(csrf_token).await?;
 
-        let user_data = github::success(code.clone()).await?;
+        let profile = self
+            .github()?
+            .success(code.to_string(), self.admission.requires_membership())
+            .await?;
+
+        self.admission
+            .check_membership(&profile.orgs, &profile.teams)?;
+        self.admission
+            .check_email(&profile.email, profile.email_verified)?;
 
-        let user_id = match self.database.get_user_by_github_id(user_data.0).await {
+        let user_id = match self.database.get_user_by_github_id(profile.id).await {
             Ok(user) => user.id,
             Err(_) => {
-                self.database
-                    .add_user(user_data.0, user_data.1, user_data.2, user_data.3)
-                    .await?
+                let invite = self.invite_hash::<GitHubError>(invite_code)?;
+
+                let new_user = self
+                    .database
+                    .add_user(
+                        profile.id,
+                        profile.login.clone(),
+                        profile.email.clone(),
+                        profile.avatar_url.clone(),
+                        invite.clone(),
+                    )
+                    .await?;
+
+                let new_user = match new_user {
+                    // A local account or a renamed GitHub account holds the
+                    // login. Local usernames can't contain `#` and the id
+                    // is unique, so this one is free unless the email is not.
+                    NewUser::Taken => {
+                        self.database
+                            .add_user(
+                                profile.id,
+                                format!("{}#{}", profile.login, profile.id),
+                                profile.email,
+                                profile.avatar_url,
+                                invite,
+                            )
+                            .await?
+                    }
+                    new_user => new_user,
+                };
+
+                let user_id = match new_user {
+                    NewUser::Added(user_id) => user_id,
+                    NewUser::Taken => return Err(GitHubError::EmailTaken),
+                    NewUser::InvalidInvite => return Err(AdmissionError::InvalidInvite.into()),
+                };
+
+                if self.admins.contains(&profile.id) {
+                    self.promote_admins(&[profile.id]).await?;
+                }
+
+                user_id
             }
         };
 
-        let session_value: String = rand::rng()
-            .sample_iter(&Alphanumeric)
-            .take(24)
-            .map(char::from)
+        Ok(self.create_session(user_id, client).await?)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn local_register(
+        &self,
+        username: &str,
+        email: &str,
+        password: String,
+        invite_code: Option<&str>,
+        client: ClientInfo,
+    ) -> Result<NewSession, LocalAuthError> {
+        local::check_username(username)?;
+        local::check_email(email)?;
+        local::check_password_strength(username, email, &password)?;
+
+        self.admission.check_local_signup()?;
+        // The address can't be verified, an invite or `allow_local_signup`
+        // vouches for it and only its domain is checked.
+        self.admission.check_email(email, true)?;
+
+        if self.database.user_exists(username, email).await? {
+            return Err(LocalAuthError::UserExists);
+        }
+
+        let invite = self.invite_hash::<LocalAuthError>(invite_code)?;
+
+        let password_hash = self.local_auth.hash(password).await?;
+
+        let user_id = match self
+            .database
+            .add_local_user(
+                username.to_string(),
+                email.to_string(),
+                password_hash,
+                invite,
+            )
+            .await?
+        {
+            NewUser::Added(user_id) => user_id,
+            NewUser::Taken => return Err(LocalAuthError::UserExists),
+            NewUser::InvalidInvite => return Err(AdmissionError::InvalidInvite.into()),
+        };
+
+        Ok(self.create_session(user_id, client).await?)
+    }
+
+    #[instrument(skip_all)]
+    pub async fn local_login(
+        &self,
+        username: &str,
+        password: String,
+        client: ClientInfo,
+    ) -> Result<NewSession, LocalAuthError> {
+        let account_key = username.to_lowercase();
+
+        self.local_auth
+            .account_throttle
+            .check(&account_key)
+            .and(self.local_auth.ip_throttle.check(&client.ip))
+            .map_err(LocalAuthError::Throttled)?;
+
+        let user_id = self
+            .database
+            .get_user_by_username(username)
+            .await
+            .ok()
+            .map(|user| user.id);
+
+        let password_hash = match user_id {
+            Some(user_id) => self.database.get_password_hash(user_id).await?,
+            None => None,
+        };
+
+        let (Some(user_id), true) = (
+            user_id,
+            self.local_auth.verify(password, password_hash).await?,
+        ) else {
+            self.local_auth.account_throttle.fail(&account_key);
+            self.local_auth.ip_throttle.fail(&client.ip);
+            return Err(LocalAuthError::InvalidCredentials);
+        };
+
+        self.local_auth.account_throttle.reset(&account_key);
+
+        let user = self.database.get_user(user_id).await?;
+
+        if user.disabled {
+            return Err(LocalAuthError::Disabled);
+        }
+
+        // The allowed domains may have changed since the account was made.
+        self.admission.check_email(&user.email, true)?;
+
+        Ok(self.create_session(user_id, client).await?)
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn change_password(
+        &self,
+        principal: &Principal,
+        current_password: String,
+        new_password: String,
+        ip: &str,
+    ) -> Result<(), LocalAuthError> {
+        let user_id = principal.user_id;
+        let user = self.database.get_user(user_id).await?;
+        let account_key = user.username.to_lowercase();
+
+        self.local_auth
+            .account_throttle
+            .check(&account_key)
+            .and(self.local_auth.ip_throttle.check(ip))
+            .map_err(LocalAuthError::Throttled)?;
+
+        let Some(password_hash) = self.database.get_password_hash(user_id).await? else {
+            return Err(LocalAuthError::NoPassword);
+        };
+
+        if !self
+            .local_auth
+            .verify(current_password, Some(password_hash))
+            .await?
+        {
+            self.local_auth.account_throttle.fail(&account_key);
+            self.local_auth.ip_throttle.fail(ip);
+            return Err(LocalAuthError::InvalidCredentials);
+        }
+
+        self.local_auth.account_throttle.reset(&account_key);
+
+        local::check_password_strength(&user.username, &user.email, &new_password)?;
+
+        let password_hash = self.local_auth.hash(new_password).await?;
+        self.database
+            .set_password_hash(user_id, password_hash)
+            .await?;
+
+        // Anyone else holding a session for this account has to log in again
+        // with the new password.
+        self.database
+            .delete_other_sessions(user_id, principal.session.clone().unwrap_or_default())
+            .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn webauthn_register_init(&self, user_id: u32) -> Result<JsonValue, ()> {
+        let user = self.get_user(user_id).await?;
+
+        let exclude_credentials: Vec<String> = self
+            .database
+            .get_webauthn_credentials(user.id)
+            .await?
+            .into_iter()
+            .map(|credential| credential.id)
             .collect();
 
-        let session_expires = SystemTime::now()
+        let challenge = WebAuthn::new_challenge();
+        self.database
+            .add_webauthn_challenge(&challenge, Some(user.id), ceremony_expires())
+            .await?;
+
+        Ok(self.webauthn.creation_options(
+            &challenge,
+            user.id,
+            &user.username,
+            &exclude_credentials,
+        ))
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn webauthn_register_finish(
+        &self,
+        user_id: u32,
+        name: &str,
+        credential: RegistrationCredential,
+    ) -> Result<(), ()> {
+        let client_data_json = webauthn::decode(&credential.response.client_data_json)?;
+        let challenge = self
+            .webauthn
+            .client_data(&client_data_json, "webauthn.create")?;
+
+        if self.database.take_webauthn_challenge(&challenge).await? != Some(user_id) {
+            error!("The WebAuthn challenge was issued for another ceremony");
+            return Err(());
+        }
+
+        let new_credential = self.webauthn.verify_registration(&credential)?;
+
+        self.database
+            .add_webauthn_credential(user_id, credential_name(name), new_credential)
+            .await
+    }
+
+    #[instrument(skip_all)]
+    pub async fn webauthn_login_init(&self) -> Result<JsonValue, ()> {
+        let challenge = WebAuthn::new_challenge();
+        self.database
+            .add_webauthn_challenge(&challenge, None, ceremony_expires())
+            .await?;
+
+        Ok(self.webauthn.request_options(&challenge))
+    }
+
+    #[instrument(skip_all)]
+    pub async fn webauthn_login_finish(
+        &self,
+        credential: AuthenticationCredential,
+        client: ClientInfo,
+    ) -> Result<NewSession, ()> {
+        let client_data_json = webauthn::decode(&credential.response.client_data_json)?;
+        let challenge = self
+            .webauthn
+            .client_data(&client_data_json, "webauthn.get")?;
+
+        if self
+            .database
+            .take_webauthn_challenge(&challenge)
+            .await?
+            .is_some()
+        {
+            error!("The WebAuthn challenge was issued for another ceremony");
+            return Err(());
+        }
+
+        let credential_id = credential.raw_id.trim_end_matches('=');
+        let (user_id, public_key, sign_count) =
+            self.database.get_webauthn_credential(credential_id).await?;
+
+        if let Some(user_handle) = &credential.response.user_handle
+            && webauthn::decode(user_handle)? != user_id.to_be_bytes()
+        {
+            error!("The WebAuthn user handle doesn't match the credential owner");
+            return Err(());
+        }
+
+        let sign_count =
+            self.webauthn
+                .verify_authentication(&credential, &public_key, sign_count)?;
+
+        self.database
+            .update_webauthn_credential_usage(credential_id, sign_count)
+            .await?;
+
+        self.create_session(user_id, client).await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn webauthn_credentials(&self, user_id: u32) -> Result<Vec<StoredCredential>, ()> {
+        self.database.get_webauthn_credentials(user_id).await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn rename_webauthn_credential(
+        &self,
+        user_id: u32,
+        id: &str,
+        name: &str,
+    ) -> Result<bool, ()> {
+        self.database
+            .rename_webauthn_credential(user_id, id, credential_name(name))
+            .await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_webauthn_credential(&self, user_id: u32, id: &str) -> Result<bool, ()> {
+        self.database.delete_webauthn_credential(user_id, id).await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn totp_enroll(&self, user_id: u32) -> Result<(String, String), TotpError> {
+        let user = self.database.get_user(user_id).await?;
+
+        if self.database.totp_enabled(user_id).await? {
+            return Err(TotpError::AlreadyEnabled);
+        }
+
+        let (secret, provisioning_uri) = totp::generate_secret(&user.username)?;
+        self.database
+            .set_totp_secret(user_id, secret.clone())
+            .await?;
+
+        Ok((secret, provisioning_uri))
+    }
+
+    /// Enables the enrolled secret once a code for it is verified and
+    /// returns the recovery codes, which are only shown this once.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn totp_enable(&self, user_id: u32, code: &str) -> Result<Vec<String>, TotpError> {
+        let user = self.database.get_user(user_id).await?;
+
+        let Some((secret, enabled, last_step)) = self.database.get_totp(user_id).await? else {
+            return Err(TotpError::NotEnrolled);
+        };
+
+        if enabled {
+            return Err(TotpError::AlreadyEnabled);
+        }
+
+        let Some(step) = totp::verify(&secret, &user.username, code, last_step)? else {
+            return Err(TotpError::InvalidCode);
+        };
+
+        let recovery_codes = totp::generate_recovery_codes();
+        self.database
+            .enable_totp(
+                user_id,
+                step,
+                recovery_codes
+                    .iter()
+                    .map(|code| totp::hash_recovery_code(code))
+                    .collect(),
+            )
+            .await?;
+
+        Ok(recovery_codes)
+    }
+
+    /// Completes a session which is waiting for its second factor.
+    #[instrument(skip_all)]
+    pub async fn totp_verify(&self, session: Cookie<'_>, code: &str) -> Result<(), TotpError> {
+        let Ok(stored) = self
+            .database
+            .get_session(self.sessions.hash_token(session.value()))
+            .await
+        else {
+            return Err(TotpError::Unauthorized);
+        };
+
+        if !stored.pending {
+            return Ok(());
+        }
+
+        self.check_second_factor(stored.user_id, code).await?;
+
+        self.database
+            .complete_session(
+                self.sessions.hash_token(session.value()),
+                self.sessions.expires(stored.created, unix_now()),
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn totp_disable(&self, user_id: u32, code: &str) -> Result<(), TotpError> {
+        self.check_second_factor(user_id, code).await?;
+
+        Ok(self.database.delete_totp(user_id).await?)
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn totp_recovery_codes(
+        &self,
+        user_id: u32,
+        code: &str,
+    ) -> Result<Vec<String>, TotpError> {
+        self.check_second_factor(user_id, code).await?;
+
+        let recovery_codes = totp::generate_recovery_codes();
+        self.database
+            .replace_recovery_codes(
+                user_id,
+                recovery_codes
+                    .iter()
+                    .map(|code| totp::hash_recovery_code(code))
+                    .collect(),
+            )
+            .await?;
+
+        Ok(recovery_codes)
+    }
+
+    /// Accepts either a TOTP code or one of the unused recovery codes.
+    async fn check_second_factor(&self, user_id: u32, code: &str) -> Result<(), TotpError> {
+        let throttle_key = user_id.to_string();
+        self.totp
+            .throttle
+            .check(&throttle_key)
+            .map_err(TotpError::Throttled)?;
+
+        let Some((secret, true, last_step)) = self.database.get_totp(user_id).await? else {
+            return Err(TotpError::NotEnrolled);
+        };
+        let user = self.database.get_user(user_id).await?;
+
+        if let Some(step) = totp::verify(&secret, &user.username, code, last_step)? {
+            self.database.set_totp_last_step(user_id, step).await?;
+        } else if !self
+            .database
+            .use_recovery_code(user_id, &totp::hash_recovery_code(code))
+            .await?
+        {
+            self.totp.throttle.fail(&throttle_key);
+            return Err(TotpError::InvalidCode);
+        }
+
+        self.totp.throttle.reset(&throttle_key);
+
+        Ok(())
+    }
+
+    /// Creates a session for a user who passed a primary login. When the
+    /// user has TOTP enabled the session is pending until a code is given.
+    async fn create_session(&self, user_id: u32, client: ClientInfo) -> Result<NewSession, ()> {
+        if self.database.get_user(user_id).await?.disabled {
+            error!("Refused to create a session for a disabled account");
+            return Err(());
+        }
+
+        let session_value = SessionConfig::new_token()?;
+
+        let two_factor_required = self.database.totp_enabled(user_id).await?;
+
+        let now = unix_now();
+        let session_expires = if two_factor_required {
+            now + totp::PENDING_SESSION_SECS
+        } else {
+            self.sessions.expires(now, now)
+        };
+
+        self.database
+            .add_session(
+                user_id,
+                self.sessions.hash_token(&session_value),
+                session_expires,
+                two_factor_required,
+                client.user_agent,
+                client.ip,
+            )
+            .await?;
+
+        Ok(NewSession {
+            csrf_token: self.sessions.csrf_token(&session_value),
+            value: session_value,
+            two_factor_required,
+            max_age: self.sessions.cookie_max_age(),
+            same_site: self.sessions.same_site,
+        })
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn sessions(&self, principal: &Principal) -> Result<Vec<SessionInfo>, ()> {
+        self.database
+            .get_sessions(
+                principal.user_id,
+                principal.session.clone().unwrap_or_default(),
+            )
+            .await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn revoke_session(&self, user_id: u32, session_id: u32) -> Result<bool, ()> {
+        self.database
+            .delete_session_by_id(user_id, session_id)
+            .await
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn revoke_other_sessions(&self, principal: &Principal) -> Result<(), ()> {
+        self.database
+            .delete_other_sessions(
+                principal.user_id,
+                principal.session.clone().unwrap_or_default(),
+            )
+            .await
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn access_tokens(&self, user_id: u32) -> Result<Vec<AccessTokenInfo>, ()> {
+        self.database.get_access_tokens(user_id).await
+    }
+
+    /// Returns the id of the new token and the token itself, which is only
+    /// ever shown this once.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn create_access_token(
+        &self,
+        user_id: u32,
+        name: &str,
+        scopes: Vec<Scope>,
+        expires_in_days: Option<u64>,
+        list_id: Option<u32>,
+    ) -> Result<Option<(u32, String)>, ()> {
+        if let Some(list_id) = list_id
+            && !self
+                .database
+                .todo_list_exists(user_id, list_id)
+                .await
+                .map_err(|_| ())?
+        {
+            return Ok(None);
+        }
+
+        let token = token::new_token()?;
+
+        let expires = expires_in_days.map(|days| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                + days * 60 * 60 * 24
+        });
+
+        let id = self
+            .database
+            .add_access_token(
+                user_id,
+                name.trim().to_string(),
+                self.sessions.hash_token(&token),
+                scopes
+                    .iter()
+                    .map(|scope| scope.as_str().to_string())
+                    .collect(),
+                expires,
+                list_id,
+            )
+            .await?;
+
+        Ok(Some((id, token)))
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn revoke_access_token(&self, user_id: u32, token_id: u32) -> Result<bool, ()> {
+        self.database.delete_access_token(user_id, token_id).await
+    }
+
+    /// Everything stored about the user, apart from secrets like password
+    /// hashes and token values.
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn export_data(&self, principal: &Principal, ip: &str) -> Result<DataExport, ()> {
+        let user_id = principal.user_id;
+
+        self.audit(principal, "account.export", Some(user_id), None, ip)
+            .await?;
+
+        Ok(DataExport {
+            version: account::EXPORT_VERSION,
+            exported: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs(),
+            user: self.database.get_user(user_id).await?,
+            todo_items: self
+                .database
+                .get_todo_items(user_id, None)
+                .await
+                .map_err(|_| ())?,
+            todo_lists: self
+                .database
+                .get_todo_lists(user_id, None)
+                .await
+                .map_err(|_| ())?,
+            sessions: self.sessions(principal).await?,
+            access_tokens: self.database.get_access_tokens(user_id).await?,
+            passkeys: self.database.get_webauthn_credentials(user_id).await?,
+            two_factor_enabled: self.database.totp_enabled(user_id).await?,
+            audit_entries: self.database.get_user_audit_entries(user_id).await?,
+        })
+    }
+
+    /// Returns a token confirming the deletion of the account and when it
+    /// expires.
+    pub fn request_account_deletion(&self, principal: &Principal) -> (String, u64) {
+        let expires = SystemTime::now()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
             .as_secs()
-            + 60 * 60 * 6;
+            + account::CONFIRMATION_SECS;
+
+        (
+            self.account_deletion.confirmation_token(
+                &self.sessions,
+                principal.session.as_deref().unwrap_or_default(),
+                expires,
+            ),
+            expires,
+        )
+    }
+
+    /// Deletes the account, or with a grace period returns when it will be
+    /// deleted. Confirming an already scheduled deletion doesn't postpone it.
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn delete_account(
+        &self,
+        principal: &Principal,
+        confirmation_token: &str,
+        ip: &str,
+    ) -> Result<Option<u64>, AccountError> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        if !self.account_deletion.verify_confirmation_token(
+            &self.sessions,
+            principal.session.as_deref().unwrap_or_default(),
+            confirmation_token,
+            now,
+        ) {
+            return Err(AccountError::InvalidConfirmation);
+        }
+
+        if self.account_deletion.grace_secs == 0 {
+            self.delete_user(principal.user_id).await?;
+            return Ok(None);
+        }
+
+        if let Some(deletion) = self
+            .database
+            .get_user(principal.user_id)
+            .await?
+            .deletion_scheduled
+        {
+            return Ok(Some(deletion));
+        }
+
+        let deletion = now + self.account_deletion.grace_secs;
 
         self.database
-            .add_session(user_id, session_value.clone(), session_expires.clone())
+            .schedule_user_deletion(principal.user_id, Some(deletion))
             .await?;
+        self.audit(
+            principal,
+            "account.deletion.schedule",
+            Some(principal.user_id),
+            Some(deletion.to_string()),
+            ip,
+        )
+        .await?;
 
-        Ok(session_value)
+        Ok(Some(deletion))
     }
 
-    pub async fn logout(&self, session: Cookie<'_>) -> Result<(), ()> {
-        self.database
-            .delete_session(session.value().to_string())
+    /// Returns whether a deletion was scheduled.
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn cancel_account_deletion(
+        &self,
+        principal: &Principal,
+        ip: &str,
+    ) -> Result<bool, ()> {
+        if !self
+            .database
+            .schedule_user_deletion(principal.user_id, None)
+            .await?
+        {
+            return Ok(false);
+        }
+
+        self.audit(
+            principal,
+            "account.deletion.cancel",
+            Some(principal.user_id),
+            None,
+            ip,
+        )
+        .await?;
+
+        Ok(true)
+    }
+
+    /// Removes everything which expired and deletes the accounts whose grace
+    /// period is over.
+    #[instrument(skip_all)]
+    pub async fn sweep(&self) -> Result<(), ()> {
+        let started = Instant::now();
+
+        let swept = self
+            .database
+            .delete_expired(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            )
             .await?;
+        let accounts = self.purge_scheduled_deletions().await?;
+
+        info!(
+            "Swept {} sessions, {} OAuth states, {} passkey challenges, {} access tokens and {} accounts in {} ms",
+            swept.sessions,
+            swept.oauth_states,
+            swept.webauthn_challenges,
+            swept.access_tokens,
+            accounts,
+            started.elapsed().as_millis()
+        );
 
         Ok(())
     }
 
-    pub async fn get_items(&self, user_id: u32) -> Result<Vec<TodoItem>, ()> {
-        self.database.get_todo_items(user_id).await.map_err(|_| ())
+    /// Deletes the accounts whose grace period is over.
+    async fn purge_scheduled_deletions(&self) -> Result<usize, ()> {
+        let due = self
+            .database
+            .get_due_user_deletions(
+                SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .unwrap()
+                    .as_secs(),
+            )
+            .await?;
+
+        for user_id in &due {
+            self.delete_user(*user_id).await?;
+        }
+
+        Ok(due.len())
+    }
+
+    /// The audit entry doesn't reference the deleted account, its id may be
+    /// given to a new one.
+    async fn delete_user(&self, user_id: u32) -> Result<(), ()> {
+        if self.database.delete_user(user_id).await? {
+            self.database
+                .add_audit_entry(None, "account.delete", None, None, None)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    fn github(&self) -> Result<&GitHub, ()> {
+        self.github.as_ref().ok_or_else(|| {
+            error!("GitHub sign-in isn't configured");
+        })
+    }
+
+    /// The hash of the invite code when new accounts need one. The database
+    /// counts its use together with adding the user.
+    fn invite_hash<E>(&self, invite_code: Option<&str>) -> Result<Option<String>, E>
+    where
+        E: From<AdmissionError>,
+    {
+        if !self.admission.invite_only {
+            return Ok(None);
+        }
+
+        match invite_code.filter(|code| !code.trim().is_empty()) {
+            Some(invite_code) => Ok(Some(admission::hash_invite_code(invite_code))),
+            None => Err(AdmissionError::InviteRequired.into()),
+        }
+    }
+
+    /// Gives the accounts of the GitHub users listed in `ADMIN_GITHUB_IDS`
+    /// the admin role. Those without an account get it when they sign up.
+    #[instrument(skip_all)]
+    pub async fn bootstrap_admins(&self) -> Result<(), ()> {
+        if self.admins.is_empty() {
+            return Ok(());
+        }
+
+        let existing = self.promote_admins(&self.admins).await?;
+
+        if existing < self.admins.len() as u64 {
+            info!(
+                "{} of the {} GitHub users in ADMIN_GITHUB_IDS don't have an account yet",
+                self.admins.len() as u64 - existing,
+                self.admins.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Returns how many of the GitHub users have an account, the audit log
+    /// only records the ones whose role changed.
+    async fn promote_admins(&self, github_ids: &[u32]) -> Result<u64, ()> {
+        let (existing, updated) = self
+            .database
+            .set_role_by_github_id(github_ids, Role::Admin)
+            .await?;
+
+        if updated > 0 {
+            self.database
+                .add_audit_entry(
+                    None,
+                    "admins.bootstrap",
+                    None,
+                    Some(
+                        github_ids
+                            .iter()
+                            .map(u32::to_string)
+                            .collect::<Vec<_>>()
+                            .join(","),
+                    ),
+                    None,
+                )
+                .await?;
+        }
+
+        Ok(existing)
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn admin_users(
+        &self,
+        principal: &Principal,
+        query: Option<&str>,
+        limit: u32,
+        offset: u32,
+        ip: &str,
+    ) -> Result<Vec<User>, ()> {
+        self.audit(principal, "users.search", None, query.map(String::from), ip)
+            .await?;
+
+        self.database
+            .search_users(query, limit.min(admin::MAX_PAGE_SIZE), offset)
+            .await
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn admin_stats(&self, principal: &Principal, ip: &str) -> Result<AdminStats, ()> {
+        self.audit(principal, "stats.view", None, None, ip).await?;
+        self.database.stats().await
+    }
+
+    /// Disabling an account ends its sessions and stops its personal access
+    /// tokens from working until it is enabled again.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn admin_set_disabled(
+        &self,
+        principal: &Principal,
+        user_id: u32,
+        disabled: bool,
+        ip: &str,
+    ) -> Result<(), AdminError> {
+        if user_id == principal.user_id {
+            return Err(AdminError::OwnAccount);
+        }
+
+        if !self.database.set_user_disabled(user_id, disabled).await? {
+            return Err(AdminError::NotFound);
+        }
+
+        let action = if disabled {
+            "user.disable"
+        } else {
+            "user.enable"
+        };
+        Ok(self
+            .audit(principal, action, Some(user_id), None, ip)
+            .await?)
+    }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn admin_logout(
+        &self,
+        principal: &Principal,
+        user_id: u32,
+        ip: &str,
+    ) -> Result<(), AdminError> {
+        if user_id == principal.user_id {
+            return Err(AdminError::OwnAccount);
+        }
+
+        if self.database.get_user(user_id).await.is_err() {
+            return Err(AdminError::NotFound);
+        }
+
+        let ended = self.database.delete_user_sessions(user_id).await?;
+
+        Ok(self
+            .audit(
+                principal,
+                "user.logout",
+                Some(user_id),
+                Some(format!("{} sessions ended", ended)),
+                ip,
+            )
+            .await?)
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn audit_log(
+        &self,
+        principal: &Principal,
+        limit: u32,
+        offset: u32,
+        ip: &str,
+    ) -> Result<Vec<AuditEntry>, ()> {
+        self.audit(principal, "audit.view", None, None, ip).await?;
+
+        self.database
+            .get_audit_entries(limit.min(admin::MAX_PAGE_SIZE), offset)
+            .await
+    }
+
+    /// Returns the id of the new invite code and the code itself, which is
+    /// only ever shown this once.
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn admin_create_invite(
+        &self,
+        principal: &Principal,
+        max_uses: u32,
+        expires_in_days: Option<u64>,
+        ip: &str,
+    ) -> Result<(u32, String), ()> {
+        let code = admission::new_invite_code();
+
+        let expires = expires_in_days.map(|days| {
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap()
+                .as_secs()
+                + days * 60 * 60 * 24
+        });
+
+        let id = self
+            .database
+            .add_invite(
+                admission::hash_invite_code(&code),
+                principal.user_id,
+                max_uses,
+                expires,
+            )
+            .await?;
+
+        self.audit(
+            principal,
+            "invite.create",
+            None,
+            Some(format!("invite {}, {} uses", id, max_uses)),
+            ip,
+        )
+        .await?;
+
+        Ok((id, code))
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn admin_invites(
+        &self,
+        principal: &Principal,
+        ip: &str,
+    ) -> Result<Vec<InviteInfo>, ()> {
+        self.audit(principal, "invites.list", None, None, ip)
+            .await?;
+        self.database.get_invites().await
+    }
+
+    #[instrument(skip_all, fields(user_id = principal.user_id))]
+    pub async fn admin_revoke_invite(
+        &self,
+        principal: &Principal,
+        invite_id: u32,
+        ip: &str,
+    ) -> Result<(), AdminError> {
+        if !self.database.delete_invite(invite_id).await? {
+            return Err(AdminError::NotFound);
+        }
+
+        Ok(self
+            .audit(
+                principal,
+                "invite.revoke",
+                None,
+                Some(format!("invite {}", invite_id)),
+                ip,
+            )
+            .await?)
+    }
+
+    async fn audit(
+        &self,
+        principal: &Principal,
+        action: &str,
+        target_id: Option<u32>,
+        details: Option<String>,
+        ip: &str,
+    ) -> Result<(), ()> {
+        self.database
+            .add_audit_entry(
+                Some(principal.user_id),
+                action,
+                target_id,
+                details,
+                Some(ip),
+            )
+            .await
+    }
+
+    pub fn csrf_token(&self, session: Cookie<'_>) -> String {
+        self.sessions.csrf_token(session.value())
+    }
+
+    /// Whether the CSRF token belongs to the session, which isn't checked
+    /// to exist.
+    pub fn verify_csrf_token(&self, session: Cookie<'_>, csrf_token: &str) -> bool {
+        self.sessions.verify_csrf_token(session.value(), csrf_token)
+    }
+
+    pub fn cookie_same_site(&self) -> SameSite {
+        self.sessions.same_site
+    }
+
+    /// Returns whether the session existed. Sessions waiting for their second
+    /// factor can be ended as well.
+    #[instrument(skip_all)]
+    pub async fn logout(&self, session: Cookie<'_>) -> Result<bool, ()> {
+        self.database
+            .delete_session(self.sessions.hash_token(session.value()))
+            .await
     }
 
-    pub async fn add_item(&self, user_id: u32, content: String) -> Result<(), ()> {
+    /// Only the items on `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_items(&self, user_id: u32, list_id: Option<u32>) -> Result<Vec<TodoItem>, ()> {
         self.database
-            .add_todo_item(user_id, content)
+            .get_todo_items(user_id, list_id)
             .await
             .map_err(|_| ())
     }
 
-    pub async fn update_item(&self, user_id: u32, item_id: u32, done: bool) -> Result<(), ()> {
+    /// How many items the user has, on `list_id` when it is given, and how
+    /// many of them are done.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn count_items(&self, user_id: u32, list_id: Option<u32>) -> Result<(u32, u32), ()> {
         self.database
-            .update_todo_item(user_id, item_id, done)
+            .count_todo_items(user_id, list_id)
             .await
             .map_err(|_| ())
     }
 
-    pub async fn delete_item(&self, user_id: u32, item_id: u32) -> Result<(), ()> {
+    /// Puts the item on `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn add_item(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+        content: &str,
+    ) -> Result<TodoItem, TodoError> {
+        let content = self.todo_limits.check_content(content)?;
+
+        if let Some(list_id) = list_id
+            && !self
+                .database
+                .todo_list_exists(user_id, list_id)
+                .await
+                .map_err(|_| TodoError::Internal)?
+        {
+            return Err(TodoError::Invalid(vec![FieldError::new(
+                "list_id",
+                "not_found",
+                "The list doesn't exist",
+            )]));
+        }
+
+        match self
+            .database
+            .add_todo_item(
+                user_id,
+                list_id,
+                content,
+                self.todo_limits.max_items_per_user,
+            )
+            .await
+        {
+            Ok(Some(item)) => {
+                self.publish(user_id, TodoChange::Added(item.clone()));
+                Ok(item)
+            }
+            Ok(None) => Err(TodoError::QuotaReached),
+            Err(_) => Err(TodoError::Internal),
+        }
+    }
+
+    /// `None` when the user has no such item, on `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn update_item(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+        item_id: u32,
+        done: bool,
+    ) -> Result<Option<TodoItem>, ()> {
+        let item = self
+            .database
+            .update_todo_item(user_id, list_id, item_id, done)
+            .await
+            .map_err(|_| ())?;
+
+        if let Some(item) = &item {
+            self.publish(user_id, TodoChange::Updated(item.clone()));
+        }
+        Ok(item)
+    }
+
+    /// `false` when the user has no such item, on `list_id` when it is
+    /// given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_item(
+        &self,
+        user_id: u32,
+        list_id: Option<u32>,
+        item_id: u32,
+    ) -> Result<bool, ()> {
+        let item = self
+            .database
+            .delete_todo_item(user_id, list_id, item_id)
+            .await
+            .map_err(|_| ())?;
+
+        match item {
+            Some(item) => {
+                self.publish(user_id, TodoChange::Deleted(item));
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Only `list_id` when it is given.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn get_lists(&self, user_id: u32, list_id: Option<u32>) -> Result<Vec<TodoList>, ()> {
         self.database
-            .delete_todo_item(user_id, item_id)
+            .get_todo_lists(user_id, list_id)
             .await
             .map_err(|_| ())
     }
+
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn add_list(&self, user_id: u32, name: &str) -> Result<TodoList, TodoError> {
+        let name = self.todo_limits.check_list_name(name)?;
+
+        match self
+            .database
+            .add_todo_list(user_id, name, self.todo_limits.max_lists_per_user)
+            .await
+        {
+            Ok(Some(list)) => Ok(list),
+            Ok(None) => Err(TodoError::QuotaReached),
+            Err(_) => Err(TodoError::Internal),
+        }
+    }
+
+    /// Deletes the items on the list and the tokens restricted to it as
+    /// well. `false` when the user has no such list.
+    #[instrument(skip_all, fields(user_id = user_id))]
+    pub async fn delete_list(&self, user_id: u32, list_id: u32) -> Result<bool, ()> {
+        let items = self
+            .database
+            .delete_todo_list(user_id, list_id)
+            .await
+            .map_err(|_| ())?;
+
+        match items {
+            Some(items) => {
+                for item in items {
+                    self.publish(user_id, TodoChange::Deleted(item));
+                }
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// The changes to every user's items from now on.
+    pub fn item_changes(&self) -> broadcast::Receiver<TodoEvent> {
+        self.todo_events.subscribe()
+    }
+
+    /// Nobody listening isn't an error.
+    fn publish(&self, user_id: u32, change: TodoChange) {
+        let _ = self.todo_events.send(TodoEvent { user_id, change });
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn ceremony_expires() -> u64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+        + webauthn::CEREMONY_TIMEOUT_SECS
+}
+
+fn credential_name(name: &str) -> String {
+    let name: String = name.trim().chars().take(64).collect();
+
+    if name.is_empty() {
+        String::from("Passkey")
+    } else {
+        name
+    }
 }
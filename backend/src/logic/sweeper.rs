@@ -0,0 +1,51 @@
+//! ```sh
+//! SWEEP_INTERVAL_SECS=300 make run_release
+//! ```
+//!
+//! Expired sessions, OAuth states, passkey challenges and access tokens are
+//! otherwise only removed when someone presents them, which abandoned ones
+//! never are. Every sweep also deletes the accounts whose grace period is
+//! over. The first sweep runs when the server starts.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{self, MissedTickBehavior};
+use tracing::info;
+
+use crate::Logic;
+use crate::config::SweeperConfig;
+
+pub struct Sweeper {
+    interval: Duration,
+}
+
+impl Sweeper {
+    pub fn new(config: &SweeperConfig) -> Self {
+        Sweeper {
+            interval: Duration::from_secs(config.interval_secs),
+        }
+    }
+
+    /// Sweeps until `shutdown` is sent, a sweep which already started is
+    /// finished first. Failed sweeps are retried at the next interval.
+    pub fn spawn(self, logic: Arc<Logic>, mut shutdown: watch::Receiver<bool>) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = time::interval(self.interval);
+            interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {
+                        let _ = logic.sweep().await;
+                    }
+                    _ = shutdown.changed() => break,
+                }
+            }
+
+            info!("Stopped the sweeper");
+        })
+    }
+}
@@ -0,0 +1,218 @@
+//! ```sh
+//! TODO_MAX_CONTENT_CHARS=280 TODO_MAX_ITEMS_PER_USER=100 make run_release
+//! ```
+//!
+//! The content of an item is normalized to NFC and trimmed before it is
+//! checked, and stored the way it was checked. Control characters, line
+//! breaks included, are rejected rather than stripped. The names of lists
+//! follow the same rules.
+//!
+//! Every change to an item is also published as a [`TodoEvent`], which
+//! GraphQL subscriptions forward to the owner of the item.
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::app::handlers::{FieldError, TodoItem};
+use crate::config::TodoConfig;
+
+#[derive(Debug)]
+pub enum TodoError {
+    Invalid(Vec<FieldError>),
+    /// The user has as many items, or lists, as they are allowed.
+    QuotaReached,
+    Internal,
+}
+
+impl From<()> for TodoError {
+    fn from(_: ()) -> Self {
+        TodoError::Internal
+    }
+}
+
+/// How many events a slow subscriber can fall behind before it misses some.
+pub const EVENT_CAPACITY: usize = 256;
+
+#[derive(Clone)]
+pub struct TodoEvent {
+    pub user_id: u32,
+    pub change: TodoChange,
+}
+
+#[derive(Clone)]
+pub enum TodoChange {
+    Added(TodoItem),
+    Updated(TodoItem),
+    /// The item as it was.
+    Deleted(TodoItem),
+}
+
+impl TodoChange {
+    pub fn item(&self) -> &TodoItem {
+        match self {
+            TodoChange::Added(item) | TodoChange::Updated(item) | TodoChange::Deleted(item) => item,
+        }
+    }
+}
+
+pub struct TodoLimits {
+    max_content_chars: usize,
+    pub max_items_per_user: u32,
+    pub max_lists_per_user: u32,
+}
+
+impl TodoLimits {
+    pub fn new(config: &TodoConfig) -> Self {
+        TodoLimits {
+            max_content_chars: config.max_content_chars,
+            max_items_per_user: config.max_items_per_user,
+            max_lists_per_user: config.max_lists_per_user,
+        }
+    }
+
+    /// The content to store, or every rule it breaks.
+    pub fn check_content(&self, content: &str) -> Result<String, TodoError> {
+        self.check_text("content", content)
+    }
+
+    /// The name of a list to store, or every rule it breaks.
+    pub fn check_list_name(&self, name: &str) -> Result<String, TodoError> {
+        self.check_text("name", name)
+    }
+
+    fn check_text(&self, field: &str, text: &str) -> Result<String, TodoError> {
+        let text = text.nfc().collect::<String>().trim().to_string();
+        let mut errors = Vec::new();
+
+        if text.is_empty() {
+            errors.push(FieldError::new(
+                field,
+                "required",
+                format!("The {} can't be empty", field),
+            ));
+        }
+        if text.chars().any(char::is_control) {
+            errors.push(FieldError::new(
+                field,
+                "control_characters",
+                format!(
+                    "The {} can't contain control characters or line breaks",
+                    field
+                ),
+            ));
+        }
+        let length = text.chars().count();
+        if length > self.max_content_chars {
+            errors.push(FieldError::new(
+                field,
+                "too_long",
+                format!(
+                    "The {} can be at most {} characters, it is {}",
+                    field, self.max_content_chars, length
+                ),
+            ));
+        }
+
+        if errors.is_empty() {
+            Ok(text)
+        } else {
+            Err(TodoError::Invalid(errors))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TodoError, TodoLimits};
+    use crate::config::{Config, TodoConfig};
+    use crate::database::{NewUser, TestDatabase};
+    use crate::logic::Logic;
+
+    fn limits(max_content_chars: usize) -> TodoLimits {
+        TodoLimits::new(&TodoConfig {
+            max_content_chars,
+            ..TodoConfig::default()
+        })
+    }
+
+    fn codes(result: Result<String, TodoError>) -> Vec<&'static str> {
+        match result {
+            Err(TodoError::Invalid(fields)) => fields.iter().map(|field| field.code).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    #[test]
+    fn content_is_normalized_before_it_is_checked() {
+        let limits = limits(5);
+
+        // "e" and a combining acute accent become a single "é".
+        assert_eq!(
+            limits.check_content("  cafe\u{301}  ").unwrap(),
+            "caf\u{e9}"
+        );
+        assert_eq!(limits.check_content("éééé").unwrap(), "éééé");
+        assert_eq!(codes(limits.check_content("ééééé!")), ["too_long"]);
+    }
+
+    #[test]
+    fn every_broken_rule_is_reported() {
+        let limits = limits(5);
+
+        assert_eq!(codes(limits.check_content(" \t ")), ["required"]);
+        assert_eq!(
+            codes(limits.check_content("one\ntwo")),
+            ["control_characters", "too_long"]
+        );
+
+        let Err(TodoError::Invalid(fields)) = limits.check_list_name("") else {
+            panic!("An empty name was accepted");
+        };
+        assert_eq!(fields[0].field, "name");
+    }
+
+    #[actix_web::test]
+    async fn items_are_limited_per_user() {
+        let (_test_database, database) = TestDatabase::connect("todo-quota").await;
+        let user = database
+            .add_local_user(
+                String::from("alice"),
+                String::from("alice@example.com"),
+                String::new(),
+                None,
+            )
+            .await;
+        assert_eq!(user, Ok(NewUser::Added(1)));
+
+        let config = Config {
+            todo: TodoConfig {
+                max_items_per_user: 2,
+                max_lists_per_user: 1,
+                ..TodoConfig::default()
+            },
+            ..Config::default()
+        };
+        let logic = Logic::new(database, &config);
+
+        let list = logic.add_list(1, "Groceries").await.unwrap();
+        assert!(matches!(
+            logic.add_list(1, "Chores").await,
+            Err(TodoError::QuotaReached)
+        ));
+
+        assert!(
+            logic
+                .add_item(1, Some(list.id as u32), "Buy milk")
+                .await
+                .is_ok()
+        );
+        assert!(matches!(
+            logic.add_item(1, Some(99), "Buy eggs").await,
+            Err(TodoError::Invalid(fields)) if fields[0].field == "list_id"
+        ));
+        assert!(logic.add_item(1, None, "Buy eggs").await.is_ok());
+        assert!(matches!(
+            logic.add_item(1, None, "Buy bread").await,
+            Err(TodoError::QuotaReached)
+        ));
+    }
+}
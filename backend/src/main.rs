@@ -1,38 +1,159 @@
-use std::{process::ExitCode, sync::Arc};
+use std::{process::ExitCode, sync::Arc, time::Duration};
 
-use tracing::{error, info};
-use tracing_subscriber;
+use clap::Parser;
+use tokio::task::JoinHandle;
+use tokio::time;
+use tracing::{error, info, warn};
 
 mod app;
+mod config;
 mod database;
+mod logging;
 mod logic;
-use app::App;
+mod metrics;
+mod shutdown;
+mod telemetry;
+use app::{App, Tls};
+use config::{Cli, Config};
 use database::Database;
-use logic::Logic;
+use logic::{
+    AccountError, AdminError, AdmissionError, AuthenticationCredential, ClientInfo, Credential,
+    GitHubError, LocalAuthError, Logic, NewSession, Principal, RegistrationCredential, Role, Scope,
+    Sweeper, TodoChange, TodoError, TotpError,
+};
+use shutdown::Shutdown;
 
 #[tokio::main]
 async fn main() -> ExitCode {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+
+    let config = Config::load(&cli);
+    let defaults = Config::default();
+    let loaded = config.as_ref().unwrap_or(&defaults);
+    let Ok(telemetry) = logging::init(&loaded.logging, &loaded.otlp) else {
+        error!("Exiting the program");
+        return ExitCode::from(1);
+    };
+
+    let config = match config {
+        Ok(config) => config,
+        Err(errors) => {
+            errors.iter().for_each(|err| error!("{}", err));
+            error!("Exiting the program");
+            return ExitCode::from(1);
+        }
+    };
+
+    if cli.print_config {
+        match config.to_toml() {
+            Ok(config) => print!("{}", config),
+            Err(err) => error!("Can't print the configuration: {}", err),
+        }
+    }
+
+    let errors = config.validate();
+    if !errors.is_empty() {
+        errors.iter().for_each(|err| error!("{}", err));
+        error!("Exiting the program");
+        return ExitCode::from(1);
+    }
+
+    if cli.print_config {
+        return ExitCode::from(0);
+    }
 
     info!("Creating a database connection pool");
-    let Ok(database) = Database::connect("database.sqlite3", 5).await else {
+    let Ok(database) =
+        Database::connect(&config.database.path, config.database.max_connections).await
+    else {
         error!("Exiting the program");
         return ExitCode::from(1);
     };
 
-    info!("Starting the web API");
-    if let Err(_) = App::run(
-        "0.0.0.0",
-        8080,
-        String::from("https://todo.celarye.dev"),
-        Arc::new(Logic::new(database)),
-    )
-    .await
-    {
+    let logic = Logic::new(database, &config);
+
+    if logic.bootstrap_admins().await.is_err() {
+        error!("Exiting the program");
+        return ExitCode::from(1);
+    }
+
+    let logic = Arc::new(logic);
+
+    let tls = match Tls::load(&config.tls) {
+        Ok(tls) => tls.map(Arc::new),
+        Err(_) => {
+            error!("Exiting the program");
+            return ExitCode::from(1);
+        }
+    };
+
+    let shutdown = Shutdown::new();
+    if shutdown.listen_for_signals().is_err() {
         error!("Exiting the program");
         return ExitCode::from(1);
+    }
+
+    info!("Starting the sweeper");
+    let sweeper = Sweeper::new(&config.sweeper).spawn(logic.clone(), shutdown.subscribe());
+
+    let tls_reloader = match &tls {
+        Some(tls) => match tls.clone().spawn_reloader(shutdown.subscribe()) {
+            Ok(tls_reloader) => Some(tls_reloader),
+            Err(_) => {
+                error!("Exiting the program");
+                return ExitCode::from(1);
+            }
+        },
+        None => None,
     };
 
+    info!("Starting the web API");
+    let result = App::run(&config, tls.as_deref(), logic.clone(), &shutdown).await;
+
+    // Also when the server failed, so the background tasks stop.
+    shutdown.start();
+
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_secs);
+    let mut clean = stop_task("sweeper", sweeper, timeout).await;
+    if let Some(tls_reloader) = tls_reloader {
+        clean &= stop_task("TLS reloader", tls_reloader, timeout).await;
+    }
+
+    info!("Closing the database");
+    clean &= logic.close().await.is_ok();
+
+    telemetry.shutdown();
+
+    if let Err(err) = result {
+        error!("The web API failed: {}", err);
+        error!("Exiting the program");
+        return ExitCode::from(1);
+    }
+
+    if !clean {
+        error!("Exiting the program after an incomplete shutdown");
+        return ExitCode::from(2);
+    }
+
     info!("Exiting the program");
     ExitCode::from(0)
 }
+
+/// Waits for a background task which was told to stop, aborting it when it
+/// doesn't within `timeout`.
+async fn stop_task(name: &str, task: JoinHandle<()>, timeout: Duration) -> bool {
+    let abort = task.abort_handle();
+
+    match time::timeout(timeout, task).await {
+        Ok(Ok(())) => true,
+        Ok(Err(_)) => {
+            error!("The {} panicked", name);
+            false
+        }
+        Err(_) => {
+            warn!("The {} didn't stop in time, aborting it", name);
+            abort.abort();
+            false
+        }
+    }
+}
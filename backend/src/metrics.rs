@@ -0,0 +1,143 @@
+//! Prometheus metrics, served on `/metrics`.
+//!
+//! Counters and histograms are updated where things happen. The gauges
+//! reading the database (sessions, users and items) and the connection pool
+//! are only updated when the metrics are scraped.
+
+use std::sync::LazyLock;
+
+use prometheus::core::Collector;
+use prometheus::{
+    HistogramOpts, HistogramTimer, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use tracing::error;
+
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub struct Metrics {
+    registry: Registry,
+    /// By method, route pattern and status.
+    pub http_requests: IntCounterVec,
+    /// By method, route pattern and status.
+    pub http_request_duration: HistogramVec,
+    /// By `Database` method.
+    pub db_query_duration: HistogramVec,
+    /// By state, `idle` or `in_use`.
+    pub db_connections: IntGaugeVec,
+    pub db_max_connections: IntGauge,
+    pub active_sessions: IntGauge,
+    pub users: IntGauge,
+    /// By state, `open` or `done`.
+    pub todo_items: IntGaugeVec,
+    /// By provider and result, `success` or `failure`.
+    pub logins: IntCounterVec,
+    /// By budget, `anonymous`, `authenticated` or `auth`.
+    pub rate_limited: IntCounterVec,
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+
+        let metrics = Metrics {
+            http_requests: IntCounterVec::new(
+                Opts::new("http_requests_total", "HTTP requests handled"),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            http_request_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "http_request_duration_seconds",
+                    "How long HTTP requests took to handle",
+                ),
+                &["method", "route", "status"],
+            )
+            .unwrap(),
+            db_query_duration: HistogramVec::new(
+                HistogramOpts::new(
+                    "db_query_duration_seconds",
+                    "How long database methods took, waiting for a connection included",
+                )
+                .buckets(vec![
+                    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
+                ]),
+                &["method"],
+            )
+            .unwrap(),
+            db_connections: IntGaugeVec::new(
+                Opts::new("db_connections", "Open database connections"),
+                &["state"],
+            )
+            .unwrap(),
+            db_max_connections: IntGauge::new(
+                "db_max_connections",
+                "The most database connections the pool opens",
+            )
+            .unwrap(),
+            active_sessions: IntGauge::new(
+                "active_sessions",
+                "Unexpired sessions which completed their login",
+            )
+            .unwrap(),
+            users: IntGauge::new("users", "Registered users").unwrap(),
+            todo_items: IntGaugeVec::new(Opts::new("todo_items", "Todo items"), &["state"])
+                .unwrap(),
+            logins: IntCounterVec::new(
+                Opts::new("logins_total", "Login attempts"),
+                &["provider", "result"],
+            )
+            .unwrap(),
+            rate_limited: IntCounterVec::new(
+                Opts::new("rate_limited_total", "Requests rejected by the rate limit"),
+                &["budget"],
+            )
+            .unwrap(),
+            registry,
+        };
+
+        let collectors: [Box<dyn Collector>; 10] = [
+            Box::new(metrics.http_requests.clone()),
+            Box::new(metrics.http_request_duration.clone()),
+            Box::new(metrics.db_query_duration.clone()),
+            Box::new(metrics.db_connections.clone()),
+            Box::new(metrics.db_max_connections.clone()),
+            Box::new(metrics.active_sessions.clone()),
+            Box::new(metrics.users.clone()),
+            Box::new(metrics.todo_items.clone()),
+            Box::new(metrics.logins.clone()),
+            Box::new(metrics.rate_limited.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+
+        metrics
+    }
+
+    /// The text exposition format.
+    pub fn encode(&self) -> Result<String, ()> {
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .map_err(|err| {
+                error!("Something went wrong while encoding the metrics: {}", err);
+            })
+    }
+}
+
+pub const CONTENT_TYPE: &str = prometheus::TEXT_FORMAT;
+
+/// Observes how long the `Database` method took once dropped.
+pub fn time_query(method: &str) -> HistogramTimer {
+    METRICS
+        .db_query_duration
+        .with_label_values(&[method])
+        .start_timer()
+}
+
+pub fn record_login(provider: &str, success: bool) {
+    METRICS
+        .logins
+        .with_label_values(&[provider, if success { "success" } else { "failure" }])
+        .inc();
+}
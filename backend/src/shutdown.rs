@@ -0,0 +1,74 @@
+//! Shutting down starts with the first `SIGTERM` or `SIGINT`: the servers
+//! stop accepting connections and get the drain timeout to finish the
+//! requests in flight, background tasks finish what they are doing, and the
+//! database is checkpointed and closed last. A second signal exits right
+//! away.
+//!
+//! The exit code is 0 after a clean shutdown, 1 when the server couldn't
+//! start or failed, 2 when a background task had to be aborted or the
+//! database couldn't be closed cleanly, and 130 after a second signal.
+
+use std::process;
+
+use tokio::signal::unix::{SignalKind, signal};
+use tokio::sync::watch;
+use tracing::{error, info, warn};
+
+/// The exit code after a second signal, as if the default handler had run.
+const FORCED_EXIT_CODE: i32 = 130;
+
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: watch::Sender<bool>,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Shutdown {
+            sender: watch::Sender::new(false),
+        }
+    }
+
+    /// Changes once the shutdown starts.
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.sender.subscribe()
+    }
+
+    pub fn is_started(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Starts the shutdown, also when nothing is listening yet.
+    pub fn start(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Starts the shutdown on the first signal and exits on the second.
+    pub fn listen_for_signals(&self) -> Result<(), ()> {
+        let (Ok(mut terminate), Ok(mut interrupt)) = (
+            signal(SignalKind::terminate()),
+            signal(SignalKind::interrupt()),
+        ) else {
+            error!("Can't listen for SIGTERM and SIGINT");
+            return Err(());
+        };
+
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = terminate.recv() => info!("Received SIGTERM, shutting down"),
+                _ = interrupt.recv() => info!("Received SIGINT, shutting down"),
+            }
+            shutdown.start();
+
+            tokio::select! {
+                _ = terminate.recv() => {}
+                _ = interrupt.recv() => {}
+            }
+            warn!("Received a second signal, exiting without shutting down");
+            process::exit(FORCED_EXIT_CODE);
+        });
+
+        Ok(())
+    }
+}
@@ -0,0 +1,133 @@
+//! ```sh
+//! cargo run --features otel
+//! OTEL_EXPORTER_OTLP_ENDPOINT=http://localhost:4318 make run_release
+//! ```
+//!
+//! With the `otel` feature and an endpoint the spans are exported over OTLP
+//! as well as logged: the requests, the `Logic` and `Database` calls they
+//! make and the requests sent to GitHub. A W3C `traceparent` header on an
+//! incoming request makes its span a child of the caller's.
+
+use actix_web::http::header::HeaderMap;
+#[cfg(feature = "otel")]
+use opentelemetry::{global, propagation::Extractor, trace::TracerProvider};
+#[cfg(feature = "otel")]
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+#[cfg(feature = "otel")]
+use opentelemetry_sdk::{
+    Resource,
+    propagation::TraceContextPropagator,
+    trace::{Sampler, SdkTracerProvider},
+};
+#[cfg(feature = "otel")]
+use tracing::error;
+use tracing::{Span, Subscriber};
+#[cfg(feature = "otel")]
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::Layer;
+use tracing_subscriber::registry::LookupSpan;
+
+use crate::config::OtlpConfig;
+
+/// Added to the subscriber next to the fmt layer.
+pub type ExportLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+/// Flushes the spans which haven't been exported yet when shut down.
+pub struct Telemetry {
+    #[cfg(feature = "otel")]
+    provider: Option<SdkTracerProvider>,
+}
+
+/// The layer exporting spans, `None` without an endpoint. The configuration
+/// has to be validated already.
+#[cfg(feature = "otel")]
+pub fn init<S>(config: &OtlpConfig) -> Result<(Option<ExportLayer<S>>, Telemetry), String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    let Some(endpoint) = &config.endpoint else {
+        return Ok((None, Telemetry { provider: None }));
+    };
+
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(format!("{}/v1/traces", endpoint.trim_end_matches('/')))
+        .build()
+        .map_err(|err| format!("Can't create the OTLP exporter: {}", err))?;
+
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio,
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name.clone())
+                .build(),
+        )
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let layer = tracing_opentelemetry::layer()
+        .with_tracer(provider.tracer(env!("CARGO_PKG_NAME")))
+        .boxed();
+
+    Ok((
+        Some(layer),
+        Telemetry {
+            provider: Some(provider),
+        },
+    ))
+}
+
+#[cfg(not(feature = "otel"))]
+pub fn init<S>(_config: &OtlpConfig) -> Result<(Option<ExportLayer<S>>, Telemetry), String>
+where
+    S: Subscriber + for<'a> LookupSpan<'a> + Send + Sync,
+{
+    Ok((None, Telemetry {}))
+}
+
+impl Telemetry {
+    pub fn shutdown(self) {
+        #[cfg(feature = "otel")]
+        if let Some(provider) = self.provider
+            && let Err(err) = provider.shutdown()
+        {
+            error!(
+                "Something went wrong while exporting the last spans: {}",
+                err
+            );
+        }
+    }
+}
+
+/// Continues the trace of the caller when the request has a `traceparent`
+/// header, and names the request's span after its route for the collector.
+pub fn continue_trace(span: &Span, headers: &HeaderMap, name: String) {
+    #[cfg(feature = "otel")]
+    {
+        struct HeaderExtractor<'a>(&'a HeaderMap);
+
+        impl Extractor for HeaderExtractor<'_> {
+            fn get(&self, key: &str) -> Option<&str> {
+                self.0.get(key).and_then(|value| value.to_str().ok())
+            }
+
+            fn keys(&self) -> Vec<&str> {
+                self.0.keys().map(|key| key.as_str()).collect()
+            }
+        }
+
+        let context = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(headers))
+        });
+        let _ = span.set_parent(context);
+        span.record("otel.name", name);
+        span.record("otel.kind", "server");
+    }
+
+    #[cfg(not(feature = "otel"))]
+    let _ = (span, headers, name);
+}
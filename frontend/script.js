@@ -1,4 +1,4 @@
-const API = "http://localhost:8080";
+const API = "http://localhost:8080/api/v1";
 const gitHubLoginBtn = document.querySelector('.github-login-btn');
 const logoutBtn = document.querySelector('.logout-btn');
 const userInfo = document.querySelector('.user-info');
@@ -8,6 +8,35 @@ const profileImgElement = document.querySelector('.profile-img');
 const loginPrompt = document.querySelector('.login-prompt');
 const todoContainer = document.querySelector('.todo-container');
 const userCountElement = document.querySelector('.user-count span');
+let csrfToken = sessionStorage.getItem('csrf_token');
+
+function storeCsrfToken(token) {
+	csrfToken = token;
+	sessionStorage.setItem('csrf_token', token);
+}
+
+function fetchCsrfToken() {
+	if (csrfToken) {
+		return;
+	}
+
+	fetch(`${API}/user/auth/csrf`, {
+		method: 'GET',
+		credentials: 'include'
+	})
+		.then(response => {
+			if (response.ok) {
+				return response.json();
+			}
+			throw new Error('Failed to fetch CSRF token');
+		})
+		.then(data => {
+			storeCsrfToken(data.csrf_token);
+		})
+		.catch(error => {
+			console.error('Error fetching CSRF token:', error);
+		});
+}
 
 function checkIfLoggedIn() {
 	let cookies = document.cookie.split(";");
@@ -80,7 +109,8 @@ function addTodoItem(content) {
 	fetch(`${API}/todo/set`, {
 		method: 'POST',
 		headers: {
-			'Content-Type': 'application/json'
+			'Content-Type': 'application/json',
+			'X-CSRF-Token': csrfToken
 		},
 		body: JSON.stringify({ content }),
 		credentials: 'include'
@@ -102,7 +132,8 @@ function updateTodoItem(itemId, done) {
 	fetch(`${API}/todo/update/${itemId}`, {
 		method: 'PATCH',
 		headers: {
-			'Content-Type': 'application/json'
+			'Content-Type': 'application/json',
+			'X-CSRF-Token': csrfToken
 		},
 		body: JSON.stringify({ done }),
 		credentials: 'include'
@@ -123,6 +154,9 @@ function updateTodoItem(itemId, done) {
 function deleteTodoItem(itemId) {
 	fetch(`${API}/todo/delete/${itemId}`, {
 		method: 'DELETE',
+		headers: {
+			'X-CSRF-Token': csrfToken
+		},
 		credentials: 'include'
 	})
 		.then(response => {
@@ -149,6 +183,8 @@ document.addEventListener('DOMContentLoaded', function() {
 		todoContainer.style.display = 'block';
 		loginPrompt.style.display = 'none';
 
+		fetchCsrfToken();
+
 		fetch(`${API}/user/`, {
 			method: 'GET',
 			credentials: 'include'
@@ -176,10 +212,14 @@ document.addEventListener('DOMContentLoaded', function() {
 		logoutBtn.addEventListener('click', function() {
 			document.cookie = 'sessionid=; Max-Age=0; Path=/; Domain=todo.celarye.dev;';
 			document.cookie = 'loggedin=; Max-Age=0; Path=/; Domain=todo.celarye.dev;';
-			fetch(`${API}/user/logout`, {
+			fetch(`${API}/user/auth/logout`, {
 				method: 'DELETE',
+				headers: {
+					'X-CSRF-Token': csrfToken
+				},
 				credentials: 'include'
 			}).catch();
+			sessionStorage.removeItem('csrf_token');
 
 			console.log('User logged out.');
 			window.location.reload();
@@ -210,6 +250,7 @@ document.addEventListener('DOMContentLoaded', function() {
 				if (!response.ok) {
 					throw new Error('Failed to complete GitHub login');
 				}
+				response.json().then(session => storeCsrfToken(session.csrf_token));
 				document.cookie = 'loggedin=true; Max-Age=21540; Path=/; Secure; Partitioned; Domain=todo.celarye.dev;';
 				if (history.pushState) {
 					const newUrl = window.location.origin + window.location.pathname;
@@ -248,10 +289,14 @@ document.addEventListener('DOMContentLoaded', function() {
 				logoutBtn.addEventListener('click', function() {
 					document.cookie = 'sessionid=; Max-Age=0; Path=/; Domain=todo.celarye.dev;';
 					document.cookie = 'loggedin=; Max-Age=0; Path=/; Domain=todo.celarye.dev;';
-					fetch(`${API}/user/logout`, {
+					fetch(`${API}/user/auth/logout`, {
 						method: 'DELETE',
+						headers: {
+							'X-CSRF-Token': csrfToken
+						},
 						credentials: 'include'
 					}).catch();
+					sessionStorage.removeItem('csrf_token');
 
 					console.log('User logged out.');
 					window.location.reload();